@@ -0,0 +1,142 @@
+
+local HANDSHAKE, STATUS, LOGIN, PLAY, ENCRYPTED = 0, 1, 2, 3, 4
+local STATE_NAMES = { [0] = "handshake", "status", "login", "play", "encrypted" }
+
+-- Protocol and state of each connection while reading the capture the first time
+local connections = {}
+-- Protocol and state each packet was read in, by frame and offset, for later passes
+local packet_states = {}
+
+local function connection_key(pinfo, to_server)
+    local client = tostring(pinfo.src) .. ":" .. pinfo.src_port
+    local server = tostring(pinfo.dst) .. ":" .. pinfo.dst_port
+    if to_server then
+        return client .. ">" .. server
+    end
+    return server .. ">" .. client
+end
+
+-- Follow the packets which change the state, their ids are the same in every version
+local function advance(connection, tvb, to_server)
+    local id, offset = checked_varint(tvb, 0)
+    if connection.state == HANDSHAKE and to_server and id == 0 then
+        local protocol, address_len, len
+        protocol, len = checked_varint(tvb, offset)
+        offset = offset + len
+        address_len, len = checked_varint(tvb, offset)
+        offset = offset + len + address_len + 2
+        local next_state = checked_varint(tvb, offset)
+        connection.protocol = protocol
+        if next_state == STATUS or next_state == LOGIN then
+            connection.state = next_state
+        end
+    elseif connection.state == LOGIN and not to_server and id == 2 then
+        connection.state = PLAY
+    elseif connection.state == LOGIN and to_server and id == 1 then
+        connection.state = ENCRYPTED
+    end
+end
+
+-- Read a packet without its length, returning its name
+local function dissect_packet(tvb, item, state, to_server)
+    local version = modules[MODULES[mcproto.prefs.module]] or versions[state.protocol]
+        or versions[LATEST]
+    local packets = version[state.state] and version[state.state][to_server and 0 or 1]
+    local id = varint(tvb, 0)
+    local name
+    if id == nil then
+        name = "no packet id"
+    elseif packets ~= nil and packets.names[id] ~= nil then
+        name = packets.names[id]
+    else
+        name = string.format("unknown 0x%02x", id)
+    end
+    item:append_text(string.format(", %s %s, %s", STATE_NAMES[state.state],
+        to_server and "to server" or "to client", name))
+
+    if packets == nil then
+        item:add(f_data, tvb())
+        return name
+    end
+    local ok, result = pcall(packets.read, tvb, 0, item)
+    if not ok and result ~= REST then
+        item:add_proto_expert_info(e_malformed, tostring(result))
+    elseif ok and result < tvb:len() then
+        item:add_proto_expert_info(e_trailing,
+            string.format("%d bytes left after the packet", tvb:len() - result))
+    end
+    return name
+end
+
+function mcproto.dissector(tvb, pinfo, tree)
+    local to_server = pinfo.dst_port == mcproto.prefs.port
+    local key = connection_key(pinfo, to_server)
+    local connection = connections[key]
+    if connection == nil then
+        connection = { protocol = LATEST, state = HANDSHAKE }
+        connections[key] = connection
+    end
+    pinfo.cols.protocol:set("Minecraft")
+
+    local read = {}
+    local offset = 0
+    while offset < tvb:len() do
+        local packet_key = pinfo.number .. ":" .. offset
+        local state = packet_states[packet_key]
+        if state == nil then
+            state = { protocol = connection.protocol, state = connection.state }
+        end
+
+        if state.state == ENCRYPTED then
+            packet_states[packet_key] = state
+            local item = tree:add(mcproto, tvb(offset))
+            item:add(f_data, tvb(offset))
+            item:add_proto_expert_info(e_encrypted, "Encrypted after the encryption response")
+            read[#read + 1] = "encrypted"
+            break
+        end
+
+        local len, prefix = varint(tvb, offset)
+        if len == nil or offset + prefix + len > tvb:len() then
+            pinfo.desegment_offset = offset
+            pinfo.desegment_len = DESEGMENT_ONE_MORE_SEGMENT
+            break
+        end
+
+        local item = tree:add(mcproto, tvb(offset, prefix + len))
+        item:add(f_length, tvb(offset, prefix), len)
+        if len == 0 then
+            item:add_proto_expert_info(e_malformed, "Packet without an id")
+            read[#read + 1] = "empty"
+        else
+            local packet = tvb(offset + prefix, len):tvb()
+            if packet_states[packet_key] == nil then
+                packet_states[packet_key] = state
+                pcall(advance, connection, packet, to_server)
+            end
+            read[#read + 1] = dissect_packet(packet, item, state, to_server)
+        end
+        offset = offset + prefix + len
+    end
+
+    if #read > 0 then
+        pinfo.cols.info:set((to_server and "to server: " or "to client: ") .. table.concat(read, ", "))
+    end
+end
+
+f[#f + 1] = f_length
+f[#f + 1] = f_data
+mcproto.fields = f
+mcproto.experts = { e_malformed, e_trailing, e_encrypted }
+
+local registered_port
+local function register()
+    local tcp = DissectorTable.get("tcp.port")
+    if registered_port ~= nil then
+        tcp:remove(registered_port, mcproto)
+    end
+    registered_port = mcproto.prefs.port
+    tcp:add(registered_port, mcproto)
+end
+mcproto.prefs_changed = register
+register()
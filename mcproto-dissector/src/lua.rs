@@ -0,0 +1,536 @@
+//! Lua source of the dissector
+//!
+//! Every enum gets a function reading its variants, the packet enums of a state are the
+//! roots. Field abbreviations follow the types: `mcproto.v1_7_10.playtoclient.keepalive.
+//! keep_alive_id` is the id of a keep alive packet sent to clients by 1.7.10.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use serde_mcproto::de::schema::{Enum, Format};
+
+use crate::Version;
+
+const PRELUDE: &str = include_str!("prelude.lua");
+const DISSECTOR: &str = include_str!("dissector.lua");
+
+/// Source of a dissector for `versions`
+pub fn dissector(versions: &[Version]) -> String {
+    let mut lua = Lua::default();
+    for (i, version) in versions.iter().enumerate() {
+        lua.version(i, version);
+    }
+    let latest = versions.iter().map(|v| v.protocol).max().unwrap_or(0);
+
+    let mut out = String::from(PRELUDE);
+    out.push('\n');
+    out.push_str(&lua.names);
+    out.push('\n');
+    for (i, field) in lua.fields.iter().enumerate() {
+        let _ = writeln!(out, "f[{}] = {}", i + 1, field);
+    }
+    out.push('\n');
+    out.push_str(&lua.functions);
+    let _ = writeln!(out, "local LATEST = {}", latest);
+    out.push_str(&preference(versions));
+    out.push_str(DISSECTOR);
+    out
+}
+
+/// Preference forcing the module connections are read with, `automatic` follows the handshake
+fn preference(versions: &[Version]) -> String {
+    let names: Vec<String> = versions.iter().map(|v| format!("\"{}\"", v.name)).collect();
+    let mut choices = vec!["{ 1, \"automatic\", 0 }".to_owned()];
+    choices.extend(
+        versions
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("{{ {}, \"{}\", {} }}", i + 2, v.name, i + 1)),
+    );
+    let mut out = String::new();
+    let _ = writeln!(out, "local MODULES = {{ {} }}", names.join(", "));
+    let _ = writeln!(
+        out,
+        "mcproto.prefs.module = Pref.enum(\"Packet definitions\", 0, \"Version module the packets are read with\", {{ {} }})",
+        choices.join(", ")
+    );
+    out
+}
+
+#[derive(Default)]
+struct Lua {
+    /// Variant names, declared before the fields showing them
+    names: String,
+    fields: Vec<String>,
+    abbrevs: HashSet<String>,
+    functions: String,
+    /// Numbers the locals of nested values apart
+    locals: usize,
+}
+
+/// Size and field type of values without a length
+fn fixed(format: &Format) -> Option<(usize, &'static str)> {
+    let fixed = match format {
+        Format::Bool => (1, "bool"),
+        Format::I8 => (1, "int8"),
+        Format::I16 => (2, "int16"),
+        Format::I32 => (4, "int32"),
+        Format::I64 => (8, "int64"),
+        Format::U8 => (1, "uint8"),
+        Format::U16 => (2, "uint16"),
+        Format::U32 => (4, "uint32"),
+        Format::U64 => (8, "uint64"),
+        Format::U128 => (16, "bytes"),
+        Format::F32 => (4, "float"),
+        Format::F64 => (8, "double"),
+        _ => return None,
+    };
+    Some(fixed)
+}
+
+/// Whether reading `format` always ends with the rest of the packet
+fn stops(format: &Format) -> bool {
+    match format {
+        Format::Unsized(_) | Format::Unsupported(_) => true,
+        Format::Struct { fields, .. } => fields.iter().any(|(_, field)| stops(field)),
+        Format::Tuple(parts) => parts.iter().any(stops),
+        Format::Metadata(entry) => !matches!(&**entry, Format::Tuple(parts)
+            if parts.len() == 2 && parts[0] == Format::U8 && matches!(parts[1], Format::Enum(_))),
+        _ => false,
+    }
+}
+
+impl Lua {
+    fn line<S: AsRef<str>>(&mut self, indent: usize, line: S) {
+        let _ = writeln!(self.functions, "{}{}", "    ".repeat(indent), line.as_ref());
+    }
+
+    /// Declare a field, returning its index in `f`
+    fn field(&mut self, kind: &str, path: &str, label: &str, extra: &str) -> usize {
+        let mut abbrev = path.to_owned();
+        let mut n = 1;
+        while !self.abbrevs.insert(abbrev.clone()) {
+            n += 1;
+            abbrev = format!("{}_{}", path, n);
+        }
+        let base = if kind.contains("int") {
+            ", base.DEC"
+        } else {
+            ""
+        };
+        self.fields.push(format!(
+            "ProtoField.{}(\"{}\", \"{}\"{}{})",
+            kind, abbrev, label, base, extra
+        ));
+        self.fields.len()
+    }
+
+    fn local(&mut self) -> usize {
+        self.locals += 1;
+        self.locals
+    }
+
+    fn version(&mut self, index: usize, version: &Version) {
+        let prefix = format!("mcproto.{}", version.name);
+        let key = |e: usize| format!("v{}_e{}", index, e);
+
+        let _ = writeln!(self.names, "-- {}", version.name);
+        for (e, walked) in version.schema.enums.iter().enumerate() {
+            let variants: Vec<String> = walked
+                .variants
+                .iter()
+                .enumerate()
+                .map(|(id, v)| format!("[{}] = \"{}\"", id, v.name))
+                .collect();
+            let _ = writeln!(
+                self.names,
+                "names.{} = {{ {} }}",
+                key(e),
+                variants.join(", ")
+            );
+        }
+
+        for (e, walked) in version.schema.enums.iter().enumerate() {
+            self.enum_reader(index, &key(e), &prefix, walked);
+        }
+
+        self.line(0, format!("modules.{} = {{", version.name));
+        self.line(1, format!("name = \"{}\",", version.name));
+        let mut states: Vec<_> = version.packets.iter().map(|(state, _, _)| *state).collect();
+        states.dedup();
+        for state in states {
+            self.line(1, format!("[{}] = {{", state.id()));
+            for (_, direction, root) in version.packets.iter().filter(|(s, _, _)| *s == state) {
+                let path = format!(
+                    "{}.{}.{}",
+                    prefix,
+                    state,
+                    direction.to_string().replace(' ', "_")
+                );
+                self.line(2, format!("[{}] = {{", direction.id()));
+                match root {
+                    Format::Enum(e) => {
+                        let names = format!("names.{}", key(*e));
+                        let id = self.field(
+                            "int32",
+                            &format!("{}.id", path),
+                            "Packet id",
+                            &format!(", {}", names),
+                        );
+                        self.line(3, format!("names = {},", names));
+                        self.line(3, "read = function(tvb, offset, tree)");
+                        self.line(4, "local id");
+                        self.line(
+                            4,
+                            format!("offset, id = add_varint(tree, f[{}], tvb, offset)", id),
+                        );
+                        self.line(
+                            4,
+                            format!("return enums.{}(tvb, offset, tree, id)", key(*e)),
+                        );
+                    }
+                    other => {
+                        self.line(3, "names = {},");
+                        self.line(3, "read = function(tvb, offset, tree)");
+                        self.value(index, other, &path, "packet", "tree", 4);
+                        self.line(4, "return offset");
+                    }
+                }
+                self.line(3, "end,");
+                self.line(2, "},");
+            }
+            self.line(1, "},");
+        }
+        self.line(0, "}");
+        // the first module of a protocol version reads it
+        self.line(
+            0,
+            format!(
+                "versions[{0}] = versions[{0}] or modules.{1}",
+                version.protocol, version.name
+            ),
+        );
+        self.line(0, "");
+    }
+
+    fn enum_reader(&mut self, version: usize, key: &str, prefix: &str, walked: &Enum) {
+        let path = format!("{}.{}", prefix, walked.name.to_lowercase());
+        self.line(0, format!("-- {}", walked.name));
+        self.line(
+            0,
+            format!("enums.{} = function(tvb, offset, tree, id)", key),
+        );
+        for (id, variant) in walked.variants.iter().enumerate() {
+            let keyword = if id == 0 { "if" } else { "elseif" };
+            self.line(1, format!("{} id == {} then", keyword, id));
+            let variant_path = format!("{}.{}", path, variant.name.to_lowercase());
+            match &variant.format {
+                Format::Struct { fields, .. } => {
+                    for (name, format) in fields {
+                        let field_path = format!("{}.{}", variant_path, name);
+                        self.value(version, format, &field_path, name, "tree", 2);
+                        if stops(format) {
+                            break;
+                        }
+                    }
+                }
+                Format::Tuple(parts) => {
+                    for (i, part) in parts.iter().enumerate() {
+                        let part_path = format!("{}.e{}", variant_path, i);
+                        self.value(version, part, &part_path, &format!("[{}]", i), "tree", 2);
+                        if stops(part) {
+                            break;
+                        }
+                    }
+                }
+                other => self.value(version, other, &variant_path, variant.name, "tree", 2),
+            }
+        }
+        if !walked.variants.is_empty() {
+            self.line(1, "else");
+        }
+        self.line(
+            2,
+            format!("error(\"Unknown {} variant \" .. id)", walked.name),
+        );
+        if !walked.variants.is_empty() {
+            self.line(1, "end");
+        }
+        self.line(1, "return offset");
+        self.line(0, "end");
+        self.line(0, "");
+    }
+
+    /// Statements reading `format` into `tree` at `offset` and moving `offset` after it
+    fn value(
+        &mut self,
+        version: usize,
+        format: &Format,
+        path: &str,
+        label: &str,
+        tree: &str,
+        indent: usize,
+    ) {
+        if let Some((size, kind)) = fixed(format) {
+            let field = self.field(kind, path, label, "");
+            self.line(
+                indent,
+                format!(
+                    "offset = add({}, f[{}], tvb, offset, {})",
+                    tree, field, size
+                ),
+            );
+            return;
+        }
+        match format {
+            Format::VarInt => {
+                let field = self.field("int32", path, label, "");
+                self.line(
+                    indent,
+                    format!("offset = add_varint({}, f[{}], tvb, offset)", tree, field),
+                );
+            }
+            Format::VarLong => {
+                let field = self.field("int64", path, label, "");
+                self.line(
+                    indent,
+                    format!("offset = add_varlong({}, f[{}], tvb, offset)", tree, field),
+                );
+            }
+            Format::String => {
+                let field = self.field("string", path, label, "");
+                self.line(
+                    indent,
+                    format!("offset = add_string({}, f[{}], tvb, offset)", tree, field),
+                );
+            }
+            Format::Nbt { gzip } => {
+                let label = if *gzip {
+                    format!("{} (gzip NBT)", label)
+                } else {
+                    format!("{} (NBT)", label)
+                };
+                let field = self.field("bytes", path, &label, "");
+                self.line(
+                    indent,
+                    format!("offset = add_nbt({}, f[{}], tvb, offset)", tree, field),
+                );
+            }
+            Format::Tuple(parts) if parts.is_empty() => {}
+            Format::Tuple(parts) => {
+                let (sub, start) = self.open(path, label, tree, indent);
+                for (i, part) in parts.iter().enumerate() {
+                    let part_path = format!("{}.e{}", path, i);
+                    self.value(
+                        version,
+                        part,
+                        &part_path,
+                        &format!("[{}]", i),
+                        &sub,
+                        indent + 1,
+                    );
+                    if stops(part) {
+                        break;
+                    }
+                }
+                self.close(&sub, &start, indent);
+            }
+            Format::Struct { fields, .. } => {
+                let (sub, start) = self.open(path, label, tree, indent);
+                for (name, field) in fields {
+                    let field_path = format!("{}.{}", path, name);
+                    self.value(version, field, &field_path, name, &sub, indent + 1);
+                    if stops(field) {
+                        break;
+                    }
+                }
+                self.close(&sub, &start, indent);
+            }
+            Format::Array { prefix, element } => {
+                let (sub, start) = self.open(path, label, tree, indent);
+                let count = format!("n{}", self.local());
+                let (kind, read) = match **prefix {
+                    Format::I8 => ("int8", "add_int"),
+                    Format::I16 => ("int16", "add_int"),
+                    Format::I32 => ("int32", "add_int"),
+                    Format::U8 => ("uint8", "add_uint"),
+                    Format::U16 => ("uint16", "add_uint"),
+                    Format::U32 => ("uint32", "add_uint"),
+                    _ => ("int32", "add_varint"),
+                };
+                let field = self.field(kind, &format!("{}.count", path), "count", "");
+                let size = fixed(prefix).map_or(String::new(), |(size, _)| format!(", {}", size));
+                self.line(indent + 1, format!("local {}", count));
+                self.line(
+                    indent + 1,
+                    format!(
+                        "offset, {} = {}({}, f[{}], tvb, offset{})",
+                        count, read, sub, field, size
+                    ),
+                );
+                self.line(indent + 1, format!("for _ = 1, {} do", count));
+                self.value(
+                    version,
+                    element,
+                    &format!("{}.element", path),
+                    label,
+                    &sub,
+                    indent + 2,
+                );
+                self.line(indent + 1, "end");
+                self.close(&sub, &start, indent);
+            }
+            Format::Enum(e) => {
+                let (sub, start) = self.open(path, label, tree, indent);
+                let key = format!("v{}_e{}", version, e);
+                let id = format!("id{}", self.local());
+                let field = self.field(
+                    "int32",
+                    &format!("{}.variant", path),
+                    "variant",
+                    &format!(", names.{}", key),
+                );
+                self.line(indent + 1, format!("local {}", id));
+                self.line(
+                    indent + 1,
+                    format!(
+                        "offset, {} = add_varint({}, f[{}], tvb, offset)",
+                        id, sub, field
+                    ),
+                );
+                self.line(
+                    indent + 1,
+                    format!("offset = enums.{}(tvb, offset, {}, {})", key, sub, id),
+                );
+                self.close(&sub, &start, indent);
+            }
+            Format::Option(inner) => {
+                let absent = self.field("none", &format!("{}.absent", path), label, "");
+                self.line(indent, "if tvb(offset, 2):int() == -1 then");
+                self.line(
+                    indent + 1,
+                    format!(
+                        "{}:add(f[{}], tvb(offset, 2)):set_text(\"{}: absent\")",
+                        tree, absent, label
+                    ),
+                );
+                self.line(indent + 1, "offset = offset + 2");
+                self.line(indent, "else");
+                self.value(version, inner, path, label, tree, indent + 1);
+                self.line(indent, "end");
+            }
+            Format::Metadata(entry) => match &**entry {
+                Format::Tuple(parts) if !stops(format) => {
+                    let e = match parts[1] {
+                        Format::Enum(e) => e,
+                        _ => unreachable!("entries of readable metadata end with an enum"),
+                    };
+                    let key = format!("v{}_e{}", version, e);
+                    let (sub, start) = self.open(path, label, tree, indent);
+                    let entry_field = self.field("none", &format!("{}.entry", path), "entry", "");
+                    let index = self.field("uint8", &format!("{}.index", path), "index", "");
+                    let kind = self.field(
+                        "uint8",
+                        &format!("{}.type", path),
+                        "type",
+                        &format!(", names.{}", key),
+                    );
+                    let local = self.local();
+                    self.line(indent + 1, "while tvb(offset, 1):uint() ~= 127 do");
+                    self.line(
+                        indent + 2,
+                        format!("local header{} = tvb(offset, 1):uint()", local),
+                    );
+                    self.line(
+                        indent + 2,
+                        format!(
+                            "local entry{} = {}:add(f[{}], tvb(offset))",
+                            local, sub, entry_field
+                        ),
+                    );
+                    self.line(indent + 2, format!("local start{} = offset", local));
+                    self.line(
+                        indent + 2,
+                        format!(
+                            "entry{0}:add(f[{1}], tvb(offset, 1), math.floor(header{0} / 32))",
+                            local, index
+                        ),
+                    );
+                    self.line(
+                        indent + 2,
+                        format!(
+                            "entry{0}:add(f[{1}], tvb(offset, 1), header{0} % 32)",
+                            local, kind
+                        ),
+                    );
+                    self.line(
+                        indent + 2,
+                        format!(
+                            "offset = enums.{0}(tvb, offset + 1, entry{1}, header{1} % 32)",
+                            key, local
+                        ),
+                    );
+                    self.line(
+                        indent + 2,
+                        format!("entry{0}:set_len(offset - start{0})", local),
+                    );
+                    self.line(indent + 1, "end");
+                    self.line(indent + 1, "offset = offset + 1");
+                    self.close(&sub, &start, indent);
+                }
+                _ => self.rest(path, label, "metadata entry", tree, indent),
+            },
+            Format::Unsized(parts) => {
+                for (i, part) in parts.iter().enumerate() {
+                    let part_path = format!("{}.e{}", path, i);
+                    self.value(
+                        version,
+                        part,
+                        &part_path,
+                        &format!("{}[{}]", label, i),
+                        tree,
+                        indent,
+                    );
+                    if stops(part) {
+                        return;
+                    }
+                }
+                self.rest(path, label, "length from earlier values", tree, indent);
+            }
+            Format::Unsupported(kind) => self.rest(path, label, kind, tree, indent),
+            _ => unreachable!("fixed size formats are read above"),
+        }
+    }
+
+    /// Subtree for a compound value, closed by [`Lua::close`]
+    fn open(&mut self, path: &str, label: &str, tree: &str, indent: usize) -> (String, String) {
+        let field = self.field("none", path, label, "");
+        let local = self.local();
+        let (sub, start) = (format!("t{}", local), format!("s{}", local));
+        self.line(indent, "do");
+        self.line(
+            indent + 1,
+            format!(
+                "local {}, {} = {}:add(f[{}], tvb(offset)), offset",
+                sub, start, tree, field
+            ),
+        );
+        (sub, start)
+    }
+
+    fn close(&mut self, sub: &str, start: &str, indent: usize) {
+        self.line(indent + 1, format!("{}:set_len(offset - {})", sub, start));
+        self.line(indent, "end");
+    }
+
+    /// The rest of the packet as bytes, for values without a layout
+    fn rest(&mut self, path: &str, label: &str, why: &str, tree: &str, indent: usize) {
+        let field = self.field(
+            "bytes",
+            &format!("{}.rest", path),
+            &format!("{} ({})", label, why),
+            "",
+        );
+        self.line(indent, format!("rest({}, f[{}], tvb, offset)", tree, field));
+    }
+}
@@ -0,0 +1,154 @@
+//! Generate a Wireshark dissector from the packet definitions
+//!
+//! Walks the packets of every version with `serde_mcproto::de::schema` and writes a Lua
+//! plugin reading the handshake, status, login and play states, to copy into the plugins
+//! folder of Wireshark.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use mcproto::packet::{Direction, State};
+use serde_mcproto::de::schema::{Format, Schema};
+use serde_mcproto::error::Result;
+
+mod lua;
+
+/// Packet formats of a version module
+pub struct Version {
+    /// Name of the module
+    pub name: &'static str,
+    pub protocol: i32,
+    /// Enums of all the packets
+    pub schema: Schema,
+    /// Packet enum of each state and direction
+    pub packets: Vec<(State, Direction, Format)>,
+}
+
+/// Walk the packet enums of a version module
+macro_rules! version {
+    ($module:ident) => {{
+        use mcproto::$module as module;
+        let mut schema = Schema::new();
+        let packets = vec![
+            (State::Handshake, Direction::ToServer, schema.walk::<module::Handshake>()?),
+            (State::Status, Direction::ToServer, schema.walk::<module::StatusToServer>()?),
+            (State::Status, Direction::ToClient, schema.walk::<module::StatusToClient>()?),
+            (State::Login, Direction::ToServer, schema.walk::<module::LoginToServer>()?),
+            (State::Login, Direction::ToClient, schema.walk::<module::LoginToClient>()?),
+            (State::Play, Direction::ToServer, schema.walk::<module::PlayToServer>()?),
+            (State::Play, Direction::ToClient, schema.walk::<module::PlayToClient>()?),
+        ];
+        Version {
+            name: stringify!($module),
+            protocol: module::PROTOCOL_VERSION.0,
+            schema,
+            packets,
+        }
+    }};
+}
+
+/// Every version module registered in `mcproto::for_each_version`.
+///
+/// A connection is read with the first module of its protocol version, or of the newest one
+/// if it is unknown, unless the preferences of the dissector pick a module.
+fn versions() -> Result<Vec<Version>> {
+    macro_rules! walk {
+        ($($module:ident),*) => {
+            Ok(vec![$(version!($module)),*])
+        };
+    }
+    mcproto::for_each_version!(walk)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let output = match args.as_slice() {
+        [] => None,
+        [path] if !path.starts_with('-') => Some(path),
+        _ => {
+            eprintln!("Usage: mcproto-dissector [output.lua]\n\nWrites to stdout without a file.");
+            process::exit(2);
+        }
+    };
+
+    let versions = versions().unwrap_or_else(|e| {
+        eprintln!("Could not walk the packets: {}", e);
+        process::exit(1);
+    });
+    let source = lua::dissector(&versions);
+    let written = match output {
+        Some(path) => fs::write(path, source),
+        None => io::stdout().write_all(source.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("Could not write the dissector: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_mcproto::de::schema::Format;
+
+    const STATES: [State; 4] = [State::Handshake, State::Status, State::Login, State::Play];
+    const DIRECTIONS: [Direction; 2] = [Direction::ToServer, Direction::ToClient];
+
+    /// Packet names of a registered module by state and direction
+    fn packets(module: &str, state: State, direction: Direction) -> &'static [&'static str] {
+        macro_rules! find {
+            ($($module:ident),*) => {
+                $(
+                    if module == stringify!($module) {
+                        return mcproto::$module::packets(state, direction);
+                    }
+                )*
+            };
+        }
+        mcproto::for_each_version!(find);
+        panic!("{} is not a version module", module)
+    }
+
+    #[test]
+    fn every_module_and_packet() {
+        let versions = versions().unwrap();
+        let names: Vec<&str> = versions.iter().map(|v| v.name).collect();
+        assert_eq!(names, vec!["v1_7_10", "v1_7"]);
+        let source = lua::dissector(&versions);
+
+        for (index, version) in versions.iter().enumerate() {
+            assert!(source.contains(&format!("modules.{} = {{", version.name)));
+            for &state in &STATES {
+                for &direction in &DIRECTIONS {
+                    let expected = packets(version.name, state, direction);
+                    let root = version
+                        .packets
+                        .iter()
+                        .find(|(s, d, _)| *s == state && *d == direction);
+                    let e = match root {
+                        Some((_, _, Format::Enum(e))) => *e,
+                        _ => {
+                            assert!(expected.is_empty(), "{} {} {} is not walked", version.name, state, direction);
+                            continue;
+                        }
+                    };
+                    let walked: Vec<&str> = version.schema.enums[e].variants.iter().map(|v| v.name).collect();
+                    assert_eq!(walked, expected, "{} {} {}", version.name, state, direction);
+                    let table: Vec<String> = expected
+                        .iter()
+                        .enumerate()
+                        .map(|(id, name)| format!("[{}] = \"{}\"", id, name))
+                        .collect();
+                    let line = format!("names.v{}_e{} = {{ {} }}", index, e, table.join(", "));
+                    assert!(source.contains(&line), "{} is missing", line);
+                }
+            }
+        }
+
+        // both modules read protocol 5, the hand written one by default
+        assert!(source.contains("versions[5] = versions[5] or modules.v1_7_10\n"));
+        assert!(source.contains("{ 3, \"v1_7\", 2 }"));
+    }
+}
@@ -0,0 +1,123 @@
+-- Minecraft protocol dissector, generated by mcproto-dissector from the packet definitions
+-- of mcproto. Regenerate it rather than editing it.
+--
+-- Connections are read until encryption is enabled, compressed packets are not supported.
+
+local mcproto = Proto("mcproto", "Minecraft Protocol")
+mcproto.prefs.port = Pref.uint("TCP port", 25565, "Port the server listens on")
+
+local f_length = ProtoField.uint32("mcproto.length", "Length", base.DEC)
+local f_data = ProtoField.bytes("mcproto.data", "Data")
+
+local e_malformed = ProtoExpert.new("mcproto.malformed", "Malformed packet",
+    expert.group.MALFORMED, expert.severity.ERROR)
+local e_trailing = ProtoExpert.new("mcproto.trailing", "Bytes left after the packet",
+    expert.group.MALFORMED, expert.severity.WARN)
+local e_encrypted = ProtoExpert.new("mcproto.encrypted", "Encrypted",
+    expert.group.UNDECODED, expert.severity.NOTE)
+
+-- Thrown to stop reading a packet whose rest has no known layout
+local REST = {}
+
+-- Value and length, nil if the bytes end first
+local function varint(tvb, offset)
+    local value, shift = 0, 1
+    for i = 0, 4 do
+        if offset + i >= tvb:len() then
+            return nil
+        end
+        local byte = tvb(offset + i, 1):uint()
+        value = value + byte % 128 * shift
+        if byte < 128 then
+            if value >= 2147483648 then
+                value = value - 4294967296
+            end
+            return value, i + 1
+        end
+        shift = shift * 128
+    end
+    error("VarInt longer than 5 bytes")
+end
+
+local function checked_varint(tvb, offset)
+    local value, len = varint(tvb, offset)
+    if value == nil then
+        error("VarInt runs past the end of the packet")
+    end
+    return value, len
+end
+
+local function varlong(tvb, offset)
+    local value = UInt64(0)
+    for i = 0, 9 do
+        local byte = tvb(offset + i, 1):uint()
+        value = value:bor(UInt64(byte % 128):lshift(7 * i))
+        if byte < 128 then
+            return Int64(value:lower(), value:higher()), i + 1
+        end
+    end
+    error("VarLong longer than 10 bytes")
+end
+
+local function add(tree, field, tvb, offset, len)
+    tree:add(field, tvb(offset, len))
+    return offset + len
+end
+
+local function add_int(tree, field, tvb, offset, len)
+    local range = tvb(offset, len)
+    tree:add(field, range)
+    return offset + len, range:int()
+end
+
+local function add_uint(tree, field, tvb, offset, len)
+    local range = tvb(offset, len)
+    tree:add(field, range)
+    return offset + len, range:uint()
+end
+
+local function add_varint(tree, field, tvb, offset)
+    local value, len = checked_varint(tvb, offset)
+    tree:add(field, tvb(offset, len), value)
+    return offset + len, value
+end
+
+local function add_varlong(tree, field, tvb, offset)
+    local value, len = varlong(tvb, offset)
+    tree:add(field, tvb(offset, len), value)
+    return offset + len
+end
+
+local function add_string(tree, field, tvb, offset)
+    local len, prefix = checked_varint(tvb, offset)
+    local value = ""
+    if len > 0 then
+        value = tvb(offset + prefix, len):string(ENC_UTF_8)
+    end
+    tree:add(field, tvb(offset, prefix + len), value)
+    return offset + prefix + len
+end
+
+local function add_nbt(tree, field, tvb, offset)
+    local len = tvb(offset, 2):uint()
+    tree:add(field, tvb(offset, 2 + len))
+    return offset + 2 + len
+end
+
+-- Show what is left of the packet and stop reading it
+local function rest(tree, field, tvb, offset)
+    if offset < tvb:len() then
+        tree:add(field, tvb(offset))
+    end
+    error(REST, 0)
+end
+
+local f = {}
+-- Names of the variants of each enum
+local names = {}
+-- Readers of the variants of each enum, called with the variant id
+local enums = {}
+-- Packets of each version module by state and direction
+local modules = {}
+-- Module reading each protocol version
+local versions = {}
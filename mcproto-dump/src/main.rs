@@ -0,0 +1,353 @@
+//! Print decoded packets with their fields, types and byte offsets
+//!
+//! Reads a capture of `tokio_serde_mcproto::capture`, a file of length prefixed frames or
+//! hexadecimal packets given on the command line.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use mcproto::packet::{Direction, State};
+use mcproto::v1_7_10::{
+    Handshake, LoginToClient, LoginToServer, PlayToClient, PlayToServer, StatusToClient,
+    StatusToServer,
+};
+use serde::de::DeserializeOwned;
+use serde_mcproto::de::trace::{trace, Trace};
+use tokio_serde_mcproto::capture::{CaptureReader, FRAME_PREFIX_LEN, HEADER_LEN, MAGIC};
+
+mod render;
+
+use render::{Decoded, Packet};
+
+const USAGE: &str = "\
+Usage: mcproto-dump [options] <file>
+       mcproto-dump [options] --hex <hex>
+
+A file is a capture or length prefixed frames. Hex input is one packet, its id and fields.
+
+Options:
+  --state <handshake|status|login|play>  state of frames and hex input, default play
+  --direction <to-client|to-server>      direction of frames and hex input, default to-client
+  --framed                               hex input is length prefixed frames
+  --bytes                                hex dump every packet, not only failed ones";
+
+struct Options {
+    state: State,
+    direction: Direction,
+    framed: bool,
+    hex_dump: bool,
+    hex: Option<String>,
+    file: Option<String>,
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}\n\n{}", error, USAGE);
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        state: State::Play,
+        direction: Direction::ToClient,
+        framed: false,
+        hex_dump: false,
+        hex: None,
+        file: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| usage(&format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "--state" => {
+                options.state = match value("--state").as_str() {
+                    "handshake" => State::Handshake,
+                    "status" => State::Status,
+                    "login" => State::Login,
+                    "play" => State::Play,
+                    other => usage(&format!("Unknown state {}", other)),
+                }
+            }
+            "--direction" => {
+                options.direction = match value("--direction").as_str() {
+                    "to-client" => Direction::ToClient,
+                    "to-server" => Direction::ToServer,
+                    other => usage(&format!("Unknown direction {}", other)),
+                }
+            }
+            "--hex" => options.hex = Some(value("--hex")),
+            "--framed" => options.framed = true,
+            "--bytes" => options.hex_dump = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            file if !file.starts_with("--") && options.file.is_none() => {
+                options.file = Some(file.to_owned())
+            }
+            other => usage(&format!("Unexpected argument {}", other)),
+        }
+    }
+    if options.hex.is_some() == options.file.is_some() {
+        usage("Give either a file or --hex");
+    }
+    options
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: String = hex
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect();
+    if digits.len() % 2 == 1 {
+        return Err("Odd number of hexadecimal digits".to_owned());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("Invalid hexadecimal {:?}", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+/// Split length prefixed frames, what can't be split is an error after the packets
+fn split_frames(bytes: &[u8], state: State, direction: Direction) -> (Vec<Packet>, Option<String>) {
+    let mut packets = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let offset = bytes.len() - rest.len();
+        let len = match serde_mcproto::read_varint(&mut rest) {
+            Ok(len) if len >= 0 => len as usize,
+            Ok(len) => return (packets, Some(format!("Negative frame length {} at 0x{:x}", len, offset))),
+            Err(e) => return (packets, Some(format!("Invalid frame length at 0x{:x}: {}", offset, e))),
+        };
+        if rest.len() < len {
+            let error = format!(
+                "Frame at 0x{:x} is {} bytes long, only {} are left",
+                offset,
+                len,
+                rest.len()
+            );
+            return (packets, Some(error));
+        }
+        packets.push(Packet {
+            index: packets.len(),
+            state,
+            direction,
+            offset: bytes.len() - rest.len(),
+            time: None,
+            data: rest[..len].to_vec(),
+        });
+        rest = &rest[len..];
+    }
+    (packets, None)
+}
+
+fn read_capture(bytes: &[u8]) -> (Vec<Packet>, Option<String>) {
+    let mut reader = match CaptureReader::new(bytes) {
+        Ok(reader) => reader,
+        Err(e) => return (Vec::new(), Some(format!("Invalid capture: {}", e))),
+    };
+    println!(
+        "capture of protocol {}, started at unix time {}.{:03}",
+        reader.header().protocol_version,
+        reader.header().started_at / 1000,
+        reader.header().started_at % 1000
+    );
+    let mut packets = Vec::new();
+    // the reader tells nothing about offsets, count them from the frame sizes
+    let mut offset = HEADER_LEN;
+    for frame in &mut reader {
+        match frame {
+            Ok(frame) => {
+                offset += FRAME_PREFIX_LEN;
+                packets.push(Packet {
+                    index: packets.len(),
+                    state: frame.state,
+                    direction: frame.direction,
+                    offset,
+                    time: Some(frame.time),
+                    data: frame.data,
+                });
+                offset += packets.last().map_or(0, |packet| packet.data.len());
+            }
+            Err(e) => return (packets, Some(format!("Invalid frame at 0x{:x}: {}", offset, e))),
+        }
+    }
+    (packets, None)
+}
+
+fn decode_as<T: DeserializeOwned>(data: &[u8]) -> Decoded {
+    let (result, trace) = trace::<T>(data);
+    let error = match result {
+        Err(e) => Some(e.to_string()),
+        Ok(_) if trace.position < data.len() => Some(format!(
+            "{} bytes left after the packet",
+            data.len() - trace.position
+        )),
+        Ok(_) => None,
+    };
+    Decoded { trace, error }
+}
+
+fn decode(packet: &Packet) -> Decoded {
+    match (packet.state, packet.direction) {
+        (State::Handshake, Direction::ToServer) => decode_as::<Handshake>(&packet.data),
+        (State::Handshake, Direction::ToClient) => Decoded {
+            trace: Trace::default(),
+            error: Some("Servers send nothing in the handshake state".to_owned()),
+        },
+        (State::Status, Direction::ToServer) => decode_as::<StatusToServer>(&packet.data),
+        (State::Status, Direction::ToClient) => decode_as::<StatusToClient>(&packet.data),
+        (State::Login, Direction::ToServer) => decode_as::<LoginToServer>(&packet.data),
+        (State::Login, Direction::ToClient) => decode_as::<LoginToClient>(&packet.data),
+        (State::Play, Direction::ToServer) => decode_as::<PlayToServer>(&packet.data),
+        (State::Play, Direction::ToClient) => decode_as::<PlayToClient>(&packet.data),
+    }
+}
+
+fn main() {
+    let options = parse_args();
+
+    let (packets, input_error) = match (&options.hex, &options.file) {
+        (Some(hex), _) => {
+            let bytes = parse_hex(hex).unwrap_or_else(|e| usage(&e));
+            if options.framed {
+                split_frames(&bytes, options.state, options.direction)
+            } else {
+                let packet = Packet {
+                    index: 0,
+                    state: options.state,
+                    direction: options.direction,
+                    offset: 0,
+                    time: None,
+                    data: bytes,
+                };
+                (vec![packet], None)
+            }
+        }
+        (None, Some(file)) => {
+            let bytes = fs::read(file).unwrap_or_else(|e| {
+                eprintln!("Could not read {}: {}", file, e);
+                process::exit(1);
+            });
+            if bytes.starts_with(&MAGIC) {
+                read_capture(&bytes)
+            } else {
+                split_frames(&bytes, options.state, options.direction)
+            }
+        }
+        (None, None) => unreachable!(),
+    };
+
+    let mut failed = 0;
+    for packet in &packets {
+        let decoded = decode(packet);
+        if decoded.error.is_some() {
+            failed += 1;
+        }
+        println!("{}", render::render(packet, &decoded, options.hex_dump));
+    }
+    if let Some(error) = &input_error {
+        println!("{}", error);
+    }
+    if failed > 0 {
+        println!("{} of {} packets failed to decode", failed, packets.len());
+    }
+    if failed > 0 || input_error.is_some() {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio_serde_mcproto::capture::{CaptureWriter, Frame, Header};
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex("0x00 2a:FF\n"), Ok(vec![0x00, 0x2a, 0xff]));
+        assert_eq!(parse_hex(""), Ok(vec![]));
+        assert_eq!(parse_hex("0a0"), Err("Odd number of hexadecimal digits".to_owned()));
+        assert_eq!(parse_hex("0g"), Err("Invalid hexadecimal \"0g\"".to_owned()));
+    }
+
+    #[test]
+    fn frames() {
+        // two KeepAlives, the second one cut short
+        let bytes = [0x05, 0x00, 0, 0, 0, 1, 0x05, 0x00, 0, 0];
+        let (packets, error) = split_frames(&bytes, State::Play, Direction::ToClient);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].offset, 1);
+        assert_eq!(packets[0].data, vec![0x00, 0, 0, 0, 1]);
+        assert_eq!(error.unwrap(), "Frame at 0x6 is 5 bytes long, only 3 are left");
+
+        let (packets, error) = split_frames(&[0xff], State::Play, Direction::ToClient);
+        assert!(packets.is_empty());
+        assert!(error.unwrap().starts_with("Invalid frame length at 0x0"));
+    }
+
+    fn capture(frames: &[&[u8]]) -> Vec<u8> {
+        let header = Header {
+            protocol_version: 5,
+            started_at: 0,
+        };
+        let mut writer = CaptureWriter::new(Vec::new(), header).unwrap();
+        for (i, data) in frames.iter().enumerate() {
+            let frame = Frame {
+                time: Duration::from_millis(i as u64),
+                direction: Direction::ToClient,
+                state: State::Play,
+                data: data.to_vec(),
+            };
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn capture_offsets() {
+        let bytes = capture(&[&[0x00, 0, 0, 0, 1], &[0x00, 0, 0, 0, 2]]);
+        let (packets, error) = read_capture(&bytes);
+        assert_eq!(error, None);
+        let offsets: Vec<_> = packets.iter().map(|packet| packet.offset).collect();
+        assert_eq!(offsets, vec![HEADER_LEN + FRAME_PREFIX_LEN, HEADER_LEN + 2 * FRAME_PREFIX_LEN + 5]);
+        assert_eq!(packets[1].time, Some(Duration::from_millis(1)));
+        assert_eq!(bytes[packets[1].offset..], packets[1].data[..]);
+
+        // cut inside the second frame
+        let (packets, error) = read_capture(&bytes[..bytes.len() - 1]);
+        assert_eq!(packets.len(), 1);
+        let at = HEADER_LEN + FRAME_PREFIX_LEN + 5;
+        assert!(error.unwrap().starts_with(&format!("Invalid frame at 0x{:x}", at)));
+
+        let (packets, error) = read_capture(&bytes[..HEADER_LEN - 1]);
+        assert!(packets.is_empty());
+        assert!(error.unwrap().starts_with("Invalid capture"));
+    }
+
+    #[test]
+    fn decode_failures() {
+        let packet = |data: &[u8]| Packet {
+            index: 0,
+            state: State::Play,
+            direction: Direction::ToClient,
+            offset: 0,
+            time: None,
+            data: data.to_vec(),
+        };
+        assert_eq!(decode(&packet(&[0x00, 0, 0, 0, 1])).error, None);
+        assert!(decode(&packet(&[0x00, 0])).error.is_some());
+        assert_eq!(
+            decode(&packet(&[0x00, 0, 0, 0, 1, 9])).error.unwrap(),
+            "1 bytes left after the packet"
+        );
+    }
+}
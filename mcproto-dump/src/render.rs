@@ -0,0 +1,194 @@
+//! Text output of traced packets
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use mcproto::packet::{Direction, State};
+use serde_mcproto::de::trace::{Field, Trace};
+
+/// Elements of a sequence shown before the rest is summed up
+const SHOWN_ELEMENTS: usize = 8;
+const HEX_DUMP_WIDTH: usize = 16;
+
+/// Packet to print and where it came from
+pub struct Packet {
+    pub index: usize,
+    pub state: State,
+    pub direction: Direction,
+    /// Offset of the packet in the input
+    pub offset: usize,
+    /// Time since the start of a capture
+    pub time: Option<Duration>,
+    pub data: Vec<u8>,
+}
+
+/// Decoding outcome of a packet
+pub struct Decoded {
+    pub trace: Trace,
+    pub error: Option<String>,
+}
+
+pub fn render(packet: &Packet, decoded: &Decoded, hex_dump: bool) -> String {
+    let mut out = String::new();
+    header(&mut out, packet, decoded);
+
+    let failed = decoded.trace.fields.iter().rposition(|field| field.end.is_none());
+    let mut hidden: Option<(usize, usize)> = None;
+    let mut elements: Vec<usize> = Vec::new();
+    // the packet enum itself is the header
+    for (i, field) in decoded.trace.fields.iter().enumerate().skip(1) {
+        if let Some((depth, count)) = hidden {
+            if field.depth > depth {
+                continue;
+            }
+            if field.depth == depth && is_element(field) && Some(i) != failed && field.end.is_some() {
+                hidden = Some((depth, count + 1));
+                continue;
+            }
+            more(&mut out, depth, count);
+            hidden = None;
+        }
+
+        elements.truncate(field.depth + 1);
+        elements.resize(field.depth + 1, 0);
+        if is_element(field) {
+            elements[field.depth] += 1;
+            if elements[field.depth] > SHOWN_ELEMENTS && field.end.is_some() {
+                hidden = Some((field.depth, 1));
+                continue;
+            }
+        }
+        line(&mut out, field, Some(i) == failed);
+    }
+    if let Some((depth, count)) = hidden {
+        more(&mut out, depth, count);
+    }
+
+    // a short read consumes what is left, the value being read tells where it started
+    let failed_at = failed.map_or(decoded.trace.position, |i| decoded.trace.fields[i].start);
+    if let Some(error) = &decoded.error {
+        let _ = writeln!(out, "  error at byte {}: {}", failed_at, error);
+    }
+    if hex_dump || decoded.error.is_some() {
+        dump(&mut out, &packet.data, decoded.error.as_ref().map(|_| failed_at));
+    }
+    out
+}
+
+fn header(out: &mut String, packet: &Packet, decoded: &Decoded) {
+    let _ = write!(
+        out,
+        "#{} {} {} at 0x{:x}",
+        packet.index, packet.state, packet.direction, packet.offset
+    );
+    if let Some(time) = packet.time {
+        let _ = write!(out, " +{}.{:03}s", time.as_secs(), time.subsec_millis());
+    }
+    let id = serde_mcproto::read_varint(&mut packet.data.as_slice()).ok();
+    let name = decoded
+        .trace
+        .fields
+        .get(1)
+        .filter(|field| field.name.as_deref() == Some("variant"))
+        .and_then(|field| field.value.as_deref())
+        .and_then(|value| value.split(" (").nth(1))
+        .map(|name| name.trim_end_matches(')'));
+    let _ = match (name, id) {
+        (Some(name), Some(id)) => write!(out, ": {} (0x{:02x})", name, id),
+        (None, Some(id)) => write!(out, ": unknown packet 0x{:02x}", id),
+        _ => write!(out, ": no packet id"),
+    };
+    let _ = writeln!(out, ", {} bytes", packet.data.len());
+}
+
+fn is_element(field: &Field) -> bool {
+    matches!(field.name.as_deref(), Some(name) if name.starts_with('['))
+}
+
+fn line(out: &mut String, field: &Field, failed: bool) {
+    let range = match field.end {
+        Some(end) => format!("{}..{}", field.start, end),
+        None => format!("{}..?", field.start),
+    };
+    let _ = write!(out, "  {:>11}  {}", range, "  ".repeat(field.depth - 1));
+    if let Some(name) = &field.name {
+        let _ = write!(out, "{}: ", name);
+    }
+    let _ = write!(out, "{}", field.kind);
+    if let Some(value) = &field.value {
+        let _ = write!(out, " = {}", value);
+    }
+    if failed {
+        let _ = write!(out, "  <-- decoding failed here");
+    }
+    out.push('\n');
+}
+
+fn more(out: &mut String, depth: usize, count: usize) {
+    let _ = writeln!(out, "  {:>11}  {}... {} more", "", "  ".repeat(depth - 1), count);
+}
+
+/// Hex dump, marking the byte at `failed_at`
+fn dump(out: &mut String, data: &[u8], failed_at: Option<usize>) {
+    for (row, chunk) in data.chunks(HEX_DUMP_WIDTH).enumerate() {
+        let start = row * HEX_DUMP_WIDTH;
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let _ = writeln!(out, "  {:06x}  {}", start, hex.join(" "));
+        if let Some(at) = failed_at.filter(|at| (start..start + chunk.len()).contains(at)) {
+            let _ = writeln!(out, "  {:6}  {}^^", "", "   ".repeat(at - start));
+        }
+    }
+    // ran out of bytes
+    if failed_at == Some(data.len()) {
+        let _ = writeln!(out, "  {:06x}  ^^ end of packet", data.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcproto::v1_7_10::PlayToClient;
+    use serde_mcproto::de::trace::trace;
+
+    fn rendered(data: &[u8], error: Option<&str>, hex_dump: bool) -> String {
+        let packet = Packet {
+            index: 3,
+            state: State::Play,
+            direction: Direction::ToClient,
+            offset: 0x20,
+            time: Some(Duration::from_millis(1500)),
+            data: data.to_vec(),
+        };
+        let decoded = Decoded {
+            trace: trace::<PlayToClient>(data).1,
+            error: error.map(str::to_owned),
+        };
+        render(&packet, &decoded, hex_dump)
+    }
+
+    #[test]
+    fn decoded() {
+        assert_eq!(
+            rendered(&[0x00, 0, 0, 0, 7], None, false),
+            "#3 play to client at 0x20 +1.500s: KeepAlive (0x00), 5 bytes\n\
+             \x20        0..1  variant: VarInt = 0 (KeepAlive)\n\
+             \x20        1..5  keep_alive_id: i32 = 7\n"
+        );
+        assert!(rendered(&[0x00, 0, 0, 0, 7], None, true).ends_with("  000000  00 00 00 00 07\n"));
+    }
+
+    #[test]
+    fn failed() {
+        assert_eq!(
+            rendered(&[0x00, 0, 0], Some("failed to fill whole buffer"), false),
+            "#3 play to client at 0x20 +1.500s: KeepAlive (0x00), 3 bytes\n\
+             \x20        0..1  variant: VarInt = 0 (KeepAlive)\n\
+             \x20        1..?  keep_alive_id: i32  <-- decoding failed here\n\
+             \x20 error at byte 1: failed to fill whole buffer\n\
+             \x20 000000  00 00 00\n\
+             \x20            ^^\n"
+        );
+        assert!(rendered(&[0x7f], Some("unknown"), false).contains(": unknown packet 0x7f, 1 bytes\n"));
+        assert!(rendered(&[], Some("empty"), false).contains(": no packet id, 0 bytes\n"));
+    }
+}
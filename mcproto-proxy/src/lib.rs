@@ -0,0 +1,635 @@
+//! Man in the middle proxy for 1.7.10
+//!
+//! Clients connect to the proxy, which connects to the upstream server and relays every
+//! packet. In the play state [`Hooks`] see the packets of both directions as [`Frame`]s and
+//! may rewrite, drop or inject packets: a frame is only decoded when a hook asks for its
+//! packet, the others are relayed as the bytes they were read from. The handshake is
+//! rewritten to name the upstream server, status pings and the login are relayed as they are.
+//!
+//! Only offline mode servers can be proxied: the client encrypts with the key of the
+//! server it authenticated for, so an `EncryptionRequest` of the upstream server ends the
+//! connection. 1.7.10 has no compression, packets are plain once the login is done.
+//!
+//! ```ignore
+//! struct NoChat;
+//!
+//! impl Hooks for NoChat {
+//!     fn to_client(&mut self, _: &mut Session, frame: Frame<PlayToClient>) -> Option<Frame<PlayToClient>> {
+//!         match frame.name() {
+//!             Some("ChatMessage") => None,
+//!             _ => Some(frame),
+//!         }
+//!     }
+//! }
+//!
+//! let listener = TcpListener::bind("127.0.0.1:25566").await?;
+//! Proxy::new("localhost", 25565, || NoChat).serve(listener).await?;
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{select, Either};
+use futures::{pin_mut, Sink, SinkExt, Stream, StreamExt};
+use mcproto::packet::Packet;
+use mcproto::types::string::BoundedString;
+use mcproto::types::uuid::Uuid;
+use mcproto::v1_7_10::{
+    Handshake, LoginToClient, LoginToServer, NextState, PlayToClient, PlayToServer,
+    StatusToClient, StatusToServer,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_serde_mcproto::codec::{switch_codec, MCProtoCodec};
+use tokio_serde_mcproto::frame::{into_frames, FrameCodec};
+use tokio_serde_mcproto::server::ConnectionInfo;
+use tokio_util::codec::Framed;
+
+/// Connection to the client, the proxy plays the server
+type ClientSide<S, T, R> = Framed<S, MCProtoCodec<T, R>>;
+/// Connection to the upstream server, the proxy plays the client
+type ServerSide<S, T, R> = Framed<S, MCProtoCodec<T, R>>;
+/// Connection in the play state, packets are only decoded for the hooks
+type PlaySide<S, T, R> = Framed<S, FrameCodec<T, R>>;
+
+pub use tokio_serde_mcproto::frame::Frame;
+
+#[derive(Debug)]
+pub enum ProxyError {
+    Io(io::Error),
+    Codec(mcproto::error::Error),
+    /// Upstream server asked for encryption, it is in online mode
+    OnlineMode,
+    /// Handshake asked for a state other than status or login
+    InvalidNextState,
+    /// One side closed before the login finished
+    Closed,
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProxyError::Io(e) => e.fmt(f),
+            ProxyError::Codec(e) => e.fmt(f),
+            ProxyError::OnlineMode => write!(f, "Upstream server is in online mode"),
+            ProxyError::InvalidNextState => write!(f, "Handshake asked for an invalid state"),
+            ProxyError::Closed => write!(f, "Connection closed during login"),
+        }
+    }
+}
+
+impl StdError for ProxyError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ProxyError::Io(e) => Some(e),
+            ProxyError::Codec(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(e: io::Error) -> ProxyError {
+        ProxyError::Io(e)
+    }
+}
+
+impl From<mcproto::error::Error> for ProxyError {
+    fn from(e: mcproto::error::Error) -> ProxyError {
+        match e {
+            mcproto::error::Error::IoError(e) => ProxyError::Io(e),
+            e => ProxyError::Codec(e),
+        }
+    }
+}
+
+/// Player of a proxied connection and the packets hooks inject
+#[derive(Debug)]
+pub struct Session {
+    pub info: ConnectionInfo,
+    pub username: String,
+    pub uuid: Uuid,
+    to_client: Vec<PlayToClient>,
+    to_server: Vec<PlayToServer>,
+}
+
+impl Session {
+    /// Send `packet` to the client after the packet being handled
+    pub fn inject_to_client(&mut self, packet: PlayToClient) {
+        self.to_client.push(packet);
+    }
+
+    /// Send `packet` to the upstream server after the packet being handled
+    pub fn inject_to_server(&mut self, packet: PlayToServer) {
+        self.to_server.push(packet);
+    }
+}
+
+/// Callbacks of a proxied connection in the play state, every connection gets its own.
+///
+/// Returning the frame forwards it, returning `None` drops it. A frame whose packet was
+/// changed through [`Frame::packet_mut`] is encoded again, others are forwarded as read.
+pub trait Hooks: Send + 'static {
+    /// Upstream server accepted the login, nothing was relayed in the play state yet
+    fn login(&mut self, _session: &mut Session) {}
+
+    fn to_server(&mut self, _session: &mut Session, frame: Frame<PlayToServer>) -> Option<Frame<PlayToServer>> {
+        Some(frame)
+    }
+
+    fn to_client(&mut self, _session: &mut Session, frame: Frame<PlayToClient>) -> Option<Frame<PlayToClient>> {
+        Some(frame)
+    }
+
+    /// Either side closed the connection or relaying failed
+    fn disconnected(&mut self, _session: &Session) {}
+}
+
+/// Relays everything untouched
+#[derive(Debug, Default)]
+pub struct Passthrough;
+
+impl Hooks for Passthrough {}
+
+pub struct Proxy<F> {
+    host: String,
+    port: u16,
+    hooks: F,
+}
+
+impl<F, H> Proxy<F>
+where
+    F: Fn() -> H + Send + Sync + 'static,
+    H: Hooks,
+{
+    /// Proxy to `host`:`port`, `hooks` is called for every connection
+    pub fn new<S: Into<String>>(host: S, port: u16, hooks: F) -> Proxy<F> {
+        Proxy {
+            host: host.into(),
+            port,
+            hooks,
+        }
+    }
+
+    /// Accept clients of `listener` forever, every connection is proxied in its own task
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let proxy = Arc::new(self);
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let proxy = proxy.clone();
+            tokio::spawn(async move {
+                // an error only ends this connection
+                let _ = proxy.proxy(stream, addr).await;
+            });
+        }
+    }
+
+    /// Proxy one client until either side closes
+    pub async fn proxy(&self, client: TcpStream, addr: SocketAddr) -> Result<(), ProxyError> {
+        let server = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        self.proxy_stream(client, addr, server).await
+    }
+
+    /// Proxy one client over established connections to it and to the upstream server
+    pub async fn proxy_stream<C, U>(&self, client: C, addr: SocketAddr, server: U) -> Result<(), ProxyError>
+    where
+        C: AsyncRead + AsyncWrite + Unpin,
+        U: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut client = Framed::new(client, MCProtoCodec::<(), Handshake>::new());
+        let (info, next_state) = match client.next().await {
+            Some(packet) => match packet? {
+                Handshake::Handshake {
+                    proto_version,
+                    server_address,
+                    server_port,
+                    next_state,
+                } => (
+                    ConnectionInfo {
+                        addr,
+                        protocol_version: proto_version.0,
+                        server_address: server_address.into_inner(),
+                        server_port,
+                    },
+                    next_state,
+                ),
+            },
+            None => return Err(ProxyError::Closed),
+        };
+
+        let mut server = Framed::new(server, MCProtoCodec::<Handshake, ()>::new());
+        server
+            .send(Handshake::Handshake {
+                proto_version: mcproto::types::var::VarInt(info.protocol_version),
+                server_address: BoundedString::new(self.host.as_str())?,
+                server_port: self.port,
+                next_state: next_state.clone(),
+            })
+            .await?;
+
+        match next_state {
+            NextState::Status => relay_status(switch_codec(client), switch_codec(server)).await,
+            NextState::Login => {
+                let (client, server, session) =
+                    match relay_login(switch_codec(client), switch_codec(server), info).await? {
+                        Some(logged_in) => logged_in,
+                        None => return Ok(()),
+                    };
+                let mut hooks = (self.hooks)();
+                relay_play(into_frames(client), into_frames(server), session, &mut hooks).await
+            }
+            NextState::None => Err(ProxyError::InvalidNextState),
+        }
+    }
+}
+
+/// Status pings are request and answer, relay them in turn
+async fn relay_status<C, U>(
+    mut client: ClientSide<C, StatusToClient, StatusToServer>,
+    mut server: ServerSide<U, StatusToServer, StatusToClient>,
+) -> Result<(), ProxyError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(request) = client.next().await {
+        server.send(request?).await?;
+        match server.next().await {
+            Some(response) => client.send(response?).await?,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Relay the login, `None` if the server refused the player
+async fn relay_login<C, U>(
+    mut client: ClientSide<C, LoginToClient, LoginToServer>,
+    mut server: ServerSide<U, LoginToServer, LoginToClient>,
+    info: ConnectionInfo,
+) -> Result<
+    Option<(
+        ClientSide<C, PlayToClient, PlayToServer>,
+        ServerSide<U, PlayToServer, PlayToClient>,
+        Session,
+    )>,
+    ProxyError,
+>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    match client.next().await {
+        Some(login_start) => server.send(login_start?).await?,
+        None => return Err(ProxyError::Closed),
+    }
+
+    let packet = match server.next().await {
+        Some(packet) => packet?,
+        None => return Err(ProxyError::Closed),
+    };
+    match packet {
+        LoginToClient::LoginSuccess { uuid, username } => {
+            client
+                .send(LoginToClient::LoginSuccess {
+                    uuid,
+                    username: username.clone(),
+                })
+                .await?;
+            let session = Session {
+                info,
+                username,
+                uuid,
+                to_client: Vec::new(),
+                to_server: Vec::new(),
+            };
+            Ok(Some((switch_codec(client), switch_codec(server), session)))
+        }
+        LoginToClient::EncryptionRequest { .. } => {
+            client
+                .send(LoginToClient::Disconnect {
+                    reason: r#"{"text":"The proxy only supports offline mode servers"}"#.to_owned(),
+                })
+                .await?;
+            Err(ProxyError::OnlineMode)
+        }
+        disconnect @ LoginToClient::Disconnect { .. } => {
+            client.send(disconnect).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Relay the play state, each direction in a future of its own so a side which is slow to
+/// read does not hold back the other direction
+async fn relay_play<C, U, H>(
+    client: PlaySide<C, PlayToClient, PlayToServer>,
+    server: PlaySide<U, PlayToServer, PlayToClient>,
+    mut session: Session,
+    hooks: &mut H,
+) -> Result<(), ProxyError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+    H: Hooks,
+{
+    hooks.login(&mut session);
+    let (to_client, injected_to_client) = mpsc::unbounded();
+    let (to_server, injected_to_server) = mpsc::unbounded();
+    let mut relay = Relay {
+        hooks,
+        session,
+        to_client,
+        to_server,
+    };
+    relay.inject();
+    let relay = Mutex::new(relay);
+
+    let result = {
+        let (client_sink, client_stream) = client.split();
+        let (server_sink, server_stream) = server.split();
+        let upstream = relay_frames(client_stream, server_sink, injected_to_server, |frame| {
+            relay.lock().unwrap_or_else(|e| e.into_inner()).hook_to_server(frame)
+        });
+        let downstream = relay_frames(server_stream, client_sink, injected_to_client, |frame| {
+            relay.lock().unwrap_or_else(|e| e.into_inner()).hook_to_client(frame)
+        });
+        pin_mut!(upstream, downstream);
+        match select(upstream, downstream).await {
+            Either::Left((result, _)) | Either::Right((result, _)) => result,
+        }
+    };
+    let relay = relay.into_inner().unwrap_or_else(|e| e.into_inner());
+    relay.hooks.disconnected(&relay.session);
+    result
+}
+
+/// Hooks of a connection and the packets they injected, shared by both directions
+struct Relay<'a, H> {
+    hooks: &'a mut H,
+    session: Session,
+    to_client: UnboundedSender<PlayToClient>,
+    to_server: UnboundedSender<PlayToServer>,
+}
+
+impl<H: Hooks> Relay<'_, H> {
+    fn hook_to_server(&mut self, frame: Frame<PlayToServer>) -> Option<Frame<PlayToServer>> {
+        let frame = self.hooks.to_server(&mut self.session, frame);
+        self.inject();
+        frame
+    }
+
+    fn hook_to_client(&mut self, frame: Frame<PlayToClient>) -> Option<Frame<PlayToClient>> {
+        let frame = self.hooks.to_client(&mut self.session, frame);
+        self.inject();
+        frame
+    }
+
+    /// Hand the injected packets to the futures writing them
+    fn inject(&mut self) {
+        // the receivers live as long as the relay, nothing is lost
+        for packet in self.session.to_client.drain(..) {
+            let _ = self.to_client.unbounded_send(packet);
+        }
+        for packet in self.session.to_server.drain(..) {
+            let _ = self.to_server.unbounded_send(packet);
+        }
+    }
+}
+
+/// Relay the frames of one direction through `hook` until either side closes. Injected
+/// packets go out before the next frame is read.
+async fn relay_frames<R, W, P, F>(
+    mut frames: R,
+    mut sink: W,
+    mut injected: UnboundedReceiver<P>,
+    mut hook: F,
+) -> Result<(), ProxyError>
+where
+    R: Stream<Item = Result<Frame<P>, mcproto::error::Error>> + Unpin,
+    W: Sink<Frame<P>, Error = mcproto::error::Error> + Unpin,
+    P: Packet,
+    F: FnMut(Frame<P>) -> Option<Frame<P>>,
+{
+    loop {
+        // the losing read is dropped, neither loses what it buffered
+        let frame = match select(injected.next(), frames.next()).await {
+            Either::Left((Some(packet), _)) => Frame::from(packet),
+            Either::Left((None, _)) => return Ok(()),
+            Either::Right((Some(frame), _)) => match hook(frame?) {
+                Some(frame) => frame,
+                None => continue,
+            },
+            Either::Right((None, _)) => return Ok(()),
+        };
+        sink.send(frame).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use futures::future;
+    use mcproto::types::string::Username;
+    use mcproto::types::uuid::UuidSimple;
+    use mcproto::v1_7_10::PROTOCOL_VERSION;
+    use tokio::io::{duplex, DuplexStream};
+    use tokio::time;
+
+    /// Drops chat messages saying "secret" and answers them with a time update, doubles the
+    /// world age of time updates sent by the server
+    struct Secret;
+
+    impl Hooks for Secret {
+        fn to_server(&mut self, session: &mut Session, mut frame: Frame<PlayToServer>) -> Option<Frame<PlayToServer>> {
+            match frame.packet() {
+                Ok(PlayToServer::ChatMessage { message }) if &**message == "secret" => {
+                    session.inject_to_client(PlayToClient::TimeUpdate {
+                        world_age: 1,
+                        time_of_day: 2,
+                    });
+                    None
+                }
+                _ => Some(frame),
+            }
+        }
+
+        fn to_client(&mut self, _session: &mut Session, mut frame: Frame<PlayToClient>) -> Option<Frame<PlayToClient>> {
+            if frame.name() == Some("TimeUpdate") {
+                if let Ok(PlayToClient::TimeUpdate { world_age, .. }) = frame.packet_mut() {
+                    *world_age *= 2;
+                }
+            }
+            Some(frame)
+        }
+    }
+
+    fn handshake(address: &str, port: u16) -> Handshake {
+        Handshake::Handshake {
+            proto_version: PROTOCOL_VERSION,
+            server_address: BoundedString::new(address).unwrap(),
+            server_port: port,
+            next_state: NextState::Login,
+        }
+    }
+
+    fn chat(message: &str) -> PlayToServer {
+        PlayToServer::ChatMessage {
+            message: BoundedString::new(message).unwrap(),
+        }
+    }
+
+    /// Log in through the proxy as Alex
+    async fn client_login(stream: DuplexStream) -> ServerSide<DuplexStream, PlayToServer, PlayToClient> {
+        let mut framed = Framed::new(stream, MCProtoCodec::<Handshake, ()>::new());
+        framed.send(handshake("proxy.example", 25566)).await.unwrap();
+        let mut framed: ServerSide<_, LoginToServer, LoginToClient> = switch_codec(framed);
+        let name = Username::new("Alex").unwrap();
+        framed.send(LoginToServer::LoginStart { name }).await.unwrap();
+        match framed.next().await.unwrap().unwrap() {
+            LoginToClient::LoginSuccess { username, .. } => assert_eq!(username, "Alex"),
+            other => panic!("expected a login success, got {:?}", other),
+        }
+        switch_codec(framed)
+    }
+
+    async fn client(stream: DuplexStream) {
+        let mut framed = client_login(stream).await;
+        framed.send(chat("secret")).await.unwrap();
+        match framed.next().await.unwrap().unwrap() {
+            PlayToClient::TimeUpdate { world_age, .. } => assert_eq!(world_age, 1),
+            other => panic!("expected the injected time update, got {:?}", other),
+        }
+        framed.send(chat("hello")).await.unwrap();
+        match framed.next().await.unwrap().unwrap() {
+            PlayToClient::KeepAlive { keep_alive_id } => assert_eq!(keep_alive_id, 7),
+            other => panic!("expected a keep alive, got {:?}", other),
+        }
+        match framed.next().await.unwrap().unwrap() {
+            PlayToClient::TimeUpdate { world_age, time_of_day } => assert_eq!((world_age, time_of_day), (200, 6000)),
+            other => panic!("expected a rewritten time update, got {:?}", other),
+        }
+    }
+
+    /// Check the rewritten handshake and accept the login
+    async fn upstream_login(stream: DuplexStream) -> ClientSide<DuplexStream, PlayToClient, PlayToServer> {
+        let mut framed = Framed::new(stream, MCProtoCodec::<(), Handshake>::new());
+        match framed.next().await.unwrap().unwrap() {
+            Handshake::Handshake {
+                proto_version,
+                server_address,
+                server_port,
+                next_state,
+            } => {
+                assert_eq!(proto_version.0, PROTOCOL_VERSION.0);
+                assert_eq!(&*server_address, "upstream.example");
+                assert_eq!(server_port, 25565);
+                assert!(matches!(next_state, NextState::Login));
+            }
+        }
+        let mut framed: ClientSide<_, LoginToClient, LoginToServer> = switch_codec(framed);
+        let username = match framed.next().await.unwrap().unwrap() {
+            LoginToServer::LoginStart { name } => name.into_inner(),
+            other => panic!("expected a login start, got {:?}", other),
+        };
+        framed
+            .send(LoginToClient::LoginSuccess {
+                uuid: Uuid::from(UuidSimple::offline(&username)),
+                username,
+            })
+            .await
+            .unwrap();
+        switch_codec(framed)
+    }
+
+    async fn upstream(stream: DuplexStream) {
+        let mut framed = upstream_login(stream).await;
+        // the secret never arrives
+        match framed.next().await.unwrap().unwrap() {
+            PlayToServer::ChatMessage { message } => assert_eq!(&*message, "hello"),
+            other => panic!("expected a chat message, got {:?}", other),
+        }
+        framed.send(PlayToClient::KeepAlive { keep_alive_id: 7 }).await.unwrap();
+        framed
+            .send(PlayToClient::TimeUpdate {
+                world_age: 100,
+                time_of_day: 6000,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rewrites_handshake_and_runs_hooks() {
+        let (client_end, proxy_client) = duplex(64 * 1024);
+        let (proxy_server, upstream_end) = duplex(64 * 1024);
+        let proxy = Proxy::new("upstream.example", 25565, || Secret);
+        let addr = "127.0.0.1:50000".parse().unwrap();
+        let (result, _, _) = future::join3(
+            proxy.proxy_stream(proxy_client, addr, proxy_server),
+            client(client_end),
+            upstream(upstream_end),
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn directions_do_not_wait_for_each_other() {
+        const PACKETS: i64 = 200;
+        // a few packets fill the connections
+        let (client_end, proxy_client) = duplex(256);
+        let (proxy_server, upstream_end) = duplex(256);
+        let proxy = Proxy::new("upstream.example", 25565, || Passthrough);
+        let addr = "127.0.0.1:50000".parse().unwrap();
+
+        // reads and writes at the same time
+        let client = async {
+            let (mut sink, mut stream) = client_login(client_end).await.split();
+            let send = async {
+                for _ in 0..PACKETS {
+                    sink.send(chat("hello")).await.unwrap();
+                }
+            };
+            let receive = async {
+                for world_age in 0..PACKETS {
+                    match stream.next().await.unwrap().unwrap() {
+                        PlayToClient::TimeUpdate { world_age: age, .. } => assert_eq!(age, world_age),
+                        other => panic!("expected a time update, got {:?}", other),
+                    }
+                }
+            };
+            future::join(send, receive).await;
+        };
+        // only reads once it wrote everything, the proxy has to keep reading meanwhile
+        let upstream = async {
+            let mut framed = upstream_login(upstream_end).await;
+            for world_age in 0..PACKETS {
+                framed
+                    .send(PlayToClient::TimeUpdate {
+                        world_age,
+                        time_of_day: 0,
+                    })
+                    .await
+                    .unwrap();
+            }
+            for _ in 0..PACKETS {
+                match framed.next().await.unwrap().unwrap() {
+                    PlayToServer::ChatMessage { message } => assert_eq!(&*message, "hello"),
+                    other => panic!("expected a chat message, got {:?}", other),
+                }
+            }
+        };
+        let relayed = future::join3(proxy.proxy_stream(proxy_client, addr, proxy_server), client, upstream);
+        let (result, _, _) = time::timeout(Duration::from_secs(10), relayed)
+            .await
+            .expect("the proxy stopped relaying");
+        result.unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Proxy printing every play packet
+//!
+//! `mcproto-proxy <listen address> <upstream host>[:port]`
+
+use std::env;
+use std::fmt::Debug;
+use std::process;
+
+use mcproto::packet::Packet;
+use mcproto::v1_7_10::{PlayToClient, PlayToServer};
+use mcproto_proxy::{Frame, Hooks, Proxy, Session};
+use tokio::net::TcpListener;
+
+struct Logger;
+
+/// Print the decoded packet, or its name if it does not decode, it is relayed anyway
+fn print<P: Packet + Debug>(username: &str, arrow: &str, frame: &mut Frame<P>) {
+    let name = frame.name().unwrap_or("unknown packet");
+    match frame.packet() {
+        Ok(packet) => println!("{} {} {:?}", username, arrow, packet),
+        Err(e) => println!("{} {} {} which does not decode: {}", username, arrow, name, e),
+    }
+}
+
+impl Hooks for Logger {
+    fn login(&mut self, session: &mut Session) {
+        println!("{} joined from {}", session.username, session.info.addr);
+    }
+
+    fn to_server(&mut self, session: &mut Session, mut frame: Frame<PlayToServer>) -> Option<Frame<PlayToServer>> {
+        print(&session.username, "->", &mut frame);
+        Some(frame)
+    }
+
+    fn to_client(&mut self, session: &mut Session, mut frame: Frame<PlayToClient>) -> Option<Frame<PlayToClient>> {
+        print(&session.username, "<-", &mut frame);
+        Some(frame)
+    }
+
+    fn disconnected(&mut self, session: &Session) {
+        println!("{} left", session.username);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: mcproto-proxy <listen address> <upstream host>[:port]");
+    process::exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let (listen, upstream) = match (args.next(), args.next()) {
+        (Some(listen), Some(upstream)) => (listen, upstream),
+        _ => usage(),
+    };
+    let (host, port) = match upstream.rfind(':') {
+        Some(i) => match upstream[i + 1..].parse() {
+            Ok(port) => (upstream[..i].to_owned(), port),
+            Err(_) => usage(),
+        },
+        None => (upstream, 25565),
+    };
+
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on {}: {}", listen, e);
+            process::exit(1);
+        }
+    };
+    println!("Proxying {} to {}:{}", listen, host, port);
+    if let Err(e) = Proxy::new(host, port, || Logger).serve(listener).await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
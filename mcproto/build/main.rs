@@ -0,0 +1,42 @@
+//! Generate the version modules of `mcproto` from the protocol.json files under `protocol/`,
+//! which follow the format of minecraft-data.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+mod protocol;
+mod rust;
+
+/// Module and directory under `protocol/` of every generated version
+const VERSIONS: &[(&str, &str)] = &[("v1_7", "1.7")];
+
+fn read_json(path: &Path) -> Value {
+    println!("cargo:rerun-if-changed={}", path.display());
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e));
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("Could not parse {}: {}", path.display(), e))
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build");
+    let out_dir = env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+    for (module, dir) in VERSIONS {
+        let dir = Path::new("protocol").join(dir);
+        let version = read_json(&dir.join("version.json"));
+        let protocol_version = version["version"]
+            .as_i64()
+            .unwrap_or_else(|| panic!("{} has no protocol version", dir.display()));
+        let minecraft_version = version["minecraftVersion"]
+            .as_str()
+            .unwrap_or_else(|| panic!("{} has no minecraft version", dir.display()));
+
+        let path = dir.join("protocol.json");
+        let protocol = protocol::Protocol::parse(&read_json(&path))
+            .unwrap_or_else(|e| panic!("Could not generate {}: {}", path.display(), e));
+        let source = rust::module(&protocol, protocol_version, minecraft_version, &path.display().to_string());
+        let out = Path::new(&out_dir).join(format!("{}.rs", module));
+        fs::write(&out, source).unwrap_or_else(|e| panic!("Could not write {}: {}", out.display(), e));
+    }
+}
@@ -0,0 +1,517 @@
+//! Read the minecraft-data protocol.json format into the packets and structs of each state
+
+use serde_json::{Map, Value};
+
+pub type Result<T> = std::result::Result<T, String>;
+
+/// States in the order they are generated, with their directions
+const STATES: &[&str] = &["handshaking", "status", "login", "play"];
+const DIRECTIONS: &[&str] = &["toClient", "toServer"];
+
+pub struct Protocol {
+    pub states: Vec<State>,
+}
+
+pub struct State {
+    /// Name in protocol.json, like `play`
+    pub name: String,
+    pub directions: Vec<Direction>,
+}
+
+pub struct Direction {
+    /// Name in protocol.json, `toClient` or `toServer`
+    pub name: String,
+    /// Packets sorted by id, without gaps
+    pub packets: Vec<Packet>,
+    /// Packet structs followed by the structs of their containers
+    pub structs: Vec<Struct>,
+}
+
+pub struct Packet {
+    pub id: u32,
+    /// Name in protocol.json, `None` for an id without packet
+    pub name: Option<String>,
+}
+
+pub struct Struct {
+    /// Rust name
+    pub name: String,
+    pub doc: String,
+    pub fields: Vec<Field>,
+}
+
+impl Struct {
+    /// Whether the fields are read one after the other, so the impls can be derived
+    pub fn is_plain(&self) -> bool {
+        self.fields.iter().all(|field| {
+            matches!(
+                field.ty,
+                Type::Native(_) | Type::Mapper { .. } | Type::Prefixed(..) | Type::Struct(_)
+            )
+        })
+    }
+}
+
+pub struct Field {
+    /// Name in protocol.json, camel case
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Rust type with a serde impl reading it
+    Native(&'static str),
+    /// Integer whose values have names, switches compare it to the names
+    Mapper {
+        ty: &'static str,
+        mappings: Vec<(String, String)>,
+    },
+    /// Prefix type of `serde_mcproto::types::array` and its element
+    Prefixed(&'static str, Box<Type>),
+    /// Generated struct
+    Struct(String),
+    /// Element count of a later field, not stored in the struct
+    Count { ty: &'static str, count_for: String },
+    /// Elements counted by an earlier `Count` field
+    Counted { count: String, element: Box<Type> },
+    /// Bool telling whether the value follows
+    Optional(Box<Type>),
+    /// Value present depending on an earlier field, `patterns` match the values of that field
+    /// for which it is `matched`, otherwise it is `!matched`
+    Switch {
+        compare: String,
+        patterns: Vec<String>,
+        matched: bool,
+        ty: Box<Type>,
+    },
+}
+
+impl Protocol {
+    pub fn parse(json: &Value) -> Result<Protocol> {
+        let root = object(json, "protocol")?;
+        let global = object(root.get("types").unwrap_or(&Value::Null), "types")?;
+        for key in root.keys() {
+            if key != "types" && !STATES.contains(&key.as_str()) {
+                return Err(format!("unknown state `{}`", key));
+            }
+        }
+
+        let mut states = Vec::new();
+        for state in STATES {
+            let json = match root.get(*state) {
+                Some(json) => object(json, state)?,
+                None => continue,
+            };
+            let mut directions = Vec::new();
+            for direction in DIRECTIONS {
+                let types = match json.get(*direction).and_then(|json| json.get("types")) {
+                    Some(types) => object(types, direction)?,
+                    None => continue,
+                };
+                let context = format!("{}.{}", state, direction);
+                if let Some(direction) = Resolver::new(global, types)
+                    .direction(direction)
+                    .map_err(|e| format!("{}: {}", context, e))?
+                {
+                    directions.push(direction);
+                }
+            }
+            states.push(State {
+                name: state.to_string(),
+                directions,
+            });
+        }
+        Ok(Protocol { states })
+    }
+}
+
+/// Resolves the types of one direction, collecting the structs of its containers
+struct Resolver<'a> {
+    global: &'a Map<String, Value>,
+    types: &'a Map<String, Value>,
+    structs: Vec<Struct>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(global: &'a Map<String, Value>, types: &'a Map<String, Value>) -> Self {
+        Resolver {
+            global,
+            types,
+            structs: Vec::new(),
+        }
+    }
+
+    /// Packets of the `packet` container, `None` for a direction without packets
+    fn direction(mut self, name: &str) -> Result<Option<Direction>> {
+        let packet = match self.types.get("packet") {
+            Some(packet) => packet,
+            None => return Ok(None),
+        };
+        let fields = match packet {
+            Value::Array(parts) if parts.len() == 2 && parts[0] == "container" => {
+                array(&parts[1], "packet fields")?
+            }
+            _ => return Err("`packet` is not a container".to_string()),
+        };
+        let (mapper, switch) = match fields.as_slice() {
+            [name, params] => (
+                params_of(&name["type"], "mapper")?,
+                params_of(&params["type"], "switch")?,
+            ),
+            _ => return Err("`packet` needs a name mapper and a params switch".to_string()),
+        };
+
+        let mut ids = Vec::new();
+        for (id, packet) in object(&mapper["mappings"], "mappings")? {
+            let id = parse_int(id).ok_or_else(|| format!("packet id `{}` is not a number", id))?;
+            ids.push((id as u32, string(packet, "packet name")?));
+        }
+        ids.sort();
+
+        let bodies = object(&switch["fields"], "packet switch")?;
+        let mut packets = Vec::new();
+        for (id, packet) in ids {
+            while packets.len() < id as usize {
+                packets.push(Packet {
+                    id: packets.len() as u32,
+                    name: None,
+                });
+            }
+            if packets.len() > id as usize {
+                return Err(format!("packet id 0x{:02x} is mapped twice", id));
+            }
+            let body = bodies
+                .get(packet)
+                .ok_or_else(|| format!("packet `{}` is missing in the switch", packet))?;
+            let body = self.lookup(string(body, "packet type")?)?;
+            let fields = match body {
+                Value::Array(parts) if parts.len() == 2 && parts[0] == "container" => &parts[1],
+                _ => return Err(format!("packet `{}` is not a container", packet)),
+            };
+            let doc = format!("`{}`, packet 0x{:02x}", packet, id);
+            self.container(camel_case(packet), doc, fields)
+                .map_err(|e| format!("{}: {}", packet, e))?;
+            packets.push(Packet {
+                id,
+                name: Some(packet.to_string()),
+            });
+        }
+
+        Ok(Some(Direction {
+            name: name.to_string(),
+            packets,
+            structs: self.structs,
+        }))
+    }
+
+    fn lookup(&self, name: &str) -> Result<&'a Value> {
+        self.types
+            .get(name)
+            .or_else(|| self.global.get(name))
+            .ok_or_else(|| format!("unknown type `{}`", name))
+    }
+
+    /// Generate the struct of a container, the struct comes before the ones of its fields
+    fn container(&mut self, name: String, doc: String, json: &Value) -> Result<()> {
+        let index = self.structs.len();
+        let mut fields: Vec<Field> = Vec::new();
+        for field in array(json, "container fields")? {
+            let field_name = match field.get("name") {
+                Some(name) => string(name, "field name")?,
+                None => return Err("anonymous fields are not supported".to_string()),
+            };
+            let ty = self
+                .field_type(&field["type"], &name, field_name, &fields)
+                .map_err(|e| format!("{}: {}", field_name, e))?;
+            if let Some(ty) = ty {
+                fields.push(Field {
+                    name: field_name.to_string(),
+                    ty,
+                });
+            }
+        }
+
+        for field in &fields {
+            if let Type::Count { count_for, .. } = &field.ty {
+                let counted = fields.iter().any(|other| match &other.ty {
+                    Type::Counted { count, .. } => count == &field.name && &other.name == count_for,
+                    _ => false,
+                });
+                if !counted {
+                    return Err(format!("`{}` counts `{}` which does not use it", field.name, count_for));
+                }
+            }
+        }
+        self.structs.insert(index, Struct { name, doc, fields });
+        Ok(())
+    }
+
+    /// Type of a field, which may depend on the fields before it. `None` for a field which is
+    /// never present.
+    fn field_type(&mut self, json: &Value, owner: &str, field: &str, siblings: &[Field]) -> Result<Option<Type>> {
+        let (kind, params) = match json {
+            Value::Array(parts) if parts.len() == 2 => (string(&parts[0], "type")?, &parts[1]),
+            _ => return self.resolve(json, owner, field).map(Some),
+        };
+        match kind {
+            "switch" => self.switch(params, owner, field, siblings),
+            "option" => Ok(Some(Type::Optional(Box::new(self.resolve(params, owner, field)?)))),
+            "count" => {
+                let ty = count_type(string(&params["type"], "count type")?)?;
+                let count_for = string(&params["countFor"], "countFor")?.to_string();
+                Ok(Some(Type::Count { ty, count_for }))
+            }
+            "array" | "buffer" if params.get("count").is_some() => {
+                let count = string(&params["count"], "count")?;
+                match siblings.iter().find(|sibling| sibling.name == count) {
+                    Some(Field {
+                        ty: Type::Count { count_for, .. },
+                        ..
+                    }) if count_for == field => {}
+                    _ => return Err(format!("`{}` is not a count for this field", count)),
+                }
+                let element = if kind == "buffer" {
+                    Type::Native("u8")
+                } else {
+                    self.resolve(&params["type"], owner, field)?
+                };
+                Ok(Some(Type::Counted {
+                    count: count.to_string(),
+                    element: Box::new(element),
+                }))
+            }
+            _ => self.resolve(json, owner, field).map(Some),
+        }
+    }
+
+    fn switch(&mut self, params: &Value, owner: &str, field: &str, siblings: &[Field]) -> Result<Option<Type>> {
+        let compare = string(&params["compareTo"], "compareTo")?;
+        let sibling = siblings
+            .iter()
+            .find(|sibling| sibling.name == compare)
+            .ok_or_else(|| format!("switch compares `{}` which is not an earlier field", compare))?;
+
+        let void = Value::String("void".to_string());
+        let mut ty: Option<Type> = None;
+        let mut present = Vec::new();
+        let mut absent = Vec::new();
+        let mut cases: Vec<(Option<&String>, &Value)> = Vec::new();
+        for (key, case) in object(&params["fields"], "switch fields")? {
+            cases.push((Some(key), case));
+        }
+        cases.push((None, params.get("default").unwrap_or(&void)));
+
+        let mut default_present = false;
+        for (key, case) in cases {
+            let case_type = if *case == void {
+                None
+            } else {
+                Some(self.resolve(case, owner, field)?)
+            };
+            if let Some(case_type) = case_type {
+                if ty.as_ref().is_some_and(|ty| *ty != case_type) {
+                    return Err("switch cases with different types are not supported".to_string());
+                }
+                ty = Some(case_type);
+                match key {
+                    Some(key) => present.push(pattern(&sibling.ty, key)?),
+                    None => default_present = true,
+                }
+            } else if let Some(key) = key {
+                absent.push(pattern(&sibling.ty, key)?);
+            }
+        }
+
+        let ty = match ty {
+            Some(ty) => ty,
+            None => return Ok(None),
+        };
+        if default_present && absent.is_empty() {
+            return Ok(Some(ty));
+        }
+        let (patterns, matched) = if default_present { (absent, false) } else { (present, true) };
+        Ok(Some(Type::Switch {
+            compare: compare.to_string(),
+            patterns,
+            matched,
+            ty: Box::new(ty),
+        }))
+    }
+
+    /// Type which does not depend on other fields
+    fn resolve(&mut self, json: &Value, owner: &str, field: &str) -> Result<Type> {
+        let (kind, params) = match json {
+            Value::String(name) => {
+                let definition = self.lookup(name)?;
+                if definition == "native" {
+                    return native(name)
+                        .map(Type::Native)
+                        .ok_or_else(|| format!("native type `{}` is not supported", name));
+                }
+                return self.resolve(definition, owner, field);
+            }
+            Value::Array(parts) if parts.len() == 2 => (string(&parts[0], "type")?, &parts[1]),
+            _ => return Err(format!("invalid type `{}`", json)),
+        };
+
+        match kind {
+            "pstring" => match params.get("countType").and_then(Value::as_str) {
+                Some("varint") => Ok(Type::Native("String")),
+                _ => Err("only varint prefixed strings are supported".to_string()),
+            },
+            "buffer" => {
+                let prefix = prefix(string(&params["countType"], "countType")?)?;
+                Ok(Type::Prefixed(prefix, Box::new(Type::Native("u8"))))
+            }
+            "array" => {
+                let prefix = prefix(string(&params["countType"], "countType")?)?;
+                let element = self.resolve(&params["type"], owner, field)?;
+                Ok(Type::Prefixed(prefix, Box::new(element)))
+            }
+            "container" => {
+                let name = format!("{}{}", owner, camel_case(field));
+                let doc = format!("`{}` of `{}`", field, owner);
+                self.container(name.clone(), doc, params)?;
+                Ok(Type::Struct(name))
+            }
+            "mapper" => {
+                let ty = match native(string(&params["type"], "mapper type")?) {
+                    Some(ty) if ty != "bool" && ty != "String" => ty,
+                    _ => return Err("mappers need an integer type".to_string()),
+                };
+                let mut mappings = Vec::new();
+                for (value, name) in object(&params["mappings"], "mappings")? {
+                    mappings.push((value.clone(), string(name, "mapping")?.to_string()));
+                }
+                Ok(Type::Mapper { ty, mappings })
+            }
+            "switch" | "option" | "count" => Err(format!("`{}` is only supported as a field", kind)),
+            _ => Err(format!("type `{}` is not supported", kind)),
+        }
+    }
+}
+
+/// Rust type of a native type of protocol.json
+fn native(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "i8" => "i8",
+        "u8" => "u8",
+        "i16" => "i16",
+        "u16" => "u16",
+        "i32" => "i32",
+        "u32" => "u32",
+        "i64" => "i64",
+        "u64" => "u64",
+        "f32" => "f32",
+        "f64" => "f64",
+        "bool" => "bool",
+        "varint" => "VarInt",
+        "varlong" => "VarLong",
+        "UUID" => "UuidBinary",
+        "slot" => "Option<Slot>",
+        "entityMetadata" => "EntityMetadata",
+        _ => return None,
+    })
+}
+
+fn prefix(count_type: &str) -> Result<&'static str> {
+    Ok(match count_type {
+        "u8" => "UBytePrefix",
+        "i8" => "BytePrefix",
+        "i16" => "ShortPrefix",
+        "i32" => "IntPrefix",
+        "varint" => "VarIntPrefix",
+        _ => return Err(format!("arrays prefixed by `{}` are not supported", count_type)),
+    })
+}
+
+fn count_type(name: &str) -> Result<&'static str> {
+    match native(name) {
+        Some(ty) if ["i8", "u8", "i16", "u16", "i32", "VarInt"].contains(&ty) => Ok(ty),
+        _ => Err(format!("counts of type `{}` are not supported", name)),
+    }
+}
+
+/// Rust pattern matching a switch key against the compared field
+fn pattern(compare: &Type, key: &str) -> Result<String> {
+    let value = match compare {
+        Type::Native("bool") if key == "true" || key == "false" => return Ok(key.to_string()),
+        Type::Mapper { mappings, .. } => match mappings.iter().find(|(_, name)| name == key) {
+            Some((value, _)) => parse_int(value),
+            None => parse_int(key),
+        },
+        Type::Native(ty) if ty.starts_with('i') || ty.starts_with('u') || ty.starts_with("Var") => parse_int(key),
+        _ => return Err("switches can only compare integers and bools".to_string()),
+    };
+    value
+        .map(|value| value.to_string())
+        .ok_or_else(|| format!("switch key `{}` is not a value of the compared field", key))
+}
+
+fn parse_int(value: &str) -> Option<i64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// `keep_alive` and `objectData` to `KeepAlive` and `ObjectData`
+pub fn camel_case(name: &str) -> String {
+    name.split('_')
+        .flat_map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
+/// `playerUUID` to `player_uuid`, keywords get a trailing underscore like `type_`
+pub fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(char::is_ascii_lowercase);
+            if previous.is_ascii_lowercase() || previous.is_ascii_digit() || (previous.is_ascii_uppercase() && next_lower) {
+                snake.push('_');
+            }
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    if KEYWORDS.contains(&snake.as_str()) {
+        snake.push('_');
+    }
+    snake
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+    "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn",
+];
+
+fn object<'v>(json: &'v Value, what: &str) -> Result<&'v Map<String, Value>> {
+    json.as_object().ok_or_else(|| format!("{} is not an object", what))
+}
+
+fn array<'v>(json: &'v Value, what: &str) -> Result<&'v Vec<Value>> {
+    json.as_array().ok_or_else(|| format!("{} is not an array", what))
+}
+
+fn string<'v>(json: &'v Value, what: &str) -> Result<&'v str> {
+    json.as_str().ok_or_else(|| format!("{} is not a string", what))
+}
+
+/// Parameters of a `[kind, params]` type
+fn params_of<'v>(json: &'v Value, kind: &str) -> Result<&'v Value> {
+    match json {
+        Value::Array(parts) if parts.len() == 2 && parts[0] == kind => Ok(&parts[1]),
+        _ => Err(format!("expected a {}", kind)),
+    }
+}
@@ -0,0 +1,410 @@
+//! Write a version module from the packets and structs read by `protocol`
+
+use std::collections::BTreeMap;
+
+use crate::protocol::{camel_case, snake_case, Direction, Field, Protocol, Struct, Type};
+
+/// Paths of the names generated code may use, imported when they appear in a module
+const IMPORTS: &[(&str, &str)] = &[
+    ("Deserialize", "serde"),
+    ("Deserializer", "serde"),
+    ("Serialize", "serde"),
+    ("Serializer", "serde"),
+    ("SeqAccess", "serde::de"),
+    ("Visitor", "serde::de"),
+    ("BytePrefix", "serde_mcproto::types::array"),
+    ("ExternalLength", "serde_mcproto::types::array"),
+    ("IntPrefix", "serde_mcproto::types::array"),
+    ("ShortPrefix", "serde_mcproto::types::array"),
+    ("UBytePrefix", "serde_mcproto::types::array"),
+    ("VarIntPrefix", "serde_mcproto::types::array"),
+    ("EntityMetadata", "serde_mcproto::types::entity_metadata"),
+    ("Slot", "serde_mcproto::types::slot"),
+    ("UuidBinary", "serde_mcproto::types::uuid"),
+    ("VarInt", "serde_mcproto::types::var"),
+    ("VarLong", "serde_mcproto::types::var"),
+];
+
+/// Source lines with the current indentation
+struct Writer {
+    source: String,
+    indent: usize,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer {
+            source: String::new(),
+            indent: 0,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.source.push_str("    ");
+            }
+            self.source.push_str(line);
+        }
+        self.source.push('\n');
+    }
+
+    /// Write a line ending with a brace and indent the following ones
+    fn open(&mut self, line: &str) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    fn close(&mut self, line: &str) {
+        self.indent -= 1;
+        self.line(line);
+    }
+}
+
+/// Source of a version module, `source` names the protocol.json it was generated from
+pub fn module(protocol: &Protocol, protocol_version: i64, minecraft_version: &str, source: &str) -> String {
+    let mut w = Writer::new();
+    w.line(&format!("// Generated from {} by build/main.rs, edit the JSON rather than this file.", source));
+    w.line("");
+    w.line("use serde_mcproto::types::var::VarInt;");
+    w.line("");
+    w.line(&format!("pub const PROTOCOL_VERSION: VarInt = VarInt({});", protocol_version));
+    w.line("/// Release the packets were described for, the other releases of the protocol version share them");
+    w.line(&format!("pub const MINECRAFT_VERSION: &str = {:?};", minecraft_version));
+    w.line("");
+
+    for state in &protocol.states {
+        for direction in &state.directions {
+            w.line(&format!(
+                "pub use self::{}::{}::Packet as {};",
+                state.name,
+                snake_case(&direction.name),
+                alias(&state.name, &direction.name)
+            ));
+        }
+    }
+    w.line("");
+    w.open("impl_packets! {");
+    for state in &protocol.states {
+        let variant = if state.name == "handshaking" { "Handshake".to_string() } else { camel_case(&state.name) };
+        for direction in &state.directions {
+            w.line(&format!(
+                "{}: {} {},",
+                alias(&state.name, &direction.name),
+                variant,
+                camel_case(&direction.name)
+            ));
+        }
+    }
+    w.close("}");
+
+    for state in &protocol.states {
+        if state.directions.is_empty() {
+            continue;
+        }
+        w.line("");
+        w.line(&format!("/// Packets of the {} state", state.name));
+        w.open(&format!("pub mod {} {{", state.name));
+        for (i, direction) in state.directions.iter().enumerate() {
+            if i > 0 {
+                w.line("");
+            }
+            direction_module(&mut w, &state.name, direction);
+        }
+        w.close("}");
+    }
+    w.source
+}
+
+/// Name of the packet enum of a state and direction outside of its module, as in the
+/// hand-written versions
+fn alias(state: &str, direction: &str) -> String {
+    if state == "handshaking" {
+        return "Handshake".to_string();
+    }
+    format!("{}{}", camel_case(state), camel_case(direction))
+}
+
+fn direction_module(w: &mut Writer, state: &str, direction: &Direction) {
+    let mut body = Writer::new();
+    body.indent = w.indent + 1;
+    let to = if direction.name == "toClient" { "client" } else { "server" };
+    body.line(&format!("/// Packets of the {} state sent to the {}", state, to));
+    body.line("#[derive(Debug, Clone, Serialize, Deserialize)]");
+    body.open("pub enum Packet {");
+    for packet in &direction.packets {
+        match &packet.name {
+            Some(name) => {
+                body.line(&format!("/// `{}`, id 0x{:02x}", name, packet.id));
+                let variant = camel_case(name);
+                body.line(&format!("{}({}),", variant, variant));
+            }
+            None => {
+                body.line(&format!("/// No packet has id 0x{:02x}", packet.id));
+                body.line(&format!("Unused0x{:02x},", packet.id));
+            }
+        }
+    }
+    body.close("}");
+    for s in &direction.structs {
+        body.line("");
+        if s.is_plain() {
+            plain_struct(&mut body, s, &direction.structs);
+        } else {
+            custom_struct(&mut body, s, &direction.structs);
+        }
+    }
+
+    w.line(&format!("/// Packets sent to the {}", to));
+    w.open(&format!("pub mod {} {{", snake_case(&direction.name)));
+    for import in imports(&body.source, &direction.structs) {
+        w.line(&import);
+    }
+    w.line("");
+    w.source.push_str(&body.source);
+    w.close("}");
+}
+
+/// `use` lines of the known names appearing in a module
+fn imports(source: &str, structs: &[Struct]) -> Vec<String> {
+    let words: Vec<&str> = source
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .collect();
+    let mut paths: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, path) in IMPORTS {
+        if words.contains(name) && !structs.iter().any(|s| s.name == *name) {
+            paths.entry(path).or_default().push(name);
+        }
+    }
+    paths
+        .into_iter()
+        .map(|(path, mut names)| {
+            names.sort();
+            match names.as_slice() {
+                [name] => format!("use {}::{};", path, name),
+                _ => format!("use {}::{{{}}};", path, names.join(", ")),
+            }
+        })
+        .collect()
+}
+
+/// Rust type of a field, `structs` are the ones of the module which hide the imports with
+/// the same name
+fn rust_type(ty: &Type, structs: &[Struct]) -> String {
+    match ty {
+        Type::Native(ty) | Type::Mapper { ty, .. } | Type::Count { ty, .. } => {
+            let mut ty = ty.to_string();
+            for (name, path) in IMPORTS {
+                if structs.iter().any(|s| s.name == *name) {
+                    ty = ty.replace(name, &format!("{}::{}", path, name));
+                }
+            }
+            ty
+        }
+        Type::Prefixed(prefix, element) => format!("{}<{}>", prefix, rust_type(element, structs)),
+        Type::Struct(name) => name.clone(),
+        Type::Counted { element, .. } => format!("Vec<{}>", rust_type(element, structs)),
+        Type::Optional(ty) => format!("Option<{}>", rust_type(ty, structs)),
+        Type::Switch { ty, .. } => format!("Option<{}>", rust_type(ty, structs)),
+    }
+}
+
+fn struct_fields(w: &mut Writer, s: &Struct, structs: &[Struct]) {
+    w.open(&format!("pub struct {} {{", s.name));
+    for field in &s.fields {
+        match &field.ty {
+            Type::Count { .. } => {}
+            Type::Switch {
+                compare,
+                patterns,
+                matched,
+                ..
+            } => {
+                let condition = if *matched { "is" } else { "is not" };
+                w.line(&format!(
+                    "/// Present when `{}` {} {}",
+                    snake_case(compare),
+                    condition,
+                    patterns.join(" or ")
+                ));
+                w.line(&format!("pub {}: {},", snake_case(&field.name), rust_type(&field.ty, structs)));
+            }
+            ty => w.line(&format!("pub {}: {},", snake_case(&field.name), rust_type(ty, structs))),
+        }
+    }
+    w.close("}");
+}
+
+fn plain_struct(w: &mut Writer, s: &Struct, structs: &[Struct]) {
+    w.line(&format!("/// {}", s.doc));
+    w.line("#[derive(Debug, Clone, Serialize, Deserialize)]");
+    struct_fields(w, s, structs);
+}
+
+/// Elements of the tuple a struct is read as, at most
+fn tuple_len(fields: &[Field]) -> usize {
+    fields
+        .iter()
+        .map(|field| match field.ty {
+            Type::Optional(_) => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Struct whose fields depend on each other, its impls read and write it as a tuple
+fn custom_struct(w: &mut Writer, s: &Struct, structs: &[Struct]) {
+    w.line(&format!("/// {}", s.doc));
+    w.line("#[derive(Debug, Clone)]");
+    struct_fields(w, s, structs);
+    w.line("");
+    serialize(w, s);
+    w.line("");
+    deserialize(w, s, structs);
+}
+
+fn serialize(w: &mut Writer, s: &Struct) {
+    w.open(&format!("impl Serialize for {} {{", s.name));
+    w.line("fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>");
+    w.line("where");
+    w.line("    S: Serializer,");
+    w.open("{");
+    if s.fields.iter().any(|field| matches!(field.ty, Type::Count { .. })) {
+        w.line("use serde::ser::{Error, SerializeTuple};");
+        w.line("use std::convert::TryFrom;");
+    } else {
+        w.line("use serde::ser::SerializeTuple;");
+    }
+    w.line(&format!("let mut tuple = serializer.serialize_tuple({})?;", tuple_len(&s.fields)));
+    for field in &s.fields {
+        let name = snake_case(&field.name);
+        match &field.ty {
+            Type::Count { ty, count_for } => {
+                // a count too small for its field would corrupt the frame
+                let counted = snake_case(count_for);
+                let int = if *ty == "VarInt" { "i32" } else { ty };
+                w.open(&format!("let {} = {}::try_from(self.{}.len()).map_err(|_| {{", name, int, counted));
+                w.line(&format!("S::Error::custom(\"{} has too many elements for its {} count\")", counted, ty));
+                w.close("})?;");
+                if *ty == "VarInt" {
+                    w.line(&format!("tuple.serialize_element(&VarInt({}))?;", name));
+                } else {
+                    w.line(&format!("tuple.serialize_element(&{})?;", name));
+                }
+            }
+            Type::Counted { .. } => {
+                w.open(&format!("for element in &self.{} {{", name));
+                w.line("tuple.serialize_element(element)?;");
+                w.close("}");
+            }
+            Type::Optional(_) => {
+                w.line(&format!("tuple.serialize_element(&self.{}.is_some())?;", name));
+                w.open(&format!("if let Some({}) = &self.{} {{", name, name));
+                w.line(&format!("tuple.serialize_element({})?;", name));
+                w.close("}");
+            }
+            Type::Switch { .. } => {
+                w.open(&format!("if let Some({}) = &self.{} {{", name, name));
+                w.line(&format!("tuple.serialize_element({})?;", name));
+                w.close("}");
+            }
+            _ => w.line(&format!("tuple.serialize_element(&self.{})?;", name)),
+        }
+    }
+    w.line("tuple.end()");
+    w.close("}");
+    w.close("}");
+}
+
+fn deserialize(w: &mut Writer, s: &Struct, structs: &[Struct]) {
+    let visitor = format!("{}Visitor", s.name);
+    w.open(&format!("impl<'de> Deserialize<'de> for {} {{", s.name));
+    w.line("fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>");
+    w.line("where");
+    w.line("    D: Deserializer<'de>,");
+    w.open("{");
+    w.line(&format!("struct {};", visitor));
+    w.open(&format!("impl<'de> Visitor<'de> for {} {{", visitor));
+    w.line(&format!("type Value = {};", s.name));
+    w.line("");
+    w.open("fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {");
+    w.line(&format!("formatter.write_str({:?})", s.doc.replace('`', "")));
+    w.close("}");
+    w.line("");
+    w.line("fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>");
+    w.line("where");
+    w.line("    A: SeqAccess<'de>,");
+    w.open("{");
+
+    let mut index = 0;
+    for field in &s.fields {
+        let name = snake_case(&field.name);
+        let ty = rust_type(&field.ty, structs);
+        match &field.ty {
+            Type::Counted { count, .. } => {
+                let len = match s.fields.iter().find(|other| other.name == *count) {
+                    Some(Field {
+                        ty: Type::Count { ty: "VarInt", .. },
+                        ..
+                    }) => format!("{}.0 as usize", snake_case(count)),
+                    _ => format!("{} as usize", snake_case(count)),
+                };
+                w.line(&format!("let mut {}: {} = Vec::new();", name, ty));
+                w.line(&format!("seq.next_element_seed(ExternalLength({}, &mut {}))?", len, name));
+                w.line(&format!("    .ok_or_else(|| serde::de::Error::invalid_length({}, &self))?;", index));
+                index += 1;
+            }
+            Type::Optional(_) => {
+                let present = next(&mut index);
+                let value = next(&mut index);
+                w.line(&format!("let present: bool = {};", present));
+                w.line(&format!("let {}: {} = if present {{ Some({}) }} else {{ None }};", name, ty, value));
+            }
+            Type::Switch {
+                compare,
+                patterns,
+                matched,
+                ..
+            } => {
+                let compared = match s.fields.iter().find(|other| other.name == *compare).map(|other| &other.ty) {
+                    Some(Type::Native(ty)) | Some(Type::Mapper { ty, .. }) if ty.starts_with("Var") => {
+                        format!("{}.0", snake_case(compare))
+                    }
+                    _ => snake_case(compare),
+                };
+                let (on_match, otherwise) = if *matched {
+                    (format!("Some({})", next(&mut index)), "None".to_string())
+                } else {
+                    ("None".to_string(), format!("Some({})", next(&mut index)))
+                };
+                w.open(&format!("let {}: {} = match {} {{", name, ty, compared));
+                w.line(&format!("{} => {},", patterns.join(" | "), on_match));
+                w.line(&format!("_ => {},", otherwise));
+                w.close("};");
+            }
+            _ => w.line(&format!("let {}: {} = {};", name, ty, next(&mut index))),
+        }
+    }
+    let fields: Vec<String> = s
+        .fields
+        .iter()
+        .filter(|field| !matches!(field.ty, Type::Count { .. }))
+        .map(|field| snake_case(&field.name))
+        .collect();
+    w.line(&format!("Ok({} {{ {} }})", s.name, fields.join(", ")));
+    w.close("}");
+    w.close("}");
+    w.line(&format!("deserializer.deserialize_tuple({}, {})", tuple_len(&s.fields), visitor));
+    w.close("}");
+    w.close("}");
+}
+
+/// Read the next element of the tuple, failing when it is missing
+fn next(index: &mut usize) -> String {
+    let read = format!(
+        "seq.next_element()?.ok_or_else(|| serde::de::Error::invalid_length({}, &self))?",
+        index
+    );
+    *index += 1;
+    read
+}
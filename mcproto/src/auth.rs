@@ -0,0 +1,623 @@
+//! Online mode authentication against the Mojang session server
+//!
+//! After `EncryptionRequest` the client picks a shared secret, encrypts it and the verify
+//! token with the server public key and tells the session server it joins using the
+//! server hash. The server then asks the session server whether the player has joined
+//! with the same hash.
+//!
+//! HTTP requests go through [`HttpClient`], the `ureq` feature provides [`UreqClient`].
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use rand::RngCore;
+use rsa::{BigUint, PaddingScheme, PublicKey, PublicKeyParts, RSAPrivateKey, RSAPublicKey};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::types::uuid::UuidSimple;
+
+pub const SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+/// Size of the key vanilla servers generate
+pub const SERVER_KEY_BITS: usize = 1024;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Request could not be made
+    Http(String),
+    /// Session server answered with an unexpected status
+    Status { status: u16, body: String },
+    Rsa(rsa::errors::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(msg) => write!(f, "HTTP request failed: {}", msg),
+            Error::Status { status, body } => write!(f, "Session server answered {}: {}", status, body),
+            Error::Rsa(e) => write!(f, "RSA: {}", e),
+            Error::Json(e) => write!(f, "Invalid session server response: {}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Rsa(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rsa::errors::Error> for Error {
+    fn from(e: rsa::errors::Error) -> Error {
+        Error::Rsa(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+/// Hexadecimal form of a digest read as a signed two's complement number, like Java's
+/// `new BigInteger(digest).toString(16)`
+pub fn java_hex_digest(digest: &[u8]) -> String {
+    let negative = digest.first().map(|b| b & 0x80 != 0).unwrap_or(false);
+    let mut magnitude = digest.to_vec();
+    if negative {
+        // two's complement: invert and add one
+        let mut carry = true;
+        for byte in magnitude.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                let (sum, overflow) = byte.overflowing_add(1);
+                *byte = sum;
+                carry = overflow;
+            }
+        }
+    }
+    let hex: String = magnitude.iter().map(|b| format!("{:02x}", b)).collect();
+    let hex = hex.trim_start_matches('0');
+    match (negative, hex.is_empty()) {
+        (_, true) => "0".to_owned(),
+        (true, false) => format!("-{}", hex),
+        (false, false) => hex.to_owned(),
+    }
+}
+
+/// Server hash sent to the session server by both sides
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(server_id.as_bytes());
+    hasher.input(shared_secret);
+    hasher.input(public_key);
+    java_hex_digest(&hasher.result())
+}
+
+/// Parse the DER encoded public key of `EncryptionRequest`
+pub fn parse_public_key(der: &[u8]) -> Result<RSAPublicKey> {
+    Ok(RSAPublicKey::from_pkcs8(der)?)
+}
+
+/// Random 16 byte secret used as the AES key and IV
+pub fn generate_shared_secret() -> [u8; 16] {
+    let mut secret = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Encrypt with PKCS#1 v1.5 padding as vanilla does
+pub fn encrypt(public_key: &RSAPublicKey, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(public_key.encrypt(&mut rand::thread_rng(), PaddingScheme::PKCS1v15Encrypt, data)?)
+}
+
+/// Random token the client has to send back encrypted
+pub fn generate_verify_token() -> [u8; 4] {
+    let mut token = [0u8; 4];
+    rand::thread_rng().fill_bytes(&mut token);
+    token
+}
+
+/// Key pair of a server, generated at startup
+#[derive(Debug, Clone)]
+pub struct ServerKey {
+    private_key: RSAPrivateKey,
+    public_key_der: Vec<u8>,
+}
+
+impl ServerKey {
+    /// Generate a 1024 bit key like vanilla does
+    pub fn generate() -> Result<ServerKey> {
+        ServerKey::generate_with_bits(SERVER_KEY_BITS)
+    }
+
+    pub fn generate_with_bits(bits: usize) -> Result<ServerKey> {
+        let private_key = RSAPrivateKey::new(&mut rand::thread_rng(), bits)?;
+        Ok(ServerKey::from_private_key(private_key))
+    }
+
+    pub fn from_private_key(private_key: RSAPrivateKey) -> ServerKey {
+        let public_key_der = public_key_der(private_key.n(), private_key.e());
+        ServerKey {
+            private_key,
+            public_key_der,
+        }
+    }
+
+    /// ASN.1 DER `SubjectPublicKeyInfo` sent in `EncryptionRequest`
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    /// Decrypt the shared secret or verify token of `EncryptionResponse`
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.private_key.decrypt(PaddingScheme::PKCS1v15Encrypt, data)?)
+    }
+}
+
+/// Encode an RSA public key as X.509 `SubjectPublicKeyInfo`
+fn public_key_der(n: &BigUint, e: &BigUint) -> Vec<u8> {
+    // rsaEncryption, 1.2.840.113549.1.1.1, with NULL parameters
+    const ALGORITHM: &[u8] = &[
+        0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+    ];
+    let mut rsa_key = der_integer(&n.to_bytes_be());
+    rsa_key.extend(der_integer(&e.to_bytes_be()));
+    let rsa_key = der(0x30, &rsa_key);
+
+    let mut bit_string = vec![0];
+    bit_string.extend(rsa_key);
+
+    let mut info = ALGORITHM.to_vec();
+    info.extend(der(0x03, &bit_string));
+    der(0x30, &info)
+}
+
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    // a set high bit would make it negative
+    if bytes.first().map(|b| b & 0x80 != 0).unwrap_or(true) {
+        let mut padded = vec![0];
+        padded.extend_from_slice(bytes);
+        der(0x02, &padded)
+    } else {
+        der(0x02, bytes)
+    }
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        bytes.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .cloned()
+            .skip_while(|b| *b == 0)
+            .collect();
+        bytes.push(0x80 | len_bytes.len() as u8);
+        bytes.extend(len_bytes);
+    }
+    bytes.extend_from_slice(content);
+    bytes
+}
+
+/// Everything a client needs to answer an `EncryptionRequest`
+#[derive(Debug, Clone)]
+pub struct EncryptionReply {
+    /// Key of the encrypted connection
+    pub shared_secret: [u8; 16],
+    pub encrypted_shared_secret: Vec<u8>,
+    pub encrypted_verify_token: Vec<u8>,
+    /// Hash to send to [`SessionServer::join`]
+    pub server_hash: String,
+}
+
+impl EncryptionReply {
+    pub fn new(server_id: &str, public_key: &[u8], verify_token: &[u8]) -> Result<EncryptionReply> {
+        EncryptionReply::with_secret(server_id, public_key, verify_token, generate_shared_secret())
+    }
+
+    pub fn with_secret(
+        server_id: &str,
+        public_key: &[u8],
+        verify_token: &[u8],
+        shared_secret: [u8; 16],
+    ) -> Result<EncryptionReply> {
+        let key = parse_public_key(public_key)?;
+        Ok(EncryptionReply {
+            shared_secret,
+            encrypted_shared_secret: encrypt(&key, &shared_secret)?,
+            encrypted_verify_token: encrypt(&key, verify_token)?,
+            server_hash: server_hash(server_id, &shared_secret, public_key),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Blocking HTTP backend of [`SessionServer`]
+pub trait HttpClient {
+    fn get(&self, url: &str) -> Result<HttpResponse>;
+
+    fn post_json(&self, url: &str, body: &str) -> Result<HttpResponse>;
+}
+
+impl<T: HttpClient + ?Sized> HttpClient for &T {
+    fn get(&self, url: &str) -> Result<HttpResponse> {
+        (**self).get(url)
+    }
+
+    fn post_json(&self, url: &str, body: &str) -> Result<HttpResponse> {
+        (**self).post_json(url, body)
+    }
+}
+
+impl<T: HttpClient + ?Sized> HttpClient for Box<T> {
+    fn get(&self, url: &str) -> Result<HttpResponse> {
+        (**self).get(url)
+    }
+
+    fn post_json(&self, url: &str, body: &str) -> Result<HttpResponse> {
+        (**self).post_json(url, body)
+    }
+}
+
+#[cfg(feature = "ureq")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UreqClient;
+
+#[cfg(feature = "ureq")]
+impl UreqClient {
+    fn response(response: ureq::Response) -> Result<HttpResponse> {
+        if let Some(e) = response.synthetic_error() {
+            return Err(Error::Http(e.to_string()));
+        }
+        let status = response.status();
+        let body = response
+            .into_string()
+            .map_err(|e| Error::Http(e.to_string()))?;
+        Ok(HttpResponse { status, body })
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl HttpClient for UreqClient {
+    fn get(&self, url: &str) -> Result<HttpResponse> {
+        UreqClient::response(ureq::get(url).call())
+    }
+
+    fn post_json(&self, url: &str, body: &str) -> Result<HttpResponse> {
+        UreqClient::response(
+            ureq::post(url)
+                .set("Content-Type", "application/json")
+                .send_string(body),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// Profile of a player who joined, as returned by `hasJoined`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameProfile {
+    pub id: UuidSimple,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JoinRequest<'a> {
+    access_token: &'a str,
+    selected_profile: UuidSimple,
+    server_id: &'a str,
+}
+
+/// Session server endpoints used by the login
+#[derive(Debug, Clone)]
+pub struct SessionServer<H> {
+    http: H,
+    base_url: String,
+}
+
+impl<H: HttpClient> SessionServer<H> {
+    pub fn new(http: H) -> SessionServer<H> {
+        SessionServer::with_base_url(http, SESSION_SERVER)
+    }
+
+    pub fn with_base_url<S: Into<String>>(http: H, base_url: S) -> SessionServer<H> {
+        SessionServer {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Erase the HTTP backend type
+    pub fn boxed(self) -> SessionServer<Box<dyn HttpClient + Send + Sync>>
+    where
+        H: Send + Sync + 'static,
+    {
+        SessionServer {
+            http: Box::new(self.http),
+            base_url: self.base_url,
+        }
+    }
+
+    /// Client side, tell the session server we are joining the server of `server_hash`
+    pub fn join(&self, access_token: &str, profile: UuidSimple, server_hash: &str) -> Result<()> {
+        let body = serde_json::to_string(&JoinRequest {
+            access_token,
+            selected_profile: profile,
+            server_id: server_hash,
+        })?;
+        let url = format!("{}/session/minecraft/join", self.base_url);
+        let response = self.http.post_json(&url, &body)?;
+        match response.status {
+            200 | 204 => Ok(()),
+            status => Err(Error::Status {
+                status,
+                body: response.body,
+            }),
+        }
+    }
+
+    /// Server side, `None` if the player did not join with this hash
+    pub fn has_joined(
+        &self,
+        username: &str,
+        server_hash: &str,
+        ip: Option<&str>,
+    ) -> Result<Option<GameProfile>> {
+        let mut url = format!(
+            "{}/session/minecraft/hasJoined?username={}&serverId={}",
+            self.base_url,
+            percent_encode(username),
+            percent_encode(server_hash)
+        );
+        if let Some(ip) = ip {
+            url.push_str("&ip=");
+            url.push_str(&percent_encode(ip));
+        }
+        let response = self.http.get(&url)?;
+        match response.status {
+            200 => Ok(Some(serde_json::from_str(&response.body)?)),
+            204 => Ok(None),
+            status => Err(Error::Status {
+                status,
+                body: response.body,
+            }),
+        }
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn sha1_hex(s: &str) -> String {
+        java_hex_digest(&Sha1::digest(s.as_bytes()))
+    }
+
+    #[test]
+    fn java_hex() {
+        assert_eq!(sha1_hex("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(sha1_hex("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(sha1_hex("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+        assert_eq!(java_hex_digest(&[0, 0]), "0");
+        assert_eq!(java_hex_digest(&[0xff, 0xff]), "-1");
+    }
+
+    #[test]
+    fn server_key() {
+        let key = ServerKey::generate_with_bits(512).unwrap();
+        let der = key.public_key_der();
+        assert_eq!(&der[..2], &[0x30, 0x5c]);
+        let public_key = parse_public_key(der).unwrap();
+        assert_eq!(public_key.n(), key.private_key.n());
+
+        let reply = EncryptionReply::new("", der, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(key.decrypt(&reply.encrypted_verify_token).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(
+            key.decrypt(&reply.encrypted_shared_secret).unwrap(),
+            reply.shared_secret.to_vec()
+        );
+        assert_eq!(reply.server_hash, server_hash("", &reply.shared_secret, der));
+    }
+
+    /// Answers every request with the same response and records what was asked
+    struct MockHttp {
+        response: HttpResponse,
+        requests: RefCell<Vec<(String, Option<String>)>>,
+    }
+
+    impl MockHttp {
+        fn new(status: u16, body: &str) -> MockHttp {
+            MockHttp {
+                response: HttpResponse {
+                    status,
+                    body: body.to_owned(),
+                },
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl HttpClient for MockHttp {
+        fn get(&self, url: &str) -> Result<HttpResponse> {
+            self.requests.borrow_mut().push((url.to_owned(), None));
+            Ok(self.response.clone())
+        }
+
+        fn post_json(&self, url: &str, body: &str) -> Result<HttpResponse> {
+            self.requests
+                .borrow_mut()
+                .push((url.to_owned(), Some(body.to_owned())));
+            Ok(self.response.clone())
+        }
+    }
+
+    #[test]
+    fn join() {
+        let http = MockHttp::new(204, "");
+        let session = SessionServer::with_base_url(&http, "http://localhost/");
+        let profile: UuidSimple = "069a79f4-44e9-4726-a5be-fca90e38aaf5".parse().unwrap();
+        session.join("token", profile, "-7c9d").unwrap();
+        assert_eq!(
+            http.requests.borrow()[0],
+            (
+                "http://localhost/session/minecraft/join".to_owned(),
+                Some(
+                    r#"{"accessToken":"token","selectedProfile":"069a79f444e94726a5befca90e38aaf5","serverId":"-7c9d"}"#
+                        .to_owned()
+                )
+            )
+        );
+
+        let http = MockHttp::new(403, "{}");
+        let session = SessionServer::with_base_url(&http, "http://localhost");
+        assert!(session.join("token", profile, "-7c9d").is_err());
+    }
+
+    #[test]
+    fn has_joined() {
+        let http = MockHttp::new(
+            200,
+            r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch","properties":[{"name":"textures","value":"e30=","signature":"c2ln"}]}"#,
+        );
+        let session = SessionServer::with_base_url(&http, "http://localhost");
+        let profile = session
+            .has_joined("Notch", "-7c9d", Some("::1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(profile.id.to_simple_string(), "069a79f444e94726a5befca90e38aaf5");
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("c2ln"));
+        assert_eq!(
+            http.requests.borrow()[0].0,
+            "http://localhost/session/minecraft/hasJoined?username=Notch&serverId=-7c9d&ip=%3A%3A1"
+        );
+
+        let http = MockHttp::new(204, "");
+        let session = SessionServer::with_base_url(&http, "http://localhost");
+        assert_eq!(session.has_joined("Notch", "-7c9d", None).unwrap(), None);
+    }
+
+    /// Serve one canned response per connection on a loopback port, returning the base URL
+    /// and the requests, their head and body
+    #[cfg(feature = "ureq")]
+    fn serve(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    if reader.read_line(&mut head).unwrap() == 0 {
+                        break;
+                    }
+                }
+                let len = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |len| len.parse().unwrap());
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                requests.push((head, String::from_utf8(body).unwrap()));
+                let (status, body) = response.split_once('\n').unwrap_or((response, ""));
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[cfg(feature = "ureq")]
+    #[test]
+    fn ureq_client() {
+        let (url, server) = serve(vec![
+            "200 OK\n{\"id\":\"069a79f444e94726a5befca90e38aaf5\",\"name\":\"Notch\"}",
+            "204 No Content",
+            "403 Forbidden\n{\"error\":\"ForbiddenOperationException\"}",
+            "200 OK\nnot json",
+            "204 No Content",
+        ]);
+        let session = SessionServer::with_base_url(UreqClient, url);
+        let profile = session.has_joined("Notch", "-7c9d", Some("::1")).unwrap().unwrap();
+        assert_eq!(profile.name, "Notch");
+        assert!(profile.properties.is_empty());
+        assert_eq!(session.has_joined("Notch", "-7c9d", None).unwrap(), None);
+        match session.has_joined("Notch", "-7c9d", None) {
+            Err(Error::Status { status: 403, body }) => assert!(body.contains("Forbidden")),
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(session.has_joined("Notch", "-7c9d", None).is_err());
+        let profile: UuidSimple = "069a79f4-44e9-4726-a5be-fca90e38aaf5".parse().unwrap();
+        session.join("token", profile, "-7c9d").unwrap();
+
+        let requests = server.join().unwrap();
+        let request_line = |i: usize| requests[i].0.lines().next().unwrap().to_owned();
+        assert_eq!(
+            request_line(0),
+            "GET /session/minecraft/hasJoined?username=Notch&serverId=-7c9d&ip=%3A%3A1 HTTP/1.1"
+        );
+        assert_eq!(
+            request_line(1),
+            "GET /session/minecraft/hasJoined?username=Notch&serverId=-7c9d HTTP/1.1"
+        );
+        assert_eq!(request_line(4), "POST /session/minecraft/join HTTP/1.1");
+        assert!(requests[4].0.contains("Content-Type: application/json\r\n"));
+        assert_eq!(
+            requests[4].1,
+            r#"{"accessToken":"token","selectedProfile":"069a79f444e94726a5befca90e38aaf5","serverId":"-7c9d"}"#
+        );
+
+        // nothing listens on the port anymore
+        match session.has_joined("Notch", "-7c9d", None) {
+            Err(Error::Http(_)) => {}
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
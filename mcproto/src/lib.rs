@@ -1,9 +1,25 @@
 //mod chat;
 extern crate serde_mcproto;
 
+pub mod auth;
+#[macro_use]
+pub mod packet;
+pub mod status;
+pub mod v1_7;
 pub mod v1_7_10;
 // pub mod v1_8;
 
+/// Call `$callback!` with the name of every version module, the hand written ones first.
+///
+/// Version modules are registered here once declared above, tools covering every version,
+/// such as the dissector, follow this list.
+#[macro_export]
+macro_rules! for_each_version {
+    ($callback:ident) => {
+        $callback!(v1_7_10, v1_7)
+    };
+}
+
 pub use serde_mcproto::de::deserialize;
 pub use serde_mcproto::ser::serialize;
 pub use serde_mcproto::types;
@@ -0,0 +1,401 @@
+//! Protocol states, packet directions and the `Packet` trait of the packet enums
+
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde::ser::{self, Impossible};
+use serde::{Serialize, Serializer};
+
+/// State of a connection, which decides the packets that can be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    Handshake,
+    Status,
+    Login,
+    Play,
+}
+
+impl State {
+    /// Byte used by captures
+    pub fn id(self) -> u8 {
+        match self {
+            State::Handshake => 0,
+            State::Status => 1,
+            State::Login => 2,
+            State::Play => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<State> {
+        match id {
+            0 => Some(State::Handshake),
+            1 => Some(State::Status),
+            2 => Some(State::Login),
+            3 => Some(State::Play),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            State::Handshake => "handshake",
+            State::Status => "status",
+            State::Login => "login",
+            State::Play => "play",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Which side sends a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+impl Direction {
+    /// Byte used by captures
+    pub fn id(self) -> u8 {
+        match self {
+            Direction::ToServer => 0,
+            Direction::ToClient => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Direction> {
+        match id {
+            0 => Some(Direction::ToServer),
+            1 => Some(Direction::ToClient),
+            _ => None,
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::ToServer => Direction::ToClient,
+            Direction::ToClient => Direction::ToServer,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Direction::ToServer => "to server",
+            Direction::ToClient => "to client",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Packet enum of a state and direction, the variant index is the packet id.
+///
+/// Ids and names are the variant indexes and names serde sees, so they follow the enum without
+/// a table to keep up to date.
+pub trait Packet: Serialize + DeserializeOwned {
+    const STATE: State;
+    const DIRECTION: Direction;
+    const PROTOCOL_VERSION: i32;
+
+    fn id(&self) -> i32 {
+        variant(self).0 as i32
+    }
+
+    fn name(&self) -> &'static str {
+        variant(self).1
+    }
+
+    fn state(&self) -> State {
+        Self::STATE
+    }
+
+    fn direction(&self) -> Direction {
+        Self::DIRECTION
+    }
+
+    fn protocol_version(&self) -> i32 {
+        Self::PROTOCOL_VERSION
+    }
+
+    /// Names of all the packets of the state and direction, indexed by id
+    fn packets() -> &'static [&'static str] {
+        let mut reflect = Reflect;
+        match Self::deserialize(&mut reflect) {
+            Err(Found::Variants(names)) => names,
+            _ => panic!("{} {} packets are not an enum", Self::STATE, Self::DIRECTION),
+        }
+    }
+
+    /// Name of a packet id, `None` if no packet has it
+    fn name_of(id: i32) -> Option<&'static str> {
+        if id < 0 {
+            return None;
+        }
+        Self::packets().get(id as usize).copied()
+    }
+}
+
+/// Implement `Packet` for the packet enums of a version module, which has a `PROTOCOL_VERSION`,
+/// and add a `packets` function giving the packet names of a state and direction
+macro_rules! impl_packets {
+    ($($packet:ident: $state:ident $direction:ident),* $(,)?) => {
+        $(
+            impl $crate::packet::Packet for $packet {
+                const STATE: $crate::packet::State = $crate::packet::State::$state;
+                const DIRECTION: $crate::packet::Direction = $crate::packet::Direction::$direction;
+                const PROTOCOL_VERSION: i32 = PROTOCOL_VERSION.0;
+            }
+        )*
+
+        /// Names of the packets of a state and direction indexed by id, empty if it has none
+        pub fn packets(
+            state: $crate::packet::State,
+            direction: $crate::packet::Direction,
+        ) -> &'static [&'static str] {
+            use $crate::packet::Packet;
+            $(
+                if state == $crate::packet::State::$state && direction == $crate::packet::Direction::$direction {
+                    return $packet::packets();
+                }
+            )*
+            &[]
+        }
+    };
+}
+
+fn variant<P: Packet>(packet: &P) -> (u32, &'static str) {
+    let mut reflect = Reflect;
+    match packet.serialize(&mut reflect) {
+        Err(Found::Variant(index, name)) => (index, name),
+        _ => panic!("{} {} packets are not an enum", P::STATE, P::DIRECTION),
+    }
+}
+
+/// Serializer and deserializer stopping at the enum of a packet, what they found is returned as
+/// the error
+struct Reflect;
+
+#[derive(Debug)]
+enum Found {
+    Variant(u32, &'static str),
+    Variants(&'static [&'static str]),
+    Other,
+}
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Found::Variant(index, name) => write!(f, "variant {} {}", index, name),
+            Found::Variants(names) => write!(f, "{} variants", names.len()),
+            Found::Other => f.write_str("not an enum"),
+        }
+    }
+}
+
+impl std::error::Error for Found {}
+
+impl ser::Error for Found {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Found::Other
+    }
+}
+
+impl de::Error for Found {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Found::Other
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Reflect {
+    type Error = Found;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Found> {
+        Err(Found::Other)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Found> {
+        Err(Found::Variants(variants))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl Serializer for &mut Reflect {
+    type Ok = ();
+    type Error = Found;
+    type SerializeSeq = Impossible<(), Found>;
+    type SerializeTuple = Impossible<(), Found>;
+    type SerializeTupleStruct = Impossible<(), Found>;
+    type SerializeTupleVariant = Impossible<(), Found>;
+    type SerializeMap = Impossible<(), Found>;
+    type SerializeStruct = Impossible<(), Found>;
+    type SerializeStructVariant = Impossible<(), Found>;
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, variant: &'static str) -> Result<(), Found> {
+        Err(Found::Variant(index, variant))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Found> {
+        Err(Found::Variant(index, variant))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Found> {
+        Err(Found::Variant(index, variant))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Found> {
+        Err(Found::Variant(index, variant))
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_none(self) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_unit(self) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, _value: &T) -> Result<(), Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Found> {
+        Err(Found::Other)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Found> {
+        Err(Found::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1_7_10::{self, PlayToClient, StatusToServer};
+    use serde_mcproto::types::chunk::ChunkDataBulk;
+
+    #[test]
+    fn packet_reflection() {
+        let keep_alive = PlayToClient::KeepAlive { keep_alive_id: 1 };
+        assert_eq!((keep_alive.id(), keep_alive.name()), (0x00, "KeepAlive"));
+        assert_eq!(keep_alive.state(), State::Play);
+        assert_eq!(keep_alive.direction(), Direction::ToClient);
+        assert_eq!(keep_alive.protocol_version(), 5);
+
+        let bulk = PlayToClient::ChunkDataBulk(ChunkDataBulk::new(true, Vec::new(), Vec::new()));
+        assert_eq!((bulk.id(), bulk.name()), (0x26, "ChunkDataBulk"));
+        assert_eq!(StatusToServer::Ping { time: 0 }.id(), 0x01);
+
+        assert_eq!(PlayToClient::packets().len(), 65);
+        assert_eq!(PlayToClient::name_of(0x0f), Some("SpawnMob"));
+        assert_eq!(PlayToClient::name_of(65), None);
+        assert_eq!(v1_7_10::packets(State::Login, Direction::ToClient)[2], "LoginSuccess");
+        assert!(v1_7_10::packets(State::Handshake, Direction::ToClient).is_empty());
+        assert_eq!(crate::v1_7::PlayToClient::name_of(0x0f), Some("SpawnEntityLiving"));
+    }
+}
@@ -0,0 +1,82 @@
+//! JSON of `StatusResponse`, what the server list shows
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::types::uuid::Uuid;
+use crate::v1_7_10::PROTOCOL_VERSION;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub version: StatusVersion,
+    pub players: StatusPlayers,
+    /// Chat component, a plain string is accepted too
+    pub description: Value,
+    /// `data:image/png;base64,` URI of a 64x64 PNG
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusVersion {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusPlayers {
+    pub max: i32,
+    pub online: i32,
+    /// Shown when hovering the player count
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample: Vec<StatusPlayer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusPlayer {
+    pub name: String,
+    pub id: Uuid,
+}
+
+impl ServerStatus {
+    /// Status of a 1.7.10 server with a plain text description
+    pub fn new<S: Into<String>>(description: S, online: i32, max: i32) -> ServerStatus {
+        ServerStatus {
+            version: StatusVersion {
+                name: "1.7.10".to_owned(),
+                protocol: PROTOCOL_VERSION.0,
+            },
+            players: StatusPlayers {
+                max,
+                online,
+                sample: Vec::new(),
+            },
+            description: serde_json::json!({ "text": description.into() }),
+            favicon: None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // only strings and integers, it can't fail
+        serde_json::to_string(self).expect("Status is always valid JSON")
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<ServerStatus> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vanilla_status() {
+        let json = r#"{"description":"A Minecraft Server","players":{"max":20,"online":1,"sample":[{"id":"069a79f4-44e9-4726-a5be-fca90e38aaf5","name":"Notch"}]},"version":{"name":"1.7.10","protocol":5}}"#;
+        let status = ServerStatus::from_json(json).unwrap();
+        assert_eq!(status.version.protocol, 5);
+        assert_eq!(status.players.sample[0].name, "Notch");
+        assert_eq!(status.description, Value::from("A Minecraft Server"));
+        assert_eq!(ServerStatus::from_json(&status.to_json()).unwrap(), status);
+    }
+}
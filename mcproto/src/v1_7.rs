@@ -0,0 +1,94 @@
+//! Packets generated from `protocol/1.7/protocol.json` by `build/main.rs`
+//!
+//! The same protocol version as `v1_7_10`, with the packet and field names of minecraft-data.
+//! Each state and direction has a module with one struct per packet, fields which depend on
+//! earlier ones are `Option`s or `Vec`s read by the generated impls.
+
+include!(concat!(env!("OUT_DIR"), "/v1_7.rs"));
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_mcproto::types::chunk::{ChunkDataBulk, ChunkMeta};
+
+    use super::play::to_client::{MapChunkBulk, MapChunkBulkMeta, MultiBlockChange, OpenWindow};
+    use super::PlayToClient;
+    use crate::{MCProtoDeserializer, MCProtoSerializer};
+
+    fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.serialize(&mut MCProtoSerializer::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    fn open_window(bytes: &[u8]) -> OpenWindow {
+        let mut reader = bytes;
+        let packet = PlayToClient::deserialize(&mut MCProtoDeserializer::new(&mut reader)).unwrap();
+        assert!(reader.is_empty());
+        match packet {
+            PlayToClient::OpenWindow(window) => window,
+            other => panic!("expected an open window, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn switch_field() {
+        let mut window = OpenWindow {
+            window_id: 2,
+            inventory_type: 11,
+            window_title: "Horse".to_owned(),
+            slot_count: 2,
+            use_provided_title: true,
+            entity_id: Some(42),
+        };
+        let bytes = encode(&PlayToClient::OpenWindow(window.clone()));
+        assert_eq!(bytes[0], 0x2d);
+        assert_eq!(&bytes[bytes.len() - 5..], &[1, 0, 0, 0, 42]);
+        assert_eq!(open_window(&bytes).entity_id, Some(42));
+
+        window.inventory_type = 0;
+        window.entity_id = None;
+        let bytes = encode(&PlayToClient::OpenWindow(window));
+        assert_eq!(bytes[bytes.len() - 1], 1);
+        assert_eq!(open_window(&bytes).entity_id, None);
+    }
+
+    #[test]
+    fn counted_fields() {
+        let bulk = MapChunkBulk {
+            sky_light_sent: true,
+            compressed_chunk_data: vec![1, 2, 3],
+            meta: vec![MapChunkBulkMeta {
+                x: -1,
+                z: 2,
+                bit_map: 1,
+                add_bit_map: 0,
+            }],
+        };
+        let bytes = encode(&bulk);
+        let decoded = ChunkDataBulk::deserialize(&mut MCProtoDeserializer::new(bytes.as_slice())).unwrap();
+        assert!(decoded.sky_light_sent());
+        assert_eq!(decoded.compressed_chunk_data(), &[1, 2, 3]);
+        assert_eq!(decoded.meta(), &[ChunkMeta { x: -1, z: 2, mask: 1, add_mask: 0 }]);
+        assert_eq!(encode(&decoded), bytes);
+
+        let decoded = MapChunkBulk::deserialize(&mut MCProtoDeserializer::new(bytes.as_slice())).unwrap();
+        assert_eq!(decoded.compressed_chunk_data, bulk.compressed_chunk_data);
+        assert_eq!(decoded.meta[0].x, -1);
+    }
+
+    #[test]
+    fn count_too_small() {
+        let change = MultiBlockChange {
+            chunk_x: 0,
+            chunk_z: 0,
+            data_length: 0,
+            records: vec![0; i16::MAX as usize + 1],
+        };
+        let error = change
+            .serialize(&mut MCProtoSerializer::new(Vec::new()))
+            .unwrap_err();
+        assert!(error.to_string().contains("records has too many elements for its i16 count"));
+        assert_eq!(encode(&MultiBlockChange { records: vec![7; 2], ..change })[8..10], [0, 2]);
+    }
+}
@@ -0,0 +1,616 @@
+//! Chunk column format of `ChunkData` and `ChunkDataBulk`
+//!
+//! A column is up to 16 sections of 16x16x16 blocks. The inflated data holds,
+//! for every section present in `bit_map` and in ascending order: all block id
+//! arrays, then all metadata, block light and (if sent) sky light nibble arrays,
+//! then the add arrays of sections present in `add_bit_map`, and finally the
+//! biome array when the column is sent ground up.
+
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde_mcproto::types::array::IntPrefix;
+use serde_mcproto::types::chunk::{data_len, ChunkDataBulk, ChunkMeta};
+
+use crate::error::{Error, Result};
+
+use super::PlayToClient;
+
+pub const SECTIONS: usize = 16;
+pub const SECTION_BLOCKS: usize = 16 * 16 * 16;
+pub const BIOMES: usize = 16 * 16;
+
+const NIBBLES: usize = SECTION_BLOCKS / 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Block {
+    pub id: u16,
+    pub metadata: u8,
+}
+
+impl Block {
+    pub fn new(id: u16, metadata: u8) -> Block {
+        Block { id, metadata }
+    }
+
+    pub fn is_air(&self) -> bool {
+        self.id == 0
+    }
+}
+
+/// 4096 values of 4 bits, even indexes in the low nibble
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NibbleArray(Vec<u8>);
+
+impl NibbleArray {
+    pub fn new(fill: u8) -> NibbleArray {
+        let fill = fill & 0xF;
+        NibbleArray(vec![fill | (fill << 4); NIBBLES])
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        let byte = self.0[index >> 1];
+        if index & 1 == 0 {
+            byte & 0xF
+        } else {
+            byte >> 4
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: u8) {
+        let byte = &mut self.0[index >> 1];
+        if index & 1 == 0 {
+            *byte = (*byte & 0xF0) | (value & 0xF);
+        } else {
+            *byte = (*byte & 0x0F) | ((value & 0xF) << 4);
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for NibbleArray {
+    fn from(bytes: &[u8]) -> Self {
+        NibbleArray(bytes.to_vec())
+    }
+}
+
+/// 16x16x16 blocks, indexed by `y << 8 | z << 4 | x`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSection {
+    pub blocks: Vec<u8>,
+    pub metadata: NibbleArray,
+    pub block_light: NibbleArray,
+    pub sky_light: Option<NibbleArray>,
+    /// Upper 4 bits of block ids above 255
+    pub add: Option<NibbleArray>,
+}
+
+impl ChunkSection {
+    /// Empty section, fully lit by the sky if the dimension has one
+    pub fn new(sky_light: bool) -> ChunkSection {
+        ChunkSection {
+            blocks: vec![0; SECTION_BLOCKS],
+            metadata: NibbleArray::new(0),
+            block_light: NibbleArray::new(0),
+            sky_light: if sky_light {
+                Some(NibbleArray::new(15))
+            } else {
+                None
+            },
+            add: None,
+        }
+    }
+
+    pub fn index(x: usize, y: usize, z: usize) -> usize {
+        ((y & 0xF) << 8) | ((z & 0xF) << 4) | (x & 0xF)
+    }
+
+    pub fn get_block(&self, x: usize, y: usize, z: usize) -> Block {
+        let index = ChunkSection::index(x, y, z);
+        let add = self.add.as_ref().map_or(0, |add| add.get(index));
+        Block {
+            id: self.blocks[index] as u16 | ((add as u16) << 8),
+            metadata: self.metadata.get(index),
+        }
+    }
+
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: Block) {
+        let index = ChunkSection::index(x, y, z);
+        self.blocks[index] = block.id as u8;
+        let add = (block.id >> 8) as u8;
+        if add != 0 || self.add.is_some() {
+            self.add
+                .get_or_insert_with(|| NibbleArray::new(0))
+                .set(index, add);
+        }
+        self.metadata.set(index, block.metadata);
+    }
+
+    /// No block other than air
+    pub fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|b| *b == 0)
+            && self
+                .add
+                .iter()
+                .all(|add| add.as_bytes().iter().all(|b| *b == 0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkColumn {
+    pub x: i32,
+    pub z: i32,
+    pub sections: [Option<ChunkSection>; SECTIONS],
+    pub biomes: Option<Vec<u8>>,
+}
+
+impl ChunkColumn {
+    pub fn new(x: i32, z: i32) -> ChunkColumn {
+        ChunkColumn {
+            x,
+            z,
+            sections: Default::default(),
+            biomes: None,
+        }
+    }
+
+    /// Decode a column from the inflated data, returns it with the number of bytes used
+    pub fn decode(
+        x: i32,
+        z: i32,
+        data: &[u8],
+        bit_map: u16,
+        add_bit_map: u16,
+        sky_light: bool,
+        ground_up: bool,
+    ) -> Result<(ChunkColumn, usize)> {
+        let mut column = ChunkColumn::new(x, z);
+        let read = column.read(data, bit_map, add_bit_map, sky_light, ground_up)?;
+        Ok((column, read))
+    }
+
+    /// Decode the payload of a `ChunkData` packet.
+    ///
+    /// The packet does not tell whether sky light is sent, it is deduced from the data length.
+    pub fn from_chunk_data(
+        x: i32,
+        z: i32,
+        ground_up: bool,
+        bit_map: u16,
+        add_bit_map: u16,
+        chunk_data: &[u8],
+    ) -> Result<ChunkColumn> {
+        let mut column = ChunkColumn::new(x, z);
+        column.read_chunk_data(ground_up, bit_map, add_bit_map, chunk_data)?;
+        Ok(column)
+    }
+
+    /// Apply the payload of a `ChunkData` packet to this column
+    pub fn read_chunk_data(
+        &mut self,
+        ground_up: bool,
+        bit_map: u16,
+        add_bit_map: u16,
+        chunk_data: &[u8],
+    ) -> Result<()> {
+        let data = inflate(chunk_data)?;
+        let sky_light = data.len() == data_len(bit_map, add_bit_map, true, ground_up);
+        self.read(&data, bit_map, add_bit_map, sky_light, ground_up)
+            .map(|_| ())
+    }
+
+    /// Decode every column of a `ChunkDataBulk`
+    pub fn from_bulk(bulk: &ChunkDataBulk) -> Result<Vec<ChunkColumn>> {
+        let inflated = bulk.inflate()?;
+        inflated
+            .columns()
+            .map(|(meta, data)| {
+                ChunkColumn::decode(
+                    meta.x,
+                    meta.z,
+                    data,
+                    meta.mask,
+                    meta.add_mask,
+                    inflated.sky_light_sent(),
+                    true,
+                )
+                .map(|(column, _)| column)
+            })
+            .collect()
+    }
+
+    /// Build the `ChunkDataBulk` sending these columns ground up
+    pub fn to_bulk(columns: &[ChunkColumn], sky_light: bool) -> Result<ChunkDataBulk> {
+        let encoded: Vec<(ChunkMeta, Vec<u8>)> = columns
+            .iter()
+            .map(|column| {
+                let (mask, add_mask, data) = column.write(sky_light, true);
+                let meta = ChunkMeta {
+                    x: column.x,
+                    z: column.z,
+                    mask,
+                    add_mask,
+                };
+                (meta, data)
+            })
+            .collect();
+        ChunkDataBulk::from_columns(
+            sky_light,
+            encoded.iter().map(|(meta, data)| (*meta, data.as_slice())),
+        )
+    }
+
+    /// Overwrite the sections present in `bit_map` from the inflated data.
+    ///
+    /// A ground up column replaces the whole column, sections missing from `bit_map` are emptied.
+    pub fn read(
+        &mut self,
+        data: &[u8],
+        bit_map: u16,
+        add_bit_map: u16,
+        sky_light: bool,
+        ground_up: bool,
+    ) -> Result<usize> {
+        let len = data_len(bit_map, add_bit_map, sky_light, ground_up);
+        if data.len() < len {
+            return Err(Error::Serde(format!(
+                "Chunk column needs {} bytes but only {} are available",
+                len,
+                data.len()
+            )));
+        }
+
+        let present: Vec<usize> = (0..SECTIONS).filter(|i| bit_map & (1 << i) != 0).collect();
+        let mut offset = 0;
+        let mut take = |n: usize| {
+            let slice = &data[offset..offset + n];
+            offset += n;
+            slice
+        };
+
+        let mut sections: Vec<ChunkSection> = present
+            .iter()
+            .map(|_| ChunkSection {
+                blocks: take(SECTION_BLOCKS).to_vec(),
+                metadata: NibbleArray::new(0),
+                block_light: NibbleArray::new(0),
+                sky_light: None,
+                add: None,
+            })
+            .collect();
+        for section in sections.iter_mut() {
+            section.metadata = take(NIBBLES).into();
+        }
+        for section in sections.iter_mut() {
+            section.block_light = take(NIBBLES).into();
+        }
+        if sky_light {
+            for section in sections.iter_mut() {
+                section.sky_light = Some(take(NIBBLES).into());
+            }
+        }
+        for (section, i) in sections.iter_mut().zip(present.iter()) {
+            if add_bit_map & (1 << i) != 0 {
+                section.add = Some(take(NIBBLES).into());
+            }
+        }
+        if ground_up {
+            self.biomes = Some(take(BIOMES).to_vec());
+            self.sections = Default::default();
+        }
+
+        for (section, i) in sections.into_iter().zip(present) {
+            self.sections[i] = Some(section);
+        }
+        Ok(len)
+    }
+
+    /// Encode the column, returns `(bit_map, add_bit_map, data)` with the data not yet deflated.
+    ///
+    /// Empty sections are skipped when writing a ground up column.
+    pub fn write(&self, sky_light: bool, ground_up: bool) -> (u16, u16, Vec<u8>) {
+        let mut bit_map = 0u16;
+        let mut add_bit_map = 0u16;
+        let mut present = Vec::new();
+        for (i, section) in self.sections.iter().enumerate() {
+            if let Some(section) = section {
+                if ground_up && section.is_empty() {
+                    continue;
+                }
+                bit_map |= 1 << i;
+                if section.add.is_some() {
+                    add_bit_map |= 1 << i;
+                }
+                present.push(section);
+            }
+        }
+
+        let mut data =
+            Vec::with_capacity(data_len(bit_map, add_bit_map, sky_light, ground_up));
+        for section in &present {
+            data.extend_from_slice(&section.blocks);
+        }
+        for section in &present {
+            data.extend_from_slice(section.metadata.as_bytes());
+        }
+        for section in &present {
+            data.extend_from_slice(section.block_light.as_bytes());
+        }
+        if sky_light {
+            let full = NibbleArray::new(15);
+            for section in &present {
+                data.extend_from_slice(section.sky_light.as_ref().unwrap_or(&full).as_bytes());
+            }
+        }
+        for section in &present {
+            if let Some(add) = &section.add {
+                data.extend_from_slice(add.as_bytes());
+            }
+        }
+        if ground_up {
+            match &self.biomes {
+                Some(biomes) => data.extend_from_slice(biomes),
+                None => data.extend_from_slice(&[0; BIOMES]),
+            }
+        }
+        (bit_map, add_bit_map, data)
+    }
+
+    /// Build the `ChunkData` packet sending this column
+    pub fn to_chunk_data(&self, sky_light: bool, ground_up: bool) -> Result<PlayToClient> {
+        let (bit_map, add_bit_map, data) = self.write(sky_light, ground_up);
+        Ok(PlayToClient::ChunkData {
+            x: self.x,
+            z: self.z,
+            ground_up,
+            bit_map,
+            add_bit_map,
+            chunk_data: IntPrefix::new(deflate(&data)?),
+        })
+    }
+
+    /// Block at column relative coordinates, `y` in `0..256`
+    pub fn get_block(&self, x: usize, y: usize, z: usize) -> Block {
+        match &self.sections[(y >> 4) & 0xF] {
+            Some(section) => section.get_block(x, y, z),
+            None => Block::default(),
+        }
+    }
+
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: Block) {
+        let sky_light = self.has_sky_light();
+        let section = &mut self.sections[(y >> 4) & 0xF];
+        if section.is_none() {
+            if block.is_air() {
+                return;
+            }
+            *section = Some(ChunkSection::new(sky_light));
+        }
+        if let Some(section) = section {
+            section.set_block(x, y, z, block);
+        }
+    }
+
+    pub fn get_block_light(&self, x: usize, y: usize, z: usize) -> u8 {
+        match &self.sections[(y >> 4) & 0xF] {
+            Some(section) => section.block_light.get(ChunkSection::index(x, y, z)),
+            None => 0,
+        }
+    }
+
+    pub fn get_sky_light(&self, x: usize, y: usize, z: usize) -> u8 {
+        match &self.sections[(y >> 4) & 0xF] {
+            Some(section) => section
+                .sky_light
+                .as_ref()
+                .map_or(0, |light| light.get(ChunkSection::index(x, y, z))),
+            None => {
+                if self.has_sky_light() {
+                    15
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    /// Drop sections that only contain air
+    pub fn prune(&mut self) {
+        for section in self.sections.iter_mut() {
+            if section.iter().any(|s| s.is_empty()) {
+                *section = None;
+            }
+        }
+    }
+
+    pub fn get_biome(&self, x: usize, z: usize) -> Option<u8> {
+        self.biomes
+            .as_ref()
+            .map(|biomes| biomes[((z & 0xF) << 4) | (x & 0xF)])
+    }
+
+    fn has_sky_light(&self) -> bool {
+        match self.sections.iter().flatten().next() {
+            Some(section) => section.sky_light.is_some(),
+            None => true,
+        }
+    }
+}
+
+/// Inflate zlib compressed chunk data
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut inflated)?;
+    Ok(inflated)
+}
+
+/// Deflate chunk data with zlib
+pub fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: Block = Block { id: 1, metadata: 0 };
+    /// Needs the add array
+    const HIGH: Block = Block { id: 0x1a3, metadata: 9 };
+
+    /// Sections 0 and 2 hold blocks, section 1 is present but empty
+    fn column(sky_light: bool) -> ChunkColumn {
+        let mut column = ChunkColumn::new(3, -4);
+        column.sections[1] = Some(ChunkSection::new(sky_light));
+        column.set_block(1, 2, 3, STONE);
+        column.set_block(15, 15, 15, Block::new(4, 3));
+        column.set_block(0, 40, 7, HIGH);
+        for (i, section) in column.sections.iter_mut().flatten().enumerate() {
+            section.block_light.set(ChunkSection::index(1, 2, 3), i as u8 + 1);
+            if let Some(sky) = section.sky_light.as_mut() {
+                sky.set(ChunkSection::index(1, 2, 3), 6);
+            }
+        }
+        column.biomes = Some((0..BIOMES).map(|i| i as u8).collect());
+        column
+    }
+
+    #[test]
+    fn nibbles() {
+        let mut nibbles = NibbleArray::new(0x1f);
+        assert_eq!(nibbles.get(0), 0xf);
+        nibbles.set(4, 0x12);
+        nibbles.set(5, 3);
+        assert_eq!((nibbles.get(4), nibbles.get(5)), (2, 3));
+        assert_eq!(nibbles.as_bytes()[2], 0x32);
+    }
+
+    #[test]
+    fn write_layout() {
+        let (bit_map, add_bit_map, data) = column(true).write(true, false);
+        assert_eq!((bit_map, add_bit_map), (0b111, 0b100));
+        assert_eq!(data.len(), data_len(bit_map, add_bit_map, true, false));
+        // block ids of the three sections, then their metadata
+        assert_eq!(data[ChunkSection::index(1, 2, 3)], 1);
+        assert_eq!(data[2 * SECTION_BLOCKS + ChunkSection::index(0, 8, 7)], 0xa3);
+        let metadata = 3 * SECTION_BLOCKS;
+        assert_eq!(data[metadata + (ChunkSection::index(15, 15, 15) >> 1)] >> 4, 3);
+        // the add array of section 2 comes last
+        let add = data.len() - NIBBLES;
+        assert_eq!(data[add + (ChunkSection::index(0, 8, 7) >> 1)], 1);
+
+        // a ground up column skips empty sections and ends with the biomes
+        let (bit_map, add_bit_map, data) = column(true).write(true, true);
+        assert_eq!((bit_map, add_bit_map), (0b101, 0b100));
+        assert_eq!(data.len(), data_len(bit_map, add_bit_map, true, true));
+        assert_eq!(data[data.len() - 1], 255);
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        for &sky_light in &[true, false] {
+            let original = column(sky_light);
+            let (bit_map, add_bit_map, mut data) = original.write(sky_light, false);
+            data.extend_from_slice(&[0xee; 5]);
+            let (decoded, read) =
+                ChunkColumn::decode(3, -4, &data, bit_map, add_bit_map, sky_light, false).unwrap();
+            assert_eq!(read, data.len() - 5);
+            let mut expected = original.clone();
+            expected.biomes = None;
+            assert_eq!(decoded, expected);
+
+            // sky light is deduced from the length of the chunk data
+            let packet = original.to_chunk_data(sky_light, true).unwrap();
+            let decoded = match packet {
+                PlayToClient::ChunkData {
+                    x,
+                    z,
+                    ground_up,
+                    bit_map,
+                    add_bit_map,
+                    chunk_data,
+                } => ChunkColumn::from_chunk_data(x, z, ground_up, bit_map, add_bit_map, &chunk_data)
+                    .unwrap(),
+                other => panic!("unexpected packet {:?}", other),
+            };
+            let mut expected = original;
+            expected.sections[1] = None;
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn partial_update() {
+        let mut column = column(true);
+        let mut update = ChunkColumn::new(3, -4);
+        update.set_block(5, 20, 5, STONE);
+        let (bit_map, add_bit_map, data) = update.write(true, false);
+        assert_eq!(bit_map, 0b10);
+        column.read(&data, bit_map, add_bit_map, true, false).unwrap();
+        assert_eq!(column.get_block(5, 20, 5), STONE);
+        assert_eq!(column.get_block(1, 2, 3), STONE);
+        assert_eq!(column.get_block(0, 40, 7), HIGH);
+        assert!(column.biomes.is_some());
+
+        // ground up replaces the whole column
+        column.read(&data, bit_map, add_bit_map, true, true).unwrap_err();
+        let (bit_map, add_bit_map, data) = update.write(true, true);
+        column.read(&data, bit_map, add_bit_map, true, true).unwrap();
+        assert_eq!(column.get_block(1, 2, 3), Block::default());
+        assert_eq!(column.get_biome(15, 15), Some(0));
+    }
+
+    #[test]
+    fn bulk_round_trip() {
+        let mut second = ChunkColumn::new(4, -4);
+        second.set_block(8, 255, 8, STONE);
+        second.biomes = Some(vec![1; BIOMES]);
+        let columns = vec![column(true), second];
+        let decoded = ChunkColumn::from_bulk(&ChunkColumn::to_bulk(&columns, true).unwrap()).unwrap();
+        let mut expected = columns;
+        expected[0].sections[1] = None;
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn light() {
+        let column = column(true);
+        assert_eq!(column.get_block_light(1, 2, 3), 1);
+        assert_eq!(column.get_block_light(1, 18, 3), 2);
+        assert_eq!(column.get_block_light(1, 34, 3), 3);
+        assert_eq!(column.get_block_light(2, 2, 3), 0);
+        assert_eq!(column.get_sky_light(1, 2, 3), 6);
+        assert_eq!(column.get_sky_light(2, 2, 3), 15);
+        // missing sections are dark but open to the sky
+        assert_eq!(column.get_block_light(1, 100, 3), 0);
+        assert_eq!(column.get_sky_light(1, 100, 3), 15);
+
+        let nether = self::column(false);
+        assert_eq!(nether.get_sky_light(1, 2, 3), 0);
+        assert_eq!(nether.get_sky_light(1, 100, 3), 0);
+        let mut nether = nether;
+        nether.set_block(0, 200, 0, STONE);
+        assert!(nether.sections[12].as_ref().unwrap().sky_light.is_none());
+    }
+
+    #[test]
+    fn prune() {
+        let mut column = column(true);
+        column.set_block(15, 15, 15, Block::default());
+        column.set_block(1, 2, 3, Block::default());
+        column.set_block(0, 40, 7, Block::default());
+        assert!(column.sections[0].is_some() && column.sections[2].is_some());
+        column.set_block(0, 60, 0, STONE);
+        column.prune();
+        // section 2 is empty although its add array is still there
+        let present: Vec<bool> = column.sections.iter().map(Option::is_some).collect();
+        assert_eq!(&present[..4], &[false, false, false, true]);
+        assert_eq!(column.get_block(0, 60, 0), STONE);
+    }
+}
@@ -0,0 +1,553 @@
+//! Entity tracking driven by spawn, move and destroy packets
+
+use std::collections::HashMap;
+
+use serde_mcproto::types::entity_metadata::EntityMetadata;
+use serde_mcproto::types::slot::Slot;
+use serde_mcproto::types::uuid::Uuid;
+
+use super::PlayToClient;
+
+/// Absolute positions are sent as fixed-point numbers with 5 fraction bits
+const POSITION_SCALE: f64 = 32.0;
+/// Velocities are sent in 1/8000 block per tick
+const VELOCITY_SCALE: f64 = 8000.0;
+
+/// Decode an absolute fixed-point coordinate
+pub fn from_fixed(v: i32) -> f64 {
+    v as f64 / POSITION_SCALE
+}
+
+/// Decode an angle sent as a step of 1/256 of a full turn
+pub fn from_angle(v: i8) -> f32 {
+    v as u8 as f32 * 360.0 / 256.0
+}
+
+pub fn from_velocity(v: i16) -> f64 {
+    v as f64 / VELOCITY_SCALE
+}
+
+#[derive(Debug, Clone)]
+pub enum EntityKind {
+    Player { uuid: Uuid, name: String },
+    /// Mob type from `SpawnMob`
+    Mob(u8),
+    /// Object type from `SpawnObject` and its object data
+    Object { type_: i8, data: i32 },
+    Painting { title: String, direction: i32 },
+    ExperienceOrb { count: i16 },
+    /// Global entity type, i.e. thunderbolt
+    Global(i8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub id: i32,
+    pub kind: EntityKind,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub head_yaw: f32,
+    /// Blocks per tick
+    pub velocity: [f64; 3],
+    pub metadata: EntityMetadata,
+    /// Held item followed by boots, leggings, chestplate and helmet
+    pub equipment: [Option<Slot>; 5],
+    /// Entity this one is riding
+    pub vehicle: Option<i32>,
+    pub leash_holder: Option<i32>,
+    /// Fixed-point position, kept to apply relative moves without drifting
+    fixed_position: [i32; 3],
+}
+
+impl Entity {
+    fn new(id: i32, kind: EntityKind, fixed_position: [i32; 3]) -> Entity {
+        Entity {
+            id,
+            kind,
+            yaw: 0.0,
+            pitch: 0.0,
+            head_yaw: 0.0,
+            velocity: [0.0; 3],
+            metadata: EntityMetadata::default(),
+            equipment: Default::default(),
+            vehicle: None,
+            leash_holder: None,
+            fixed_position,
+        }
+    }
+
+    pub fn position(&self) -> [f64; 3] {
+        [
+            from_fixed(self.fixed_position[0]),
+            from_fixed(self.fixed_position[1]),
+            from_fixed(self.fixed_position[2]),
+        ]
+    }
+
+    pub fn distance_squared(&self, to: [f64; 3]) -> f64 {
+        let position = self.position();
+        (0..3).map(|i| (position[i] - to[i]).powi(2)).sum()
+    }
+
+    pub fn is_player(&self) -> bool {
+        matches!(self.kind, EntityKind::Player { .. })
+    }
+
+    fn set_velocity(&mut self, velocity: &[i16; 3]) {
+        self.velocity = [
+            from_velocity(velocity[0]),
+            from_velocity(velocity[1]),
+            from_velocity(velocity[2]),
+        ];
+    }
+
+    fn move_by(&mut self, delta: &[i8; 3]) {
+        for (position, delta) in self.fixed_position.iter_mut().zip(delta.iter()) {
+            *position += *delta as i32;
+        }
+    }
+}
+
+/// Entities known to the client, keyed by entity id
+#[derive(Debug, Clone, Default)]
+pub struct EntityTracker {
+    entities: HashMap<i32, Entity>,
+}
+
+impl EntityTracker {
+    pub fn new() -> EntityTracker {
+        EntityTracker::default()
+    }
+
+    /// Apply a packet
+    pub fn handle(&mut self, packet: &PlayToClient) {
+        match packet {
+            PlayToClient::Respawn { .. } => self.entities.clear(),
+            PlayToClient::SpawnPlayer {
+                entity_id,
+                player_uuid,
+                player_name,
+                position,
+                yaw,
+                pitch,
+                metadata,
+                ..
+            } => {
+                let kind = EntityKind::Player {
+                    uuid: *player_uuid,
+                    name: player_name.clone(),
+                };
+                let mut entity = Entity::new(entity_id.0, kind, *position);
+                entity.yaw = from_angle(*yaw as i8);
+                entity.pitch = from_angle(*pitch as i8);
+                entity.head_yaw = entity.yaw;
+                entity.metadata = metadata.clone();
+                self.spawn(entity);
+            }
+            PlayToClient::SpawnMob {
+                entity_id,
+                type_,
+                position,
+                yaw,
+                pitch,
+                head_pitch,
+                velocity,
+                metadata,
+            } => {
+                let mut entity = Entity::new(entity_id.0, EntityKind::Mob(*type_), *position);
+                entity.yaw = from_angle(*yaw);
+                entity.pitch = from_angle(*pitch);
+                entity.head_yaw = from_angle(*head_pitch);
+                entity.set_velocity(velocity);
+                entity.metadata = metadata.clone();
+                self.spawn(entity);
+            }
+            PlayToClient::SpawnObject {
+                entity_id,
+                type_,
+                position,
+                pitch,
+                yaw,
+                data,
+            } => {
+                let kind = EntityKind::Object {
+                    type_: *type_,
+                    data: data.object_id,
+                };
+                let mut entity = Entity::new(entity_id.0, kind, *position);
+                entity.yaw = from_angle(*yaw);
+                entity.pitch = from_angle(*pitch);
+                entity.set_velocity(&[data.velocity_x, data.velocity_y, data.velocity_z]);
+                self.spawn(entity);
+            }
+            PlayToClient::SpawnPainting {
+                entity_id,
+                title,
+                x,
+                y,
+                z,
+                direction,
+            } => {
+                let kind = EntityKind::Painting {
+                    title: title.clone(),
+                    direction: *direction,
+                };
+                // paintings are positioned on a block, not in fixed-point
+                let position = [*x * 32, *y * 32, *z * 32];
+                self.spawn(Entity::new(entity_id.0, kind, position));
+            }
+            PlayToClient::SpawnExperienceOrb {
+                entity_id,
+                position,
+                count,
+            } => {
+                let kind = EntityKind::ExperienceOrb { count: *count };
+                self.spawn(Entity::new(entity_id.0, kind, *position));
+            }
+            PlayToClient::SpawnGlobalEntity {
+                entity_id,
+                type_,
+                position,
+            } => {
+                self.spawn(Entity::new(entity_id.0, EntityKind::Global(*type_), *position));
+            }
+            PlayToClient::EntityRelativeMove { entity_id, delta } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.move_by(delta);
+                }
+            }
+            PlayToClient::EntityLook {
+                entity_id,
+                yaw,
+                pitch,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.yaw = from_angle(*yaw);
+                    entity.pitch = from_angle(*pitch);
+                }
+            }
+            PlayToClient::EntityLookAndRelativeMove {
+                entity_id,
+                delta,
+                yaw,
+                pitch,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.move_by(delta);
+                    entity.yaw = from_angle(*yaw);
+                    entity.pitch = from_angle(*pitch);
+                }
+            }
+            PlayToClient::EntityTeleport {
+                entity_id,
+                position,
+                yaw,
+                pitch,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.fixed_position = *position;
+                    entity.yaw = from_angle(*yaw);
+                    entity.pitch = from_angle(*pitch);
+                }
+            }
+            PlayToClient::EntityHeadLook {
+                entity_id,
+                head_yaw,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.head_yaw = from_angle(*head_yaw);
+                }
+            }
+            PlayToClient::EntityVelocity {
+                entity_id,
+                velocity,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.set_velocity(velocity);
+                }
+            }
+            PlayToClient::EntityMetadata {
+                entity_id,
+                metadata,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    entity.metadata.update(metadata);
+                }
+            }
+            PlayToClient::EntityEquipment {
+                entity_id,
+                slot,
+                item,
+            } => {
+                if let Some(entity) = self.entities.get_mut(entity_id) {
+                    if let Some(equipment) = entity.equipment.get_mut(*slot as usize) {
+                        *equipment = item.clone();
+                    }
+                }
+            }
+            PlayToClient::AttachEntity {
+                riding_eid,
+                vehicle_eid,
+                leash,
+            } => {
+                if let Some(entity) = self.entities.get_mut(riding_eid) {
+                    let target = if *vehicle_eid == -1 {
+                        None
+                    } else {
+                        Some(*vehicle_eid)
+                    };
+                    if *leash {
+                        entity.leash_holder = target;
+                    } else {
+                        entity.vehicle = target;
+                    }
+                }
+            }
+            PlayToClient::DestroyEntities { entity_ids } => {
+                for entity_id in entity_ids.iter() {
+                    self.remove(*entity_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, entity_id: i32) -> Option<&Entity> {
+        self.entities.get(&entity_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Entities riding the given vehicle
+    pub fn passengers(&self, vehicle_id: i32) -> impl Iterator<Item = &Entity> {
+        self.entities
+            .values()
+            .filter(move |entity| entity.vehicle == Some(vehicle_id))
+    }
+
+    /// Nearest entity to `position` accepted by `filter`
+    pub fn nearest<F>(&self, position: [f64; 3], filter: F) -> Option<&Entity>
+    where
+        F: Fn(&Entity) -> bool,
+    {
+        self.entities
+            .values()
+            .filter(|entity| filter(entity))
+            .map(|entity| (entity.distance_squared(position), entity))
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, entity)| entity)
+    }
+
+    /// Nearest mob of the given `SpawnMob` type
+    pub fn nearest_mob(&self, position: [f64; 3], mob_type: u8) -> Option<&Entity> {
+        self.nearest(position, |entity| matches!(entity.kind, EntityKind::Mob(type_) if type_ == mob_type))
+    }
+
+    /// Nearest object of the given `SpawnObject` type
+    pub fn nearest_object(&self, position: [f64; 3], object_type: i8) -> Option<&Entity> {
+        self.nearest(position, |entity| {
+            matches!(entity.kind, EntityKind::Object { type_, .. } if type_ == object_type)
+        })
+    }
+
+    pub fn nearest_player(&self, position: [f64; 3]) -> Option<&Entity> {
+        self.nearest(position, Entity::is_player)
+    }
+
+    fn spawn(&mut self, entity: Entity) {
+        self.entities.insert(entity.id, entity);
+    }
+
+    fn remove(&mut self, entity_id: i32) {
+        self.entities.remove(&entity_id);
+        for entity in self.entities.values_mut() {
+            if entity.vehicle == Some(entity_id) {
+                entity.vehicle = None;
+            }
+            if entity.leash_holder == Some(entity_id) {
+                entity.leash_holder = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1_7_10::ObjectData;
+    use serde_mcproto::types::array::{BytePrefix, VarIntPrefix};
+    use serde_mcproto::types::entity_metadata::Entry;
+    use serde_mcproto::types::var::VarInt;
+
+    const ZOMBIE: u8 = 54;
+    const ARROW: i8 = 60;
+
+    fn mob(entity_id: i32, position: [i32; 3], velocity: [i16; 3]) -> PlayToClient {
+        let mut metadata = EntityMetadata::default();
+        metadata.insert(0, Entry::Byte(0));
+        PlayToClient::SpawnMob {
+            entity_id: VarInt(entity_id),
+            type_: ZOMBIE,
+            position,
+            yaw: 64,
+            pitch: -128,
+            head_pitch: -64,
+            velocity,
+            metadata,
+        }
+    }
+
+    fn relative_move(entity_id: i32, delta: [i8; 3]) -> PlayToClient {
+        PlayToClient::EntityRelativeMove { entity_id, delta }
+    }
+
+    fn attach(riding_eid: i32, vehicle_eid: i32, leash: bool) -> PlayToClient {
+        PlayToClient::AttachEntity {
+            riding_eid,
+            vehicle_eid,
+            leash,
+        }
+    }
+
+    #[test]
+    fn spawn_decodes_fixed_point() {
+        let mut tracker = EntityTracker::new();
+        tracker.handle(&mob(1, [-48, 2080, 16], [8000, -4000, 800]));
+        let zombie = tracker.get(1).unwrap();
+        assert!(matches!(zombie.kind, EntityKind::Mob(ZOMBIE)));
+        assert_eq!(zombie.position(), [-1.5, 65.0, 0.5]);
+        assert_eq!(zombie.velocity, [1.0, -0.5, 0.1]);
+        assert_eq!((zombie.yaw, zombie.pitch, zombie.head_yaw), (90.0, 180.0, 270.0));
+        assert!(zombie.metadata.get(0).is_some());
+
+        tracker.handle(&PlayToClient::SpawnObject {
+            entity_id: VarInt(2),
+            type_: ARROW,
+            position: [1, -1, 0],
+            pitch: 0,
+            yaw: 0,
+            data: ObjectData {
+                object_id: 1,
+                velocity_x: -8000,
+                velocity_y: 16000,
+                velocity_z: 0,
+            },
+        });
+        let arrow = tracker.get(2).unwrap();
+        assert!(matches!(arrow.kind, EntityKind::Object { type_: ARROW, data: 1 }));
+        assert_eq!(arrow.position(), [0.03125, -0.03125, 0.0]);
+        assert_eq!(arrow.velocity, [-1.0, 2.0, 0.0]);
+
+        tracker.handle(&PlayToClient::SpawnPlayer {
+            entity_id: VarInt(3),
+            player_uuid: Uuid::offline("Notch"),
+            player_name: "Notch".to_owned(),
+            data: VarIntPrefix::new(Vec::new()),
+            position: [320, 2048, -320],
+            yaw: 192,
+            pitch: 0,
+            current_item: 0,
+            metadata: EntityMetadata::default(),
+        });
+        let player = tracker.get(3).unwrap();
+        assert!(player.is_player());
+        assert_eq!(player.position(), [10.0, 64.0, -10.0]);
+        assert_eq!((player.yaw, player.head_yaw), (270.0, 270.0));
+
+        // paintings are placed on a block
+        tracker.handle(&PlayToClient::SpawnPainting {
+            entity_id: VarInt(4),
+            title: "Kebab".to_owned(),
+            x: -3,
+            y: 70,
+            z: 5,
+            direction: 0,
+        });
+        assert_eq!(tracker.get(4).unwrap().position(), [-3.0, 70.0, 5.0]);
+
+        assert_eq!(tracker.nearest_mob([0.0, 65.0, 0.0], ZOMBIE).unwrap().id, 1);
+        assert_eq!(tracker.nearest_object([0.0; 3], ARROW).unwrap().id, 2);
+        assert_eq!(tracker.nearest_player([0.0; 3]).unwrap().id, 3);
+        assert!(tracker.nearest_mob([0.0; 3], ZOMBIE + 1).is_none());
+    }
+
+    #[test]
+    fn moves_do_not_drift() {
+        let mut tracker = EntityTracker::new();
+        tracker.handle(&mob(1, [0, 0, 0], [0; 3]));
+        // a hundred moves of 1/32 block add up exactly
+        for _ in 0..100 {
+            tracker.handle(&relative_move(1, [1, -1, 0]));
+        }
+        assert_eq!(tracker.get(1).unwrap().position(), [3.125, -3.125, 0.0]);
+
+        tracker.handle(&PlayToClient::EntityLookAndRelativeMove {
+            entity_id: 1,
+            delta: [-128, 127, 32],
+            yaw: -64,
+            pitch: 32,
+        });
+        let zombie = tracker.get(1).unwrap();
+        assert_eq!(zombie.position(), [-0.875, 0.84375, 1.0]);
+        assert_eq!((zombie.yaw, zombie.pitch), (270.0, 45.0));
+
+        tracker.handle(&PlayToClient::EntityTeleport {
+            entity_id: 1,
+            position: [-1, 4096, 33],
+            yaw: 0,
+            pitch: 0,
+        });
+        assert_eq!(tracker.get(1).unwrap().position(), [-0.03125, 128.0, 1.03125]);
+
+        tracker.handle(&PlayToClient::EntityVelocity {
+            entity_id: 1,
+            velocity: [-8000, 0, 4],
+        });
+        assert_eq!(tracker.get(1).unwrap().velocity, [-1.0, 0.0, 0.0005]);
+
+        // unknown entities are ignored
+        tracker.handle(&relative_move(2, [1, 1, 1]));
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn destroy_detaches_riders() {
+        let mut tracker = EntityTracker::new();
+        for id in 1..4 {
+            tracker.handle(&mob(id, [0; 3], [0; 3]));
+        }
+        tracker.handle(&attach(2, 1, false));
+        tracker.handle(&attach(3, 1, true));
+        assert_eq!(tracker.passengers(1).map(|entity| entity.id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(tracker.get(3).unwrap().leash_holder, Some(1));
+
+        tracker.handle(&PlayToClient::DestroyEntities {
+            entity_ids: BytePrefix::new(vec![1, 5]),
+        });
+        assert!(tracker.get(1).is_none());
+        assert_eq!(tracker.get(2).unwrap().vehicle, None);
+        assert_eq!(tracker.get(3).unwrap().leash_holder, None);
+
+        tracker.handle(&attach(3, 2, false));
+        tracker.handle(&attach(3, -1, false));
+        assert_eq!(tracker.get(3).unwrap().vehicle, None);
+
+        tracker.handle(&PlayToClient::Respawn {
+            dimension: 0,
+            difficulty: 0,
+            gamemode: 0,
+            level_type: "default".to_owned(),
+        });
+        assert!(tracker.is_empty());
+    }
+}
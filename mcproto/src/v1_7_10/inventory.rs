@@ -0,0 +1,664 @@
+//! Window and inventory tracking driven by window packets
+
+use std::collections::{HashMap, HashSet};
+
+use serde_mcproto::types::slot::Slot;
+
+use super::{PlayToClient, PlayToServer};
+
+/// Window id of the player's own inventory, which is always open
+pub const PLAYER_WINDOW: u8 = 0;
+
+/// Slot number used for clicks outside of the window
+pub const OUTSIDE_SLOT: i16 = -999;
+
+/// Main inventory (27) and hotbar (9) slots appended to every window
+const PLAYER_SLOTS: usize = 36;
+const HOTBAR_SLOTS: usize = 9;
+
+/// Crafting grid (0..5) and armor (5..9) slots of the player window
+const PLAYER_WINDOW_SLOTS: usize = 9;
+
+/// `inventory_type` of `PlayToClient::OpenWindow`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InventoryType {
+    Chest,
+    CraftingTable,
+    Furnace,
+    Dispenser,
+    EnchantmentTable,
+    BrewingStand,
+    Villager,
+    Beacon,
+    Anvil,
+    Hopper,
+    Dropper,
+    Horse,
+    Unknown(u8),
+}
+
+impl From<u8> for InventoryType {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => InventoryType::Chest,
+            1 => InventoryType::CraftingTable,
+            2 => InventoryType::Furnace,
+            3 => InventoryType::Dispenser,
+            4 => InventoryType::EnchantmentTable,
+            5 => InventoryType::BrewingStand,
+            6 => InventoryType::Villager,
+            7 => InventoryType::Beacon,
+            8 => InventoryType::Anvil,
+            9 => InventoryType::Hopper,
+            10 => InventoryType::Dropper,
+            11 => InventoryType::Horse,
+            other => InventoryType::Unknown(other),
+        }
+    }
+}
+
+impl From<InventoryType> for u8 {
+    fn from(v: InventoryType) -> Self {
+        match v {
+            InventoryType::Chest => 0,
+            InventoryType::CraftingTable => 1,
+            InventoryType::Furnace => 2,
+            InventoryType::Dispenser => 3,
+            InventoryType::EnchantmentTable => 4,
+            InventoryType::BrewingStand => 5,
+            InventoryType::Villager => 6,
+            InventoryType::Beacon => 7,
+            InventoryType::Anvil => 8,
+            InventoryType::Hopper => 9,
+            InventoryType::Dropper => 10,
+            InventoryType::Horse => 11,
+            InventoryType::Unknown(other) => other,
+        }
+    }
+}
+
+impl InventoryType {
+    /// Number of slots owned by the window itself, without the player inventory.
+    ///
+    /// Vanilla advertises the size of the backing inventory in `OpenWindow::slots`,
+    /// which only matches the window layout for chests, horses and unknown types.
+    pub fn window_slots(&self, advertised: u8) -> usize {
+        match self {
+            InventoryType::CraftingTable => 10,
+            InventoryType::Furnace => 3,
+            InventoryType::Dispenser | InventoryType::Dropper => 9,
+            InventoryType::EnchantmentTable => 1,
+            InventoryType::BrewingStand => 4,
+            InventoryType::Villager => 3,
+            InventoryType::Beacon => 1,
+            InventoryType::Anvil => 3,
+            InventoryType::Hopper => 5,
+            InventoryType::Chest | InventoryType::Horse | InventoryType::Unknown(_) => {
+                advertised as usize
+            }
+        }
+    }
+
+    /// Slots whose content is produced by the window (crafting result, furnace output...)
+    pub fn output_slots(&self) -> &'static [i16] {
+        match self {
+            InventoryType::CraftingTable => &[0],
+            InventoryType::Furnace | InventoryType::Villager | InventoryType::Anvil => &[2],
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    Player,
+    Container(InventoryType),
+}
+
+/// Client side click on a window slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Click {
+    Left,
+    Right,
+    ShiftLeft,
+    ShiftRight,
+    /// Swap with the hotbar slot `0..9` bound to the pressed number key
+    NumberKey(u8),
+    Middle,
+    Drop,
+    DropStack,
+    DoubleClick,
+}
+
+impl Click {
+    /// `(mode, button)` pair sent in `PlayToServer::ClickWindow`
+    pub fn mode_and_button(&self) -> (i8, i8) {
+        match *self {
+            Click::Left => (0, 0),
+            Click::Right => (0, 1),
+            Click::ShiftLeft => (1, 0),
+            Click::ShiftRight => (1, 1),
+            Click::NumberKey(key) => (2, key as i8),
+            Click::Middle => (3, 2),
+            Click::Drop => (4, 0),
+            Click::DropStack => (4, 1),
+            Click::DoubleClick => (6, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: u8,
+    pub kind: WindowKind,
+    pub title: String,
+    /// Horse entity for `InventoryType::Horse` windows
+    pub entity_id: Option<i32>,
+    window_slots: usize,
+    slots: Vec<Option<Slot>>,
+    properties: HashMap<i16, i16>,
+    next_action_number: i16,
+}
+
+impl Window {
+    fn new(id: u8, kind: WindowKind, title: String, window_slots: usize) -> Window {
+        Window {
+            id,
+            kind,
+            title,
+            entity_id: None,
+            window_slots,
+            slots: vec![None; window_slots + PLAYER_SLOTS],
+            properties: HashMap::new(),
+            next_action_number: 0,
+        }
+    }
+
+    fn player() -> Window {
+        Window::new(
+            PLAYER_WINDOW,
+            WindowKind::Player,
+            String::new(),
+            PLAYER_WINDOW_SLOTS,
+        )
+    }
+
+    /// Item in the given window slot
+    pub fn slot(&self, slot: i16) -> Option<&Slot> {
+        if slot < 0 {
+            return None;
+        }
+        self.slots.get(slot as usize).and_then(|s| s.as_ref())
+    }
+
+    pub fn slots(&self) -> &[Option<Slot>] {
+        &self.slots
+    }
+
+    /// Total number of slots, including the player inventory
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of slots owned by the window itself, the player inventory follows them
+    pub fn window_slots(&self) -> usize {
+        self.window_slots
+    }
+
+    /// Window slot of the main player inventory slot `0..27`
+    pub fn main_inventory_slot(&self, index: usize) -> i16 {
+        (self.window_slots + index) as i16
+    }
+
+    /// Window slot of the hotbar slot `0..9`
+    pub fn hotbar_slot(&self, index: usize) -> i16 {
+        (self.window_slots + PLAYER_SLOTS - HOTBAR_SLOTS + index) as i16
+    }
+
+    /// Slots whose content is produced by the window
+    pub fn output_slots(&self) -> &'static [i16] {
+        match self.kind {
+            WindowKind::Player => &[0],
+            WindowKind::Container(inventory_type) => inventory_type.output_slots(),
+        }
+    }
+
+    /// Last value of a `WindowProperty`
+    pub fn property(&self, property: i16) -> Option<i16> {
+        self.properties.get(&property).cloned()
+    }
+
+    fn set_slot(&mut self, slot: i16, item: Option<Slot>) {
+        if slot < 0 {
+            return;
+        }
+        if let Some(s) = self.slots.get_mut(slot as usize) {
+            *s = item;
+        }
+    }
+
+    /// Index of a window slot in the player inventory part, `0..36`
+    fn player_index(&self, slot: i16) -> Option<usize> {
+        if slot < self.window_slots as i16 {
+            return None;
+        }
+        let index = slot as usize - self.window_slots;
+        if index < PLAYER_SLOTS {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn next_action_number(&mut self) -> i16 {
+        self.next_action_number = self.next_action_number.wrapping_add(1);
+        self.next_action_number
+    }
+}
+
+/// Window contents maintained from `PlayToClient` packets
+#[derive(Debug, Clone)]
+pub struct Inventory {
+    windows: HashMap<u8, Window>,
+    cursor: Option<Slot>,
+    held_slot: u8,
+    pending: HashSet<(u8, i16)>,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        let mut windows = HashMap::new();
+        windows.insert(PLAYER_WINDOW, Window::player());
+        Inventory {
+            windows,
+            cursor: None,
+            held_slot: 0,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// Apply a packet. Returns the reply the client must send, if any.
+    pub fn handle(&mut self, packet: &PlayToClient) -> Option<PlayToServer> {
+        match packet {
+            PlayToClient::OpenWindow {
+                window_id,
+                inventory_type,
+                window_title,
+                slots,
+                entity_id,
+                ..
+            } => {
+                // only one container is open at a time, opening one closes the previous
+                let open: Vec<u8> = self
+                    .windows
+                    .keys()
+                    .filter(|id| **id != PLAYER_WINDOW)
+                    .cloned()
+                    .collect();
+                for id in open {
+                    self.forget(id);
+                }
+                let inventory_type = InventoryType::from(*inventory_type);
+                let mut window = Window::new(
+                    *window_id,
+                    WindowKind::Container(inventory_type),
+                    window_title.clone(),
+                    inventory_type.window_slots(*slots),
+                );
+                window.entity_id = *entity_id;
+                self.windows.insert(*window_id, window);
+                None
+            }
+            PlayToClient::CloseWindow { window_id } => {
+                self.forget(*window_id);
+                None
+            }
+            PlayToClient::SetSlot {
+                window_id,
+                slot,
+                item,
+            } => {
+                if *window_id == -1 && *slot == -1 {
+                    self.cursor = item.clone();
+                } else {
+                    self.set_slot(*window_id as u8, *slot, item.clone());
+                }
+                None
+            }
+            PlayToClient::WindowItems { window_id, slots } => {
+                for (slot, item) in slots.iter().enumerate() {
+                    self.set_slot(*window_id, slot as i16, item.clone());
+                }
+                None
+            }
+            PlayToClient::WindowProperty {
+                window_id,
+                property,
+                value,
+            } => {
+                if let Some(window) = self.windows.get_mut(&(*window_id as u8)) {
+                    window.properties.insert(*property, *value);
+                }
+                None
+            }
+            PlayToClient::HeldItemChange { slot } => {
+                self.held_slot = *slot as u8;
+                None
+            }
+            PlayToClient::ConfirmTransaction {
+                window_id,
+                action_number,
+                accepted,
+            } => {
+                self.pending.remove(&(*window_id, *action_number));
+                if *accepted {
+                    None
+                } else {
+                    // the server waits for this apology before accepting further clicks
+                    Some(PlayToServer::ConfirmTransaction {
+                        window_id: *window_id as i8,
+                        action_number: *action_number,
+                        accepted: true,
+                    })
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn window(&self, window_id: u8) -> Option<&Window> {
+        self.windows.get(&window_id)
+    }
+
+    pub fn player(&self) -> &Window {
+        &self.windows[&PLAYER_WINDOW]
+    }
+
+    /// Currently open container window
+    pub fn open_window(&self) -> Option<&Window> {
+        self.windows
+            .values()
+            .find(|window| window.id != PLAYER_WINDOW)
+    }
+
+    /// Item held by the mouse cursor
+    pub fn cursor(&self) -> Option<&Slot> {
+        self.cursor.as_ref()
+    }
+
+    /// Selected hotbar slot `0..9`
+    pub fn held_slot(&self) -> u8 {
+        self.held_slot
+    }
+
+    pub fn held_item(&self) -> Option<&Slot> {
+        let player = self.player();
+        player.slot(player.hotbar_slot(self.held_slot as usize))
+    }
+
+    /// Is the click still waiting for its `ConfirmTransaction`
+    pub fn is_pending(&self, window_id: u8, action_number: i16) -> bool {
+        self.pending.contains(&(window_id, action_number))
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Build the `ClickWindow` packet for a click, `None` if the window is not open
+    pub fn click(&mut self, window_id: u8, slot: i16, click: Click) -> Option<PlayToServer> {
+        let window = self.windows.get_mut(&window_id)?;
+        let (mode, button) = click.mode_and_button();
+        let clicked_item = window.slot(slot).cloned();
+        let action_number = window.next_action_number();
+        self.pending.insert((window_id, action_number));
+        Some(PlayToServer::ClickWindow {
+            window_id,
+            slot,
+            button,
+            action_number,
+            mode,
+            clicked_item,
+        })
+    }
+
+    /// Build the `ClickWindow` sequence spreading the cursor stack over `slots`.
+    ///
+    /// A left drag splits the stack evenly, a right drag places one item per slot.
+    pub fn drag(&mut self, window_id: u8, slots: &[i16], right: bool) -> Option<Vec<PlayToServer>> {
+        let window = self.windows.get_mut(&window_id)?;
+        let offset = if right { 4 } else { 0 };
+        let mut stages = Vec::with_capacity(slots.len() + 2);
+        stages.push((OUTSIDE_SLOT, offset));
+        stages.extend(slots.iter().map(|slot| (*slot, offset + 1)));
+        stages.push((OUTSIDE_SLOT, offset + 2));
+
+        let mut packets = Vec::with_capacity(stages.len());
+        for (slot, button) in stages {
+            let action_number = window.next_action_number();
+            self.pending.insert((window_id, action_number));
+            packets.push(PlayToServer::ClickWindow {
+                window_id,
+                slot,
+                button,
+                action_number,
+                mode: 5,
+                clicked_item: None,
+            });
+        }
+        Some(packets)
+    }
+
+    /// Close a window from the client side
+    pub fn close(&mut self, window_id: u8) -> PlayToServer {
+        self.forget(window_id);
+        PlayToServer::CloseWindow { window_id }
+    }
+
+    fn forget(&mut self, window_id: u8) {
+        if window_id != PLAYER_WINDOW {
+            self.windows.remove(&window_id);
+        }
+        self.pending.retain(|(id, _)| *id != window_id);
+    }
+
+    fn set_slot(&mut self, window_id: u8, slot: i16, item: Option<Slot>) {
+        let mirror = match self.windows.get_mut(&window_id) {
+            Some(window) => {
+                window.set_slot(slot, item.clone());
+                window.player_index(slot)
+            }
+            None => return,
+        };
+        // the player inventory is shared by every window, keep it in sync
+        if let Some(index) = mirror {
+            for window in self.windows.values_mut() {
+                if window.id != window_id {
+                    let slot = window.main_inventory_slot(index);
+                    window.set_slot(slot, item.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Inventory::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_mcproto::types::array::ShortPrefix;
+
+    fn item(id: i16, count: u8) -> Option<Slot> {
+        Some(Slot {
+            id,
+            count,
+            damage: 0,
+            tag: None,
+        })
+    }
+
+    fn open(inventory: &mut Inventory, window_id: u8, inventory_type: InventoryType, slots: u8) {
+        let packet = PlayToClient::OpenWindow {
+            window_id,
+            inventory_type: inventory_type.into(),
+            window_title: format!("window {}", window_id),
+            slots,
+            use_provided_title: true,
+            entity_id: None,
+        };
+        assert!(inventory.handle(&packet).is_none());
+    }
+
+    fn set_slot(inventory: &mut Inventory, window_id: i8, slot: i16, item: Option<Slot>) {
+        let packet = PlayToClient::SetSlot {
+            window_id,
+            slot,
+            item,
+        };
+        assert!(inventory.handle(&packet).is_none());
+    }
+
+    fn item_id(window: &Window, slot: i16) -> Option<i16> {
+        window.slot(slot).map(|item| item.id)
+    }
+
+    #[test]
+    fn opening_a_window_closes_the_previous_one() {
+        let mut inventory = Inventory::new();
+        open(&mut inventory, 1, InventoryType::Chest, 27);
+        inventory.click(1, 0, Click::Left).unwrap();
+        open(&mut inventory, 2, InventoryType::Furnace, 3);
+
+        assert!(inventory.window(1).is_none());
+        assert_eq!(inventory.pending_count(), 0);
+        let window = inventory.open_window().unwrap();
+        assert_eq!(window.id, 2);
+        assert_eq!(window.kind, WindowKind::Container(InventoryType::Furnace));
+        assert_eq!(window.slot_count(), 3 + 36);
+        assert!(inventory.player().slot_count() > 0);
+
+        assert!(inventory.handle(&PlayToClient::CloseWindow { window_id: 2 }).is_none());
+        assert!(inventory.open_window().is_none());
+    }
+
+    #[test]
+    fn player_slots_are_mirrored() {
+        let mut inventory = Inventory::new();
+        open(&mut inventory, 1, InventoryType::Chest, 27);
+
+        // first main inventory slot of the chest window, 9 in the player window
+        set_slot(&mut inventory, 1, 27, item(1, 64));
+        assert_eq!(item_id(inventory.player(), 9), Some(1));
+        // first hotbar slot of the player window, 27 + 27 in the chest window
+        set_slot(&mut inventory, 0, 36, item(2, 1));
+        assert_eq!(item_id(inventory.window(1).unwrap(), 54), Some(2));
+        assert_eq!(inventory.held_item().map(|item| item.id), Some(2));
+        // chest slots stay in the chest
+        set_slot(&mut inventory, 1, 3, item(3, 1));
+        assert!(inventory.player().slots().iter().flatten().all(|item| item.id != 3));
+
+        let packet = PlayToClient::WindowItems {
+            window_id: 0,
+            slots: ShortPrefix::new(vec![None; 45]),
+        };
+        assert!(inventory.handle(&packet).is_none());
+        assert_eq!(item_id(inventory.window(1).unwrap(), 27), None);
+        assert_eq!(item_id(inventory.window(1).unwrap(), 3), Some(3));
+
+        set_slot(&mut inventory, -1, -1, item(4, 2));
+        assert_eq!(inventory.cursor().map(|item| item.count), Some(2));
+    }
+
+    #[test]
+    fn clicks_wait_for_their_confirmation() {
+        let mut inventory = Inventory::new();
+        set_slot(&mut inventory, 0, 9, item(1, 16));
+        assert!(inventory.click(1, 0, Click::Left).is_none());
+
+        match inventory.click(0, 9, Click::ShiftRight) {
+            Some(PlayToServer::ClickWindow {
+                window_id: 0,
+                slot: 9,
+                button: 1,
+                action_number: 1,
+                mode: 1,
+                clicked_item: Some(item),
+            }) => assert_eq!((item.id, item.count), (1, 16)),
+            other => panic!("unexpected click {:?}", other),
+        }
+        match inventory.click(0, 10, Click::NumberKey(3)) {
+            Some(PlayToServer::ClickWindow {
+                button: 3,
+                action_number: 2,
+                mode: 2,
+                clicked_item: None,
+                ..
+            }) => {}
+            other => panic!("unexpected click {:?}", other),
+        }
+        assert!(inventory.is_pending(0, 1) && inventory.is_pending(0, 2));
+
+        let accepted = PlayToClient::ConfirmTransaction {
+            window_id: 0,
+            action_number: 1,
+            accepted: true,
+        };
+        assert!(inventory.handle(&accepted).is_none());
+        assert!(!inventory.is_pending(0, 1));
+
+        let rejected = PlayToClient::ConfirmTransaction {
+            window_id: 0,
+            action_number: 2,
+            accepted: false,
+        };
+        match inventory.handle(&rejected) {
+            Some(PlayToServer::ConfirmTransaction {
+                window_id: 0,
+                action_number: 2,
+                accepted: true,
+            }) => {}
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(inventory.pending_count(), 0);
+    }
+
+    #[test]
+    fn drag_stages() {
+        let mut inventory = Inventory::new();
+        assert!(inventory.drag(1, &[1, 2], false).is_none());
+        open(&mut inventory, 1, InventoryType::Chest, 27);
+
+        let stages = |packets: Vec<PlayToServer>| -> Vec<(i16, i8, i16)> {
+            packets
+                .into_iter()
+                .map(|packet| match packet {
+                    PlayToServer::ClickWindow {
+                        window_id: 1,
+                        slot,
+                        button,
+                        action_number,
+                        mode: 5,
+                        clicked_item: None,
+                    } => (slot, button, action_number),
+                    other => panic!("unexpected drag packet {:?}", other),
+                })
+                .collect()
+        };
+        let left = stages(inventory.drag(1, &[3, 4], false).unwrap());
+        assert_eq!(left, vec![(OUTSIDE_SLOT, 0, 1), (3, 1, 2), (4, 1, 3), (OUTSIDE_SLOT, 2, 4)]);
+        let right = stages(inventory.drag(1, &[5], true).unwrap());
+        assert_eq!(right, vec![(OUTSIDE_SLOT, 4, 5), (5, 5, 6), (OUTSIDE_SLOT, 6, 7)]);
+        assert_eq!(inventory.pending_count(), 7);
+
+        match inventory.close(1) {
+            PlayToServer::CloseWindow { window_id: 1 } => {}
+            other => panic!("unexpected close {:?}", other),
+        }
+        assert_eq!(inventory.pending_count(), 0);
+    }
+}
@@ -1,17 +1,37 @@
 //! MC Protocol packets
 
-use serde::{Deserialize, Serialize};
+use serde::de::{SeqAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::ops::Deref;
 use serde_mcproto::types::array::{BytePrefix, IntPrefix, ShortPrefix, VarIntPrefix};
 use serde_mcproto::types::chat::Chat;
 use serde_mcproto::types::chunk::ChunkDataBulk;
 use serde_mcproto::types::entity_metadata::EntityMetadata;
 use serde_mcproto::types::nbt::GZIPNBT;
 use serde_mcproto::types::slot::Slot;
-use serde_mcproto::types::uuid::{Uuid, Uuidi128};
+use serde_mcproto::types::string::{BoundedString, Username};
+use serde_mcproto::types::uuid::{Uuid, UuidBinary};
 use serde_mcproto::types::var::VarInt;
 
+pub mod chunk;
+pub mod entity;
+pub mod inventory;
+pub mod scoreboard;
+pub mod world;
+
 pub const PROTOCOL_VERSION: VarInt = VarInt(5);
 
+impl_packets! {
+    Handshake: Handshake ToServer,
+    StatusToServer: Status ToServer,
+    StatusToClient: Status ToClient,
+    LoginToServer: Login ToServer,
+    LoginToClient: Login ToClient,
+    PlayToServer: Play ToServer,
+    PlayToClient: Play ToClient,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NextState {
     None,
@@ -25,11 +45,125 @@ pub enum NextState {
 // 00 FF 00 00 - y
 // 00 00 FF F0 - block_id
 // 00 00 00 0F - block_metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Block change of a `MultiBlockChange`, `x` and `z` are relative to the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BlockChangeRecord {
-    xz: u8,
-    y: u8,
-    block_id: VarInt,
+    pub x: u8,
+    pub z: u8,
+    pub y: u8,
+    pub block_id: u16,
+    pub metadata: u8,
+}
+
+impl From<u32> for BlockChangeRecord {
+    fn from(v: u32) -> Self {
+        BlockChangeRecord {
+            x: (v >> 28) as u8,
+            z: ((v >> 24) & 0xF) as u8,
+            y: ((v >> 16) & 0xFF) as u8,
+            block_id: ((v >> 4) & 0xFFF) as u16,
+            metadata: (v & 0xF) as u8,
+        }
+    }
+}
+
+impl From<BlockChangeRecord> for u32 {
+    fn from(v: BlockChangeRecord) -> Self {
+        (v.x as u32 & 0xF) << 28
+            | (v.z as u32 & 0xF) << 24
+            | (v.y as u32) << 16
+            | (v.block_id as u32 & 0xFFF) << 4
+            | (v.metadata as u32 & 0xF)
+    }
+}
+
+/// Record count, byte length and packed records of a `MultiBlockChange`
+#[derive(Debug, Clone)]
+pub struct BlockChangeRecords(Vec<BlockChangeRecord>);
+
+impl BlockChangeRecords {
+    pub fn new(records: Vec<BlockChangeRecord>) -> Self {
+        BlockChangeRecords(records)
+    }
+
+    pub fn into_inner(self) -> Vec<BlockChangeRecord> {
+        self.0
+    }
+}
+
+impl Deref for BlockChangeRecords {
+    type Target = Vec<BlockChangeRecord>;
+
+    fn deref(&self) -> &Vec<BlockChangeRecord> {
+        &self.0
+    }
+}
+
+impl Serialize for BlockChangeRecords {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let count = i16::try_from(self.0.len()).map_err(|_| {
+            serde::ser::Error::custom(format!("{} block change records do not fit a short count", self.0.len()))
+        })?;
+        let mut bytes: Vec<u8> = Vec::with_capacity(6 + self.0.len() * 4);
+        bytes.extend_from_slice(&count.to_be_bytes());
+        bytes.extend_from_slice(&((self.0.len() * 4) as i32).to_be_bytes());
+        for record in &self.0 {
+            bytes.extend_from_slice(&u32::from(*record).to_be_bytes());
+        }
+        serializer.serialize_bytes(bytes.as_slice())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockChangeRecords {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RecordsVisitor;
+        impl<'de> Visitor<'de> for RecordsVisitor {
+            type Value = BlockChangeRecords;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("block change records")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let count: i16 = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+                if count < 0 {
+                    return Err(serde::de::Error::invalid_value(
+                        Unexpected::Signed(count as i64),
+                        &"a record count of at least 0",
+                    ));
+                }
+                let len = count as usize * 4;
+                let data: IntPrefix<u8> = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+                if data.len() != len {
+                    return Err(serde::de::Error::custom(format!(
+                        "{} block change records need {} bytes, got {}",
+                        count,
+                        len,
+                        data.len()
+                    )));
+                }
+                let records = data
+                    .chunks(4)
+                    .map(|b| BlockChangeRecord::from(u32::from_be_bytes([b[0], b[1], b[2], b[3]])))
+                    .collect();
+                Ok(BlockChangeRecords(records))
+            }
+        }
+        deserializer.deserialize_tuple(2, RecordsVisitor)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,7 +205,7 @@ pub struct Version {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModifierData {
-    uuid: Uuidi128,
+    uuid: UuidBinary,
     amount: f64,
     operation: i8,
 }
@@ -92,17 +226,17 @@ pub struct Data {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectData {
-    object_id: i32,
-    velocity_x: i16,
-    velocity_y: i16,
-    velocity_z: i16,
+    pub object_id: i32,
+    pub velocity_x: i16,
+    pub velocity_y: i16,
+    pub velocity_z: i16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Handshake {
     Handshake {
         proto_version: VarInt,
-        server_address: String,
+        server_address: BoundedString<255>,
         server_port: u16,
         next_state: NextState,
     },
@@ -110,6 +244,40 @@ pub enum Handshake {
 
 type MapData = Vec<u8>;
 
+/// Mode byte of `UpdateScore` followed by its fields.
+///
+/// Modes are bytes on the wire, variant indexes below 128 encode to the same single VarInt byte.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ScoreAction {
+    Update { score_name: String, value: i32 },
+    Remove,
+}
+
+/// Mode byte of `UpdateTeam` followed by its fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TeamAction {
+    Create {
+        display_name: String,
+        prefix: String,
+        suffix: String,
+        friendly_fire: i8,
+        players: ShortPrefix<String>,
+    },
+    Remove,
+    UpdateInfo {
+        display_name: String,
+        prefix: String,
+        suffix: String,
+        friendly_fire: i8,
+    },
+    AddPlayers {
+        players: ShortPrefix<String>,
+    },
+    RemovePlayers {
+        players: ShortPrefix<String>,
+    },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PlayToClient {
     KeepAlive {
@@ -294,10 +462,8 @@ pub enum PlayToClient {
     MultiBlockChange {
         chunk_x: i32,
         chunk_z: i32,
-        records: i16,
-        data: IntPrefix<u8>,
+        records: BlockChangeRecords,
     },
-    //TODO parse data
     BlockChange {
         x: i32,
         y: i8,
@@ -436,9 +602,7 @@ pub enum PlayToClient {
     },
     UpdateScore {
         item_name: String,
-        action: i8,
-        score_name: String,
-        value: i32,
+        action: ScoreAction,
     },
     DisplayScoreboard {
         position: i8,
@@ -446,11 +610,10 @@ pub enum PlayToClient {
     },
     UpdateTeam {
         team_name: String,
-        action: Vec<u8>,
+        action: TeamAction,
     },
-    //TODO implement TeamAction
     PluginMessage {
-        channel: String,
+        channel: BoundedString<20>,
         data: ShortPrefix<u8>,
     },
     Disconnect {
@@ -473,7 +636,7 @@ pub enum PlayToServer {
         keep_alive_id: i32,
     },
     ChatMessage {
-        message: String,
+        message: BoundedString<100>,
     },
     UseEntity {
         target_eid: i32,
@@ -566,10 +729,10 @@ pub enum PlayToServer {
         x: i32,
         y: i16,
         z: i32,
-        line0: String,
-        line1: String,
-        line2: String,
-        line3: String,
+        line0: BoundedString<15>,
+        line1: BoundedString<15>,
+        line2: BoundedString<15>,
+        line3: BoundedString<15>,
     },
     PlayerAbilities {
         flags: i8,
@@ -628,10 +791,47 @@ pub enum LoginToClient {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LoginToServer {
     LoginStart {
-        name: String,
+        name: Username,
     },
     EncryptionResponse {
         shared_secret: ShortPrefix<u8>,
         verify_token: ShortPrefix<u8>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MCProtoDeserializer, MCProtoSerializer};
+
+    fn decode(bytes: &[u8]) -> crate::error::Result<BlockChangeRecords> {
+        let mut reader = bytes;
+        BlockChangeRecords::deserialize(&mut MCProtoDeserializer::new(&mut reader))
+    }
+
+    #[test]
+    fn block_change_records() {
+        let record = BlockChangeRecord {
+            x: 3,
+            z: 12,
+            y: 64,
+            block_id: 0x123,
+            metadata: 5,
+        };
+        assert_eq!(u32::from(record), 0x3c40_1235);
+        assert_eq!(BlockChangeRecord::from(0x3c40_1235), record);
+
+        let records = BlockChangeRecords::new(vec![record, BlockChangeRecord::from(0xffff_ffff)]);
+        let mut bytes = Vec::new();
+        records.serialize(&mut MCProtoSerializer::new(&mut bytes)).unwrap();
+        assert_eq!(&bytes[..6], &[0, 2, 0, 0, 0, 8]);
+        assert_eq!(&bytes[6..10], &[0x3c, 0x40, 0x12, 0x35]);
+        assert_eq!(decode(&bytes).unwrap().into_inner(), records.into_inner());
+
+        // count and byte length disagree
+        assert!(decode(&[0, 2, 0, 0, 0, 4, 0, 0, 0, 0]).is_err());
+        // negative count
+        assert!(decode(&[0xff, 0xff, 0, 0, 0, 0]).is_err());
+        assert!(decode(&[0x80, 0x00, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,472 @@
+//! Tab list, scoreboard and team state driven by their packets
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::{PlayToClient, ScoreAction, TeamAction};
+
+/// Players shown in the tab list with their ping
+#[derive(Debug, Clone, Default)]
+pub struct PlayerList {
+    players: HashMap<String, i16>,
+}
+
+impl PlayerList {
+    pub fn new() -> PlayerList {
+        PlayerList::default()
+    }
+
+    /// Apply a packet
+    pub fn handle(&mut self, packet: &PlayToClient) {
+        if let PlayToClient::UpdatePlayerList {
+            player_name,
+            online,
+            ping,
+        } = packet
+        {
+            if *online {
+                self.players.insert(player_name.clone(), *ping);
+            } else {
+                self.players.remove(player_name);
+            }
+        }
+    }
+
+    pub fn ping(&self, player_name: &str) -> Option<i16> {
+        self.players.get(player_name).cloned()
+    }
+
+    pub fn contains(&self, player_name: &str) -> bool {
+        self.players.contains_key(player_name)
+    }
+
+    /// Players and their ping, sorted by name
+    pub fn players(&self) -> Vec<(&str, i16)> {
+        let mut players: Vec<(&str, i16)> = self
+            .players
+            .iter()
+            .map(|(name, ping)| (name.as_str(), *ping))
+            .collect();
+        players.sort();
+        players
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+}
+
+/// `position` of `DisplayScoreboard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisplaySlot {
+    List,
+    Sidebar,
+    BelowName,
+    Unknown(i8),
+}
+
+impl From<i8> for DisplaySlot {
+    fn from(v: i8) -> Self {
+        match v {
+            0 => DisplaySlot::List,
+            1 => DisplaySlot::Sidebar,
+            2 => DisplaySlot::BelowName,
+            other => DisplaySlot::Unknown(other),
+        }
+    }
+}
+
+impl From<DisplaySlot> for i8 {
+    fn from(v: DisplaySlot) -> Self {
+        match v {
+            DisplaySlot::List => 0,
+            DisplaySlot::Sidebar => 1,
+            DisplaySlot::BelowName => 2,
+            DisplaySlot::Unknown(other) => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Objective {
+    pub name: String,
+    pub display_text: String,
+    scores: HashMap<String, i32>,
+}
+
+impl Objective {
+    pub fn score(&self, item_name: &str) -> Option<i32> {
+        self.scores.get(item_name).cloned()
+    }
+
+    /// Scores sorted the way the sidebar shows them, highest first
+    pub fn scores(&self) -> Vec<(&str, i32)> {
+        let mut scores: Vec<(&str, i32)> = self
+            .scores
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect();
+        scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        scores
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Team {
+    pub name: String,
+    pub display_name: String,
+    pub prefix: String,
+    pub suffix: String,
+    /// Bit 0: friendly fire, bit 1: see invisible teammates
+    pub friendly_fire: i8,
+    pub players: BTreeSet<String>,
+}
+
+impl Team {
+    pub fn allows_friendly_fire(&self) -> bool {
+        self.friendly_fire & 0x1 != 0
+    }
+
+    pub fn sees_invisible_teammates(&self) -> bool {
+        self.friendly_fire & 0x2 != 0
+    }
+
+    /// Player name decorated with the team prefix and suffix
+    pub fn format(&self, player_name: &str) -> String {
+        format!("{}{}{}", self.prefix, player_name, self.suffix)
+    }
+}
+
+/// Objectives, scores, display slots and teams
+#[derive(Debug, Clone, Default)]
+pub struct Scoreboard {
+    objectives: HashMap<String, Objective>,
+    display: HashMap<DisplaySlot, String>,
+    teams: HashMap<String, Team>,
+}
+
+impl Scoreboard {
+    pub fn new() -> Scoreboard {
+        Scoreboard::default()
+    }
+
+    /// Apply a packet
+    pub fn handle(&mut self, packet: &PlayToClient) {
+        match packet {
+            PlayToClient::ScoreboardObjective {
+                name,
+                display_text,
+                action,
+            } => match action {
+                0 => {
+                    self.objectives.insert(
+                        name.clone(),
+                        Objective {
+                            name: name.clone(),
+                            display_text: display_text.clone(),
+                            scores: HashMap::new(),
+                        },
+                    );
+                }
+                1 => {
+                    self.objectives.remove(name);
+                    self.display.retain(|_, objective| objective != name);
+                }
+                2 => {
+                    if let Some(objective) = self.objectives.get_mut(name) {
+                        objective.display_text = display_text.clone();
+                    }
+                }
+                _ => {}
+            },
+            PlayToClient::UpdateScore { item_name, action } => match action {
+                ScoreAction::Update { score_name, value } => {
+                    if let Some(objective) = self.objectives.get_mut(score_name) {
+                        objective.scores.insert(item_name.clone(), *value);
+                    }
+                }
+                ScoreAction::Remove => {
+                    for objective in self.objectives.values_mut() {
+                        objective.scores.remove(item_name);
+                    }
+                }
+            },
+            PlayToClient::DisplayScoreboard { position, name } => {
+                let slot = DisplaySlot::from(*position);
+                if name.is_empty() {
+                    self.display.remove(&slot);
+                } else {
+                    self.display.insert(slot, name.clone());
+                }
+            }
+            PlayToClient::UpdateTeam { team_name, action } => match action {
+                TeamAction::Create {
+                    display_name,
+                    prefix,
+                    suffix,
+                    friendly_fire,
+                    players,
+                } => {
+                    let team = Team {
+                        name: team_name.clone(),
+                        display_name: display_name.clone(),
+                        prefix: prefix.clone(),
+                        suffix: suffix.clone(),
+                        friendly_fire: *friendly_fire,
+                        players: BTreeSet::new(),
+                    };
+                    self.teams.insert(team_name.clone(), team);
+                    self.add_players(team_name, players);
+                }
+                TeamAction::Remove => {
+                    self.teams.remove(team_name);
+                }
+                TeamAction::UpdateInfo {
+                    display_name,
+                    prefix,
+                    suffix,
+                    friendly_fire,
+                } => {
+                    if let Some(team) = self.teams.get_mut(team_name) {
+                        team.display_name = display_name.clone();
+                        team.prefix = prefix.clone();
+                        team.suffix = suffix.clone();
+                        team.friendly_fire = *friendly_fire;
+                    }
+                }
+                TeamAction::AddPlayers { players } => self.add_players(team_name, players),
+                TeamAction::RemovePlayers { players } => {
+                    if let Some(team) = self.teams.get_mut(team_name) {
+                        for player in players.iter() {
+                            team.players.remove(player);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    pub fn objective(&self, name: &str) -> Option<&Objective> {
+        self.objectives.get(name)
+    }
+
+    pub fn objectives(&self) -> impl Iterator<Item = &Objective> {
+        self.objectives.values()
+    }
+
+    /// Objective shown in a display slot
+    pub fn displayed(&self, slot: DisplaySlot) -> Option<&Objective> {
+        self.display
+            .get(&slot)
+            .and_then(|name| self.objectives.get(name))
+    }
+
+    pub fn team(&self, name: &str) -> Option<&Team> {
+        self.teams.get(name)
+    }
+
+    pub fn teams(&self) -> impl Iterator<Item = &Team> {
+        self.teams.values()
+    }
+
+    /// Team the player belongs to
+    pub fn team_of(&self, player_name: &str) -> Option<&Team> {
+        self.teams
+            .values()
+            .find(|team| team.players.contains(player_name))
+    }
+
+    fn add_players(&mut self, team_name: &str, players: &[String]) {
+        // a player belongs to a single team
+        for team in self.teams.values_mut() {
+            for player in players {
+                team.players.remove(player);
+            }
+        }
+        if let Some(team) = self.teams.get_mut(team_name) {
+            team.players.extend(players.iter().cloned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_mcproto::types::array::ShortPrefix;
+
+    fn objective(name: &str, display_text: &str, action: i8) -> PlayToClient {
+        PlayToClient::ScoreboardObjective {
+            name: name.to_owned(),
+            display_text: display_text.to_owned(),
+            action,
+        }
+    }
+
+    fn score(item_name: &str, score_name: &str, value: i32) -> PlayToClient {
+        PlayToClient::UpdateScore {
+            item_name: item_name.to_owned(),
+            action: ScoreAction::Update {
+                score_name: score_name.to_owned(),
+                value,
+            },
+        }
+    }
+
+    fn team(team_name: &str, action: TeamAction) -> PlayToClient {
+        PlayToClient::UpdateTeam {
+            team_name: team_name.to_owned(),
+            action,
+        }
+    }
+
+    fn players(names: &[&str]) -> ShortPrefix<String> {
+        ShortPrefix::new(names.iter().map(|name| (*name).to_owned()).collect())
+    }
+
+    fn members(scoreboard: &Scoreboard, team_name: &str) -> Vec<String> {
+        scoreboard.team(team_name).unwrap().players.iter().cloned().collect()
+    }
+
+    #[test]
+    fn scores() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.handle(&objective("kills", "Kills", 0));
+        scoreboard.handle(&objective("deaths", "Deaths", 0));
+        scoreboard.handle(&score("alice", "kills", 3));
+        scoreboard.handle(&score("bob", "kills", 5));
+        scoreboard.handle(&score("carol", "kills", 3));
+        scoreboard.handle(&score("alice", "deaths", 1));
+        scoreboard.handle(&score("alice", "unknown", 1));
+        scoreboard.handle(&score("alice", "kills", 4));
+
+        let kills = scoreboard.objective("kills").unwrap();
+        assert_eq!(kills.scores(), vec![("bob", 5), ("alice", 4), ("carol", 3)]);
+
+        // removing a score removes it from every objective
+        scoreboard.handle(&PlayToClient::UpdateScore {
+            item_name: "alice".to_owned(),
+            action: ScoreAction::Remove,
+        });
+        assert_eq!(scoreboard.objective("kills").unwrap().score("alice"), None);
+        assert_eq!(scoreboard.objective("deaths").unwrap().score("alice"), None);
+        assert_eq!(scoreboard.objective("kills").unwrap().score("bob"), Some(5));
+
+        scoreboard.handle(&objective("kills", "Frags", 2));
+        assert_eq!(scoreboard.objective("kills").unwrap().display_text, "Frags");
+    }
+
+    #[test]
+    fn display_slots() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.handle(&objective("kills", "Kills", 0));
+        scoreboard.handle(&PlayToClient::DisplayScoreboard {
+            position: 1,
+            name: "kills".to_owned(),
+        });
+        assert_eq!(scoreboard.displayed(DisplaySlot::Sidebar).unwrap().name, "kills");
+        assert!(scoreboard.displayed(DisplaySlot::List).is_none());
+
+        scoreboard.handle(&objective("kills", "", 1));
+        assert!(scoreboard.objective("kills").is_none());
+        // recreating the objective does not display it again
+        scoreboard.handle(&objective("kills", "Kills", 0));
+        assert!(scoreboard.displayed(DisplaySlot::Sidebar).is_none());
+
+        scoreboard.handle(&PlayToClient::DisplayScoreboard {
+            position: 2,
+            name: "kills".to_owned(),
+        });
+        scoreboard.handle(&PlayToClient::DisplayScoreboard {
+            position: 2,
+            name: String::new(),
+        });
+        assert!(scoreboard.displayed(DisplaySlot::BelowName).is_none());
+        assert_eq!(DisplaySlot::from(5), DisplaySlot::Unknown(5));
+        assert_eq!(i8::from(DisplaySlot::BelowName), 2);
+    }
+
+    #[test]
+    fn team_modes() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.handle(&team(
+            "red",
+            TeamAction::Create {
+                display_name: "Red".to_owned(),
+                prefix: "[R] ".to_owned(),
+                suffix: "!".to_owned(),
+                friendly_fire: 0x1,
+                players: players(&["alice", "bob"]),
+            },
+        ));
+        let red = scoreboard.team("red").unwrap();
+        assert_eq!(red.format("alice"), "[R] alice!");
+        assert!(red.allows_friendly_fire() && !red.sees_invisible_teammates());
+        assert_eq!(members(&scoreboard, "red"), vec!["alice", "bob"]);
+
+        scoreboard.handle(&team(
+            "red",
+            TeamAction::UpdateInfo {
+                display_name: "Reds".to_owned(),
+                prefix: String::new(),
+                suffix: String::new(),
+                friendly_fire: 0x2,
+            },
+        ));
+        let red = scoreboard.team("red").unwrap();
+        assert_eq!((red.display_name.as_str(), red.format("bob")), ("Reds", "bob".to_owned()));
+        assert!(!red.allows_friendly_fire() && red.sees_invisible_teammates());
+        // updating the info keeps the players
+        assert_eq!(members(&scoreboard, "red"), vec!["alice", "bob"]);
+
+        // joining a team leaves the previous one
+        scoreboard.handle(&team(
+            "blue",
+            TeamAction::Create {
+                display_name: "Blue".to_owned(),
+                prefix: String::new(),
+                suffix: String::new(),
+                friendly_fire: 0,
+                players: players(&["carol"]),
+            },
+        ));
+        scoreboard.handle(&team("blue", TeamAction::AddPlayers { players: players(&["bob"]) }));
+        assert_eq!(members(&scoreboard, "red"), vec!["alice"]);
+        assert_eq!(members(&scoreboard, "blue"), vec!["bob", "carol"]);
+        assert_eq!(scoreboard.team_of("bob").unwrap().name, "blue");
+
+        scoreboard.handle(&team(
+            "blue",
+            TeamAction::RemovePlayers {
+                players: players(&["carol", "dave"]),
+            },
+        ));
+        assert_eq!(members(&scoreboard, "blue"), vec!["bob"]);
+        assert!(scoreboard.team_of("carol").is_none());
+
+        scoreboard.handle(&team("red", TeamAction::Remove));
+        assert!(scoreboard.team("red").is_none());
+        assert!(scoreboard.team_of("alice").is_none());
+        assert_eq!(scoreboard.teams().count(), 1);
+    }
+
+    #[test]
+    fn player_list() {
+        let mut list = PlayerList::new();
+        let update = |player_name: &str, online, ping| PlayToClient::UpdatePlayerList {
+            player_name: player_name.to_owned(),
+            online,
+            ping,
+        };
+        list.handle(&update("bob", true, 120));
+        list.handle(&update("alice", true, 30));
+        list.handle(&update("bob", true, 80));
+        assert_eq!(list.players(), vec![("alice", 30), ("bob", 80)]);
+        list.handle(&update("alice", false, 0));
+        assert!(!list.contains("alice"));
+        assert_eq!((list.len(), list.ping("bob")), (1, Some(80)));
+    }
+}
@@ -0,0 +1,375 @@
+//! Client side world state driven by chunk and block packets
+
+use std::collections::HashMap;
+
+use crate::error::Result;
+
+use super::chunk::{Block, ChunkColumn};
+use super::PlayToClient;
+
+/// Change made to the world by a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldEvent {
+    ChunkLoaded {
+        x: i32,
+        z: i32,
+    },
+    ChunkUnloaded {
+        x: i32,
+        z: i32,
+    },
+    /// A loaded chunk received new section data without being replaced
+    ChunkUpdated {
+        x: i32,
+        z: i32,
+    },
+    BlockChanged {
+        position: (i32, i32, i32),
+        old: Block,
+        new: Block,
+    },
+}
+
+/// Loaded chunk columns keyed by chunk coordinates
+#[derive(Debug, Clone, Default)]
+pub struct World {
+    columns: HashMap<(i32, i32), ChunkColumn>,
+    dimension: i8,
+}
+
+impl World {
+    pub fn new() -> World {
+        World::default()
+    }
+
+    /// Apply a packet, returns what changed
+    pub fn handle(&mut self, packet: &PlayToClient) -> Result<Vec<WorldEvent>> {
+        let mut events = Vec::new();
+        match packet {
+            PlayToClient::JoinGame { dimension, .. } => {
+                self.dimension = *dimension;
+            }
+            PlayToClient::Respawn { dimension, .. } => {
+                self.dimension = *dimension;
+                events.extend(
+                    self.columns
+                        .drain()
+                        .map(|((x, z), _)| WorldEvent::ChunkUnloaded { x, z }),
+                );
+            }
+            PlayToClient::ChunkData {
+                x,
+                z,
+                ground_up,
+                bit_map,
+                add_bit_map,
+                chunk_data,
+            } => {
+                if *ground_up && *bit_map == 0 {
+                    if self.columns.remove(&(*x, *z)).is_some() {
+                        events.push(WorldEvent::ChunkUnloaded { x: *x, z: *z });
+                    }
+                } else if *ground_up {
+                    let column =
+                        ChunkColumn::from_chunk_data(*x, *z, true, *bit_map, *add_bit_map, chunk_data)?;
+                    self.columns.insert((*x, *z), column);
+                    events.push(WorldEvent::ChunkLoaded { x: *x, z: *z });
+                } else {
+                    let loaded = self.columns.contains_key(&(*x, *z));
+                    self.columns
+                        .entry((*x, *z))
+                        .or_insert_with(|| ChunkColumn::new(*x, *z))
+                        .read_chunk_data(false, *bit_map, *add_bit_map, chunk_data)?;
+                    events.push(if loaded {
+                        WorldEvent::ChunkUpdated { x: *x, z: *z }
+                    } else {
+                        WorldEvent::ChunkLoaded { x: *x, z: *z }
+                    });
+                }
+            }
+            PlayToClient::ChunkDataBulk(bulk) => {
+                for column in ChunkColumn::from_bulk(bulk)? {
+                    events.push(WorldEvent::ChunkLoaded {
+                        x: column.x,
+                        z: column.z,
+                    });
+                    self.columns.insert((column.x, column.z), column);
+                }
+            }
+            PlayToClient::BlockChange {
+                x,
+                y,
+                z,
+                block_type,
+                metadata,
+            } => {
+                let block = Block::new(block_type.0 as u16, *metadata);
+                events.extend(self.change_block(*x, *y as u8 as i32, *z, block));
+            }
+            PlayToClient::MultiBlockChange {
+                chunk_x,
+                chunk_z,
+                records,
+            } => {
+                for record in records.iter() {
+                    let x = (chunk_x << 4) + record.x as i32;
+                    let z = (chunk_z << 4) + record.z as i32;
+                    let block = Block::new(record.block_id, record.metadata);
+                    events.extend(self.change_block(x, record.y as i32, z, block));
+                }
+            }
+            PlayToClient::Explosion {
+                position, records, ..
+            } => {
+                // truncated towards zero like the `(int)` cast of vanilla
+                let origin = [position[0] as i32, position[1] as i32, position[2] as i32];
+                for record in records.iter() {
+                    let x = origin[0] + record[0] as i32;
+                    let y = origin[1] + record[1] as i32;
+                    let z = origin[2] + record[2] as i32;
+                    events.extend(self.change_block(x, y, z, Block::default()));
+                }
+            }
+            _ => {}
+        }
+        Ok(events)
+    }
+
+    /// Dimension from the last `JoinGame` or `Respawn`
+    pub fn dimension(&self) -> i8 {
+        self.dimension
+    }
+
+    /// Block at a world position, `None` if its chunk is not loaded
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> Option<Block> {
+        if !(0..256).contains(&y) {
+            return None;
+        }
+        self.columns
+            .get(&(x >> 4, z >> 4))
+            .map(|column| column.get_block((x & 0xF) as usize, y as usize, (z & 0xF) as usize))
+    }
+
+    /// Set a block at a world position, returns the previous block if its chunk is loaded
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: Block) -> Option<Block> {
+        if !(0..256).contains(&y) {
+            return None;
+        }
+        let column = self.columns.get_mut(&(x >> 4, z >> 4))?;
+        let (local_x, local_z) = ((x & 0xF) as usize, (z & 0xF) as usize);
+        let old = column.get_block(local_x, y as usize, local_z);
+        column.set_block(local_x, y as usize, local_z, block);
+        Some(old)
+    }
+
+    pub fn column(&self, chunk_x: i32, chunk_z: i32) -> Option<&ChunkColumn> {
+        self.columns.get(&(chunk_x, chunk_z))
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = &ChunkColumn> {
+        self.columns.values()
+    }
+
+    pub fn is_loaded(&self, chunk_x: i32, chunk_z: i32) -> bool {
+        self.columns.contains_key(&(chunk_x, chunk_z))
+    }
+
+    pub fn loaded_chunks(&self) -> usize {
+        self.columns.len()
+    }
+
+    fn change_block(&mut self, x: i32, y: i32, z: i32, block: Block) -> Option<WorldEvent> {
+        let old = self.set_block(x, y, z, block)?;
+        if old == block {
+            return None;
+        }
+        Some(WorldEvent::BlockChanged {
+            position: (x, y, z),
+            old,
+            new: block,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1_7_10::chunk::{ChunkSection, NibbleArray};
+    use crate::v1_7_10::{BlockChangeRecord, BlockChangeRecords};
+    use serde_mcproto::types::array::IntPrefix;
+    use serde_mcproto::types::var::VarInt;
+
+    const STONE: Block = Block { id: 1, metadata: 0 };
+    const AIR: Block = Block { id: 0, metadata: 0 };
+
+    /// World with a chunk of the given blocks, its first section has block light 7 and sky light 4
+    fn world(chunk_x: i32, chunk_z: i32, blocks: &[(usize, usize, usize)]) -> World {
+        let mut column = ChunkColumn::new(chunk_x, chunk_z);
+        for &(x, y, z) in blocks {
+            column.set_block(x, y, z, STONE);
+        }
+        let section = column.sections[0].get_or_insert_with(|| ChunkSection::new(true));
+        section.block_light = NibbleArray::new(7);
+        section.sky_light = Some(NibbleArray::new(4));
+
+        let mut world = World::new();
+        let events = world.handle(&column.to_chunk_data(true, true).unwrap()).unwrap();
+        assert_eq!(events, vec![WorldEvent::ChunkLoaded { x: chunk_x, z: chunk_z }]);
+        world
+    }
+
+    fn light(world: &World, x: i32, y: i32, z: i32) -> (u8, u8) {
+        let column = world.column(x >> 4, z >> 4).unwrap();
+        let (x, y, z) = ((x & 0xF) as usize, y as usize, (z & 0xF) as usize);
+        (column.get_block_light(x, y, z), column.get_sky_light(x, y, z))
+    }
+
+    fn block_change(x: i32, y: i8, z: i32, block: Block) -> PlayToClient {
+        PlayToClient::BlockChange {
+            x,
+            y,
+            z,
+            block_type: VarInt(block.id as i32),
+            metadata: block.metadata,
+        }
+    }
+
+    #[test]
+    fn single_block_change() {
+        let mut world = world(0, 0, &[(1, 1, 1)]);
+        assert_eq!(world.get_block(1, 1, 1), Some(STONE));
+
+        let events = world.handle(&block_change(1, 1, 1, AIR)).unwrap();
+        assert_eq!(
+            events,
+            vec![WorldEvent::BlockChanged {
+                position: (1, 1, 1),
+                old: STONE,
+                new: AIR,
+            }]
+        );
+        assert_eq!(world.get_block(1, 1, 1), Some(AIR));
+        // the emptied section keeps its light
+        assert_eq!(light(&world, 1, 1, 1), (7, 4));
+
+        // same block, and a chunk that is not loaded
+        assert!(world.handle(&block_change(1, 1, 1, AIR)).unwrap().is_empty());
+        assert!(world.handle(&block_change(16, 1, 1, STONE)).unwrap().is_empty());
+        assert_eq!(world.get_block(16, 1, 1), None);
+
+        let glass = Block::new(20, 0);
+        let events = world.handle(&block_change(2, 40, 3, glass)).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(world.get_block(2, 40, 3), Some(glass));
+        // a new section is fully lit by the sky
+        assert_eq!(light(&world, 2, 40, 3), (0, 15));
+    }
+
+    #[test]
+    fn multi_block_change() {
+        let mut world = world(-1, 2, &[(15, 0, 0), (0, 5, 15)]);
+        let record = |x, y, z, block: Block| BlockChangeRecord {
+            x,
+            z,
+            y,
+            block_id: block.id,
+            metadata: block.metadata,
+        };
+        let wool = Block::new(35, 14);
+        let packet = PlayToClient::MultiBlockChange {
+            chunk_x: -1,
+            chunk_z: 2,
+            records: BlockChangeRecords::new(vec![
+                record(15, 0, 0, AIR),
+                record(0, 5, 15, AIR),
+                record(3, 200, 4, wool),
+                record(4, 4, 4, AIR),
+            ]),
+        };
+        let events = world.handle(&packet).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                WorldEvent::BlockChanged {
+                    position: (-1, 0, 32),
+                    old: STONE,
+                    new: AIR,
+                },
+                WorldEvent::BlockChanged {
+                    position: (-16, 5, 47),
+                    old: STONE,
+                    new: AIR,
+                },
+                WorldEvent::BlockChanged {
+                    position: (-13, 200, 36),
+                    old: AIR,
+                    new: wool,
+                },
+            ]
+        );
+        assert_eq!(world.get_block(-1, 0, 32), Some(AIR));
+        assert_eq!(world.get_block(-16, 5, 47), Some(AIR));
+        assert_eq!(world.get_block(-13, 200, 36), Some(wool));
+        assert_eq!(light(&world, -1, 0, 32), (7, 4));
+        assert_eq!(light(&world, -13, 200, 36), (0, 15));
+    }
+
+    #[test]
+    fn explosion_with_negative_origin() {
+        let mut world = world(-1, -1, &[(14, 10, 13), (13, 10, 12)]);
+        let packet = PlayToClient::Explosion {
+            position: [-1.5, 10.2, -2.5],
+            radius: 2.0,
+            // origin (-1, 10, -2) as vanilla truncates, flooring would give (-2, 10, -3)
+            records: IntPrefix::new(vec![[-1, 0, -1], [0, 1, 0]]),
+            player_motion: [0.0; 3],
+        };
+        let events = world.handle(&packet).unwrap();
+        assert_eq!(
+            events,
+            vec![WorldEvent::BlockChanged {
+                position: (-2, 10, -3),
+                old: STONE,
+                new: AIR,
+            }]
+        );
+        assert_eq!(world.get_block(-2, 10, -3), Some(AIR));
+        assert_eq!(world.get_block(-3, 10, -4), Some(STONE));
+        assert_eq!(light(&world, -2, 10, -3), (7, 4));
+    }
+
+    #[test]
+    fn chunk_unload() {
+        let mut world = world(3, -4, &[(0, 0, 0)]);
+        let unload = |x, z| PlayToClient::ChunkData {
+            x,
+            z,
+            ground_up: true,
+            bit_map: 0,
+            add_bit_map: 0,
+            chunk_data: IntPrefix::new(Vec::new()),
+        };
+        // not loaded, nothing happens
+        assert!(world.handle(&unload(0, 0)).unwrap().is_empty());
+        assert!(world.is_loaded(3, -4));
+
+        let events = world.handle(&unload(3, -4)).unwrap();
+        assert_eq!(events, vec![WorldEvent::ChunkUnloaded { x: 3, z: -4 }]);
+        assert!(!world.is_loaded(3, -4));
+        assert_eq!(world.loaded_chunks(), 0);
+        assert_eq!(world.get_block(48, 0, -64), None);
+        assert!(world.handle(&block_change(48, 0, -64, STONE)).unwrap().is_empty());
+
+        let mut world = self::world(1, 1, &[(0, 0, 0)]);
+        let respawn = PlayToClient::Respawn {
+            dimension: -1,
+            difficulty: 0,
+            gamemode: 0,
+            level_type: "default".to_owned(),
+        };
+        let events = world.handle(&respawn).unwrap();
+        assert_eq!(events, vec![WorldEvent::ChunkUnloaded { x: 1, z: 1 }]);
+        assert_eq!(world.dimension(), -1);
+        assert_eq!(world.loaded_chunks(), 0);
+    }
+}
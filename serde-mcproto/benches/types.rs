@@ -0,0 +1,191 @@
+//! Throughput of the serializer and deserializer on the types that dominate play traffic
+//!
+//! `cargo bench -p serde-mcproto`, or `cargo bench -p serde-mcproto -- varint` for one group.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use serde_mcproto::de::MCProtoDeserializer;
+use serde_mcproto::ser::MCProtoSerializer;
+use serde_mcproto::types::array::ShortPrefix;
+use serde_mcproto::types::chunk::{ChunkDataBulk, ChunkMeta};
+use serde_mcproto::types::entity_metadata::EntityMetadata;
+use serde_mcproto::types::slot::Slot;
+use serde_mcproto::types::var::{VarInt, VarLong};
+use serde_mcproto::{read_varint, write_varint};
+
+fn encode<T: Serialize>(value: &T, buffer: &mut Vec<u8>) {
+    buffer.clear();
+    serde_mcproto::ser::serialize(value, &mut MCProtoSerializer { writer: buffer }).unwrap();
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> T {
+    serde_mcproto::de::deserialize(&mut MCProtoDeserializer { reader: bytes }).unwrap()
+}
+
+/// One value per varint length, 1 to 5 bytes
+const VARINTS: [i32; 5] = [1, 300, 100_000, 20_000_000, -1];
+
+fn varint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varint");
+    let mut buffer = Vec::new();
+    for &value in VARINTS.iter() {
+        encode(&VarInt(value), &mut buffer);
+        let bytes = buffer.clone();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("write", bytes.len()), &value, |b, &value| {
+            b.iter(|| {
+                buffer.clear();
+                write_varint(black_box(&value), &mut buffer).unwrap();
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("serialize", bytes.len()), &value, |b, &value| {
+            b.iter(|| encode(&VarInt(black_box(value)), &mut buffer))
+        });
+        group.bench_with_input(BenchmarkId::new("read", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| read_varint(&mut black_box(bytes.as_slice())).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| decode::<VarInt>(black_box(bytes)))
+        });
+    }
+    group.finish();
+}
+
+fn varlong(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varlong");
+    let mut buffer = Vec::new();
+    for &value in [1i64, 100_000, 1 << 40, -1].iter() {
+        encode(&VarLong(value), &mut buffer);
+        let bytes = buffer.clone();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("serialize", bytes.len()), &value, |b, &value| {
+            b.iter(|| encode(&VarLong(black_box(value)), &mut buffer))
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| decode::<VarLong>(black_box(bytes)))
+        });
+    }
+    group.finish();
+}
+
+fn string(c: &mut Criterion) {
+    let mut group = c.benchmark_group("string");
+    let mut buffer = Vec::new();
+    let strings = [
+        ("name", "Notch".to_string()),
+        ("chat", "a".repeat(100)),
+        ("json", "{\"text\":\"Привет, мир\"}".repeat(200)),
+        ("max", "b".repeat(32767)),
+    ];
+    for (name, value) in strings.iter() {
+        encode(value, &mut buffer);
+        let bytes = buffer.clone();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("serialize", name), value, |b, value| {
+            b.iter(|| encode(black_box(value), &mut buffer))
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", name), &bytes, |b, bytes| {
+            b.iter(|| decode::<String>(black_box(bytes)))
+        });
+    }
+    group.finish();
+}
+
+/// Bulk of `columns` full height columns with sky light, as sent when joining
+fn chunk_data_bulk(columns: i32) -> ChunkDataBulk {
+    let metas: Vec<_> = (0..columns)
+        .map(|i| ChunkMeta {
+            x: i % 8,
+            z: i / 8,
+            mask: 0xffff,
+            add_mask: 0,
+        })
+        .collect();
+    let data: Vec<Vec<u8>> = metas
+        .iter()
+        .map(|meta| {
+            (0..meta.data_len(true))
+                .map(|i| (i % 251) as u8 ^ (i >> 12) as u8)
+                .collect()
+        })
+        .collect();
+    ChunkDataBulk::from_columns(true, metas.into_iter().zip(data.iter().map(Vec::as_slice))).unwrap()
+}
+
+fn chunk_data(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunk_data_bulk");
+    let mut buffer = Vec::new();
+    for &columns in [1, 16].iter() {
+        let bulk = chunk_data_bulk(columns);
+        encode(&bulk, &mut buffer);
+        let bytes = buffer.clone();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("serialize", columns), &bulk, |b, bulk| {
+            b.iter(|| encode(black_box(bulk), &mut buffer))
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", columns), &bytes, |b, bytes| {
+            b.iter(|| decode::<ChunkDataBulk>(black_box(bytes)))
+        });
+        group.bench_with_input(BenchmarkId::new("inflate", columns), &bulk, |b, bulk| {
+            b.iter(|| bulk.inflate().unwrap().columns().count())
+        });
+    }
+    group.finish();
+}
+
+/// Metadata of a dropped item: a byte, short, int, float, string, slot and rotation entry
+fn entity_metadata_bytes() -> Vec<u8> {
+    let mut bytes = vec![0x00, 0x01, 0x21, 0x01, 0x2c, 0x42, 0, 0, 0, 7, 0x63];
+    bytes.extend_from_slice(&1.5f32.to_bits().to_be_bytes());
+    bytes.push(0x84);
+    write_varint(&10, &mut bytes).unwrap();
+    bytes.extend_from_slice(b"Dinnerbone");
+    bytes.extend_from_slice(&[0xa5, 0x01, 0x14, 0x40, 0x00, 0x00, 0xff, 0xff]);
+    bytes.push(0xc6);
+    for &i in [1i32, 64, -3].iter() {
+        bytes.extend_from_slice(&i.to_be_bytes());
+    }
+    bytes.push(0x7f);
+    bytes
+}
+
+fn entity_metadata(c: &mut Criterion) {
+    let mut group = c.benchmark_group("entity_metadata");
+    let bytes = entity_metadata_bytes();
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("deserialize", |b| {
+        b.iter(|| decode::<EntityMetadata>(black_box(&bytes)))
+    });
+    group.finish();
+}
+
+/// Slots of a player inventory as in `WindowItems`, `filled` of them hold an item without a tag
+fn slots_bytes(filled: usize) -> Vec<u8> {
+    let mut bytes = 45i16.to_be_bytes().to_vec();
+    for i in 0..45 {
+        if i < filled {
+            bytes.extend_from_slice(&(i as i16 + 1).to_be_bytes());
+            bytes.extend_from_slice(&[64, 0, 0, 0xff, 0xff]);
+        } else {
+            bytes.extend_from_slice(&[0xff, 0xff]);
+        }
+    }
+    bytes
+}
+
+fn slots(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slots");
+    for &filled in [0, 45].iter() {
+        let bytes = slots_bytes(filled);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("deserialize", filled), &bytes, |b, bytes| {
+            b.iter(|| decode::<ShortPrefix<Option<Slot>>>(black_box(bytes)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, varint, varlong, string, chunk_data, entity_metadata, slots);
+criterion_main!(benches);
@@ -5,6 +5,8 @@ pub(crate) use de::Seq;
 
 mod de;
 pub(crate) mod read;
+pub mod schema;
+pub mod trace;
 
 pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     where
@@ -1,5 +1,6 @@
 use std::io::Read;
 use crate::error::{Result, Error};
+use crate::types::string::{check_len, MAX_STRING_LEN};
 
 use byteorder::{BigEndian, ReadBytesExt};
 
@@ -80,7 +81,8 @@ pub fn read_f64<R: Read>(reader: &mut R) -> Result<f64> {
 pub fn read_String<R: Read>(reader: &mut R) -> Result<String> {
     let length = read_varint(reader)? as usize;
 
-    if length > (1 << 16) {
+    // vanilla allows up to 4 bytes per character before decoding
+    if length > MAX_STRING_LEN * 4 {
         return Err(Error::Serde(format!("read_string refusing to read string due to its length")));
     }
 
@@ -91,6 +93,8 @@ pub fn read_String<R: Read>(reader: &mut R) -> Result<String> {
         return Err(Error::Serde(format!("read_String expected a string with length {} but was only able to read {} bytes", length, read)));
     }
 
+    check_len(&ret, MAX_STRING_LEN)?;
+
     Ok(ret)
 }
 
@@ -0,0 +1,687 @@
+//! Layout of types as [`MCProtoDeserializer`](super::MCProtoDeserializer) reads them
+//!
+//! [`Schema::walk`] runs the `Deserialize` impl of a type against a deserializer without
+//! any bytes, which answers every read with a sample value and notes what was asked for.
+//! Enums are walked again until every variant was taken, so the result covers all the
+//! packets of a state.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, SeqAccess,
+    VariantAccess, Visitor,
+};
+
+use crate::error::{Error, Result};
+
+/// Walks after which an enum still missing variants is given up on
+const MAX_WALKS: usize = 10_000;
+
+/// Compound tag without a name or entries, the smallest NBT
+const EMPTY_COMPOUND: [u8; 4] = [0x0a, 0x00, 0x00, 0x00];
+
+/// Parses as the UUIDs of this crate, which reject empty strings
+const NIL_UUID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// How a value is laid out on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    F32,
+    F64,
+    VarInt,
+    VarLong,
+    /// VarInt byte length and UTF-8
+    String,
+    /// Count of elements, then the elements
+    Array {
+        prefix: Box<Format>,
+        element: Box<Format>,
+    },
+    /// Values one after the other
+    Tuple(Vec<Format>),
+    Struct {
+        name: &'static str,
+        fields: Vec<(&'static str, Format)>,
+    },
+    /// Index in [`Schema::enums`], sent as a VarInt variant id and the variant
+    Enum(usize),
+    /// An i16 of -1 when absent, otherwise the i16 is the start of the value
+    Option(Box<Format>),
+    /// u16 byte length and an NBT compound, compressed with gzip or not
+    Nbt {
+        gzip: bool,
+    },
+    /// Entries ended by a 127 byte. The header byte of an entry holds the index in its 3
+    /// high bits and the variant of the enum in the element in its 5 low bits.
+    Metadata(Box<Format>),
+    /// Values whose count depends on values read before, only the ones read are known
+    Unsized(Vec<Format>),
+    /// The deserializer refuses this type
+    Unsupported(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: &'static str,
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    /// Name of the type, with a number appended if another enum has the same name
+    pub name: String,
+    /// In variant id order
+    pub variants: Vec<Variant>,
+    type_name: &'static str,
+}
+
+/// Enums of the types walked so far, shared by the types which use them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    /// In the order they were first met
+    pub enums: Vec<Enum>,
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// Walk the `Deserialize` impl of `T`, adding the enums it uses
+    ///
+    /// Numbers are read as 0, strings as empty, prefixed arrays as one element which is
+    /// then dropped and options as present. Impls which reject these values make this fail
+    /// and leave the schema as it was.
+    pub fn walk<T: DeserializeOwned>(&mut self) -> Result<Format> {
+        let mut walker = Walker {
+            out: Vec::new(),
+            enums: self.enums.iter().map(WalkedEnum::from).collect(),
+            open: Vec::new(),
+        };
+        for _ in 0..MAX_WALKS {
+            T::deserialize(&mut walker)?;
+            let root = walker.out.pop().expect("every read leaves a format behind");
+            if walker.enums.iter().all(WalkedEnum::complete) {
+                self.enums = walker.enums.into_iter().map(Enum::from).collect();
+                return Ok(root);
+            }
+        }
+        Err(Error::Serde(format!(
+            "Variants of {} are out of reach after {} walks",
+            walker
+                .enums
+                .iter()
+                .find(|e| !e.complete())
+                .map_or("an enum", |e| e.name.as_str()),
+            MAX_WALKS
+        )))
+    }
+}
+
+struct WalkedEnum {
+    name: String,
+    type_name: &'static str,
+    variants: Vec<&'static str>,
+    formats: Vec<Option<Format>>,
+    /// Enums met while walking each variant, at any depth
+    reach: Vec<BTreeSet<usize>>,
+}
+
+impl WalkedEnum {
+    fn complete(&self) -> bool {
+        self.formats.iter().all(Option::is_some)
+    }
+}
+
+impl From<&Enum> for WalkedEnum {
+    /// Enums of earlier walks are complete, as are the ones they reach
+    fn from(e: &Enum) -> WalkedEnum {
+        WalkedEnum {
+            name: e.name.clone(),
+            type_name: e.type_name,
+            variants: e.variants.iter().map(|v| v.name).collect(),
+            formats: e.variants.iter().map(|v| Some(v.format.clone())).collect(),
+            reach: vec![BTreeSet::new(); e.variants.len()],
+        }
+    }
+}
+
+impl From<WalkedEnum> for Enum {
+    fn from(e: WalkedEnum) -> Enum {
+        Enum {
+            name: e.name,
+            variants: e
+                .variants
+                .into_iter()
+                .zip(e.formats)
+                .map(|(name, format)| Variant {
+                    name,
+                    format: format.expect("complete enums have every variant"),
+                })
+                .collect(),
+            type_name: e.type_name,
+        }
+    }
+}
+
+struct Walker {
+    /// Formats of the values read, a compound takes the ones of its parts
+    out: Vec<Format>,
+    enums: Vec<WalkedEnum>,
+    /// Enums and variants being walked
+    open: Vec<(usize, usize)>,
+}
+
+impl Walker {
+    fn enum_index(&mut self, type_name: &'static str, variants: &'static [&'static str]) -> usize {
+        let found = self
+            .enums
+            .iter()
+            .position(|e| e.type_name == type_name && e.variants == variants);
+        let index = found.unwrap_or_else(|| {
+            let same_name = self
+                .enums
+                .iter()
+                .filter(|e| e.type_name == type_name)
+                .count();
+            let name = match same_name {
+                0 => type_name.to_owned(),
+                n => format!("{}{}", type_name, n + 1),
+            };
+            self.enums.push(WalkedEnum {
+                name,
+                type_name,
+                variants: variants.to_vec(),
+                formats: vec![None; variants.len()],
+                reach: vec![BTreeSet::new(); variants.len()],
+            });
+            self.enums.len() - 1
+        });
+        for &(e, variant) in &self.open {
+            self.enums[e].reach[variant].insert(index);
+        }
+        index
+    }
+
+    /// The first variant not walked yet, or one leading to an enum which has such a variant
+    fn choose(&self, index: usize) -> usize {
+        let walked = &self.enums[index];
+        if let Some(variant) = walked.formats.iter().position(Option::is_none) {
+            return variant;
+        }
+        walked
+            .reach
+            .iter()
+            .position(|reach| reach.iter().any(|&e| !self.enums[e].complete()))
+            .unwrap_or(0)
+    }
+
+    /// Replace the formats read since `start` with the one of their compound
+    fn wrap<F: FnOnce(Vec<Format>) -> Format>(&mut self, start: usize, wrap: F) {
+        let parts = self.out.split_off(start);
+        self.out.push(wrap(parts));
+    }
+}
+
+/// Shows what a visitor expects, which is the only thing telling visitors apart
+struct Expecting<'a, V>(&'a V);
+
+impl<'a, 'de, V: Visitor<'de>> fmt::Display for Expecting<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(f)
+    }
+}
+
+fn sample_string<'de, V: Visitor<'de>>(visitor: &V) -> String {
+    if Expecting(visitor).to_string().contains("UUID") {
+        NIL_UUID.to_owned()
+    } else {
+        String::new()
+    }
+}
+
+macro_rules! walk_scalar {
+    ($method:ident, $format:ident, $visit:ident, $sample:expr) => {
+        fn $method<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            self.out.push(Format::$format);
+            visitor.$visit($sample)
+        }
+    };
+}
+
+macro_rules! walk_unsupported {
+    ($method:ident, $kind:expr, $visit:ident $(, $sample:expr)*) => {
+        fn $method<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            self.out.push(Format::Unsupported($kind));
+            visitor.$visit($($sample),*)
+        }
+    };
+}
+
+impl<'de> Deserializer<'de> for &mut Walker {
+    type Error = Error;
+
+    walk_scalar!(deserialize_bool, Bool, visit_bool, false);
+    walk_scalar!(deserialize_i8, I8, visit_i8, 0);
+    walk_scalar!(deserialize_i16, I16, visit_i16, 0);
+    walk_scalar!(deserialize_i32, I32, visit_i32, 0);
+    walk_scalar!(deserialize_i64, I64, visit_i64, 0);
+    walk_scalar!(deserialize_u8, U8, visit_u8, 0);
+    walk_scalar!(deserialize_u16, U16, visit_u16, 0);
+    walk_scalar!(deserialize_u32, U32, visit_u32, 0);
+    walk_scalar!(deserialize_u64, U64, visit_u64, 0);
+    walk_scalar!(deserialize_u128, U128, visit_u128, 0);
+    walk_scalar!(deserialize_f32, F32, visit_f32, 0.0);
+    walk_scalar!(deserialize_f64, F64, visit_f64, 0.0);
+
+    walk_unsupported!(deserialize_any, "any", visit_unit);
+    walk_unsupported!(deserialize_char, "char", visit_char, '\0');
+    walk_unsupported!(deserialize_str, "a str", visit_str, "");
+    walk_unsupported!(deserialize_bytes, "a bytes", visit_bytes, &[]);
+    walk_unsupported!(
+        deserialize_byte_buf,
+        "a byte buf",
+        visit_byte_buf,
+        Vec::new()
+    );
+    walk_unsupported!(deserialize_unit, "a unit", visit_unit);
+    walk_unsupported!(deserialize_identifier, "an identifier", visit_unit);
+    walk_unsupported!(deserialize_ignored_any, "any", visit_unit);
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let sample = sample_string(&visitor);
+        self.out.push(Format::String);
+        visitor.visit_string(sample)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let start = self.out.len();
+        let value = visitor.visit_some(&mut *self)?;
+        self.wrap(start, |mut parts| Format::Option(Box::new(parts.remove(0))));
+        Ok(value)
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.out.push(Format::Unsupported("a unit struct"));
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let prefix = match name {
+            "MCVARINT" => {
+                self.out.push(Format::VarInt);
+                return visitor.visit_i32(0);
+            }
+            "MCVARLONG" => {
+                self.out.push(Format::VarLong);
+                return visitor.visit_i64(0);
+            }
+            "NBT" | "GZIPNBT" => {
+                self.out.push(Format::Nbt {
+                    gzip: name == "GZIPNBT",
+                });
+                let mut decoder = nbt::de::Decoder::new(&EMPTY_COMPOUND[..]);
+                return visitor
+                    .visit_newtype_struct(&mut decoder)
+                    .map_err(|e| Error::Serde(format!("NBT read error: {}", e)));
+            }
+            "MCMETADATAENTRY" => None,
+            "MCUBYTEPREFIXEDARRAY" => Some(Format::U8),
+            "MCBYTEPREFIXEDARRAY" => Some(Format::I8),
+            "MCSHORTPREFIXEDARRAY" => Some(Format::I16),
+            "MCINTPREFIXEDARRAY" => Some(Format::I32),
+            "MCVARINTPREFIXEDARRAY" => Some(Format::VarInt),
+            _ => {
+                let value = visitor.visit_newtype_struct(&mut *self)?;
+                self.out.pop();
+                self.out.push(Format::Unsupported(name));
+                return Ok(value);
+            }
+        };
+
+        let start = self.out.len();
+        let value = visitor.visit_seq(OneElement {
+            walker: &mut *self,
+            taken: false,
+        })?;
+        self.wrap(start, |mut parts| {
+            let element = parts.pop().unwrap_or(Format::Unsized(Vec::new()));
+            match prefix {
+                Some(prefix) => Format::Array {
+                    prefix: Box::new(prefix),
+                    element: Box::new(element),
+                },
+                None => Format::Metadata(Box::new(element)),
+            }
+        });
+        Ok(value)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.out.push(Format::Unsupported("a unsized seq"));
+        visitor.visit_seq(SeqDeserializer::new(std::iter::empty::<()>()))
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let start = self.out.len();
+        let value = visitor.visit_seq(Elements {
+            walker: &mut *self,
+            left: len,
+        })?;
+        // a visitor stopping early reads a length from the values before
+        self.wrap(start, |parts| {
+            if len > 0 && parts.len() == len {
+                Format::Tuple(parts)
+            } else {
+                Format::Unsized(parts)
+            }
+        });
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = self.deserialize_tuple(len, visitor)?;
+        self.out.pop();
+        self.out.push(Format::Unsupported("a tuple struct"));
+        Ok(value)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.out.push(Format::Unsupported("a map"));
+        visitor.visit_map(MapDeserializer::new(std::iter::empty::<((), ())>()))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let start = self.out.len();
+        let value = visitor.visit_seq(Elements {
+            walker: &mut *self,
+            left: fields.len(),
+        })?;
+        self.wrap(start, |parts| Format::Struct {
+            name,
+            fields: fields.iter().cloned().zip(parts).collect(),
+        });
+        Ok(value)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.enum_index(name, variants);
+        let variant = self.choose(index);
+        self.open.push((index, variant));
+        let value = visitor.visit_enum(Chosen {
+            walker: &mut *self,
+            index,
+            variant,
+        });
+        self.open.pop();
+        self.out.push(Format::Enum(index));
+        value
+    }
+}
+
+/// Reads of a tuple or struct, as many as the visitor asks for
+struct Elements<'a> {
+    walker: &'a mut Walker,
+    left: usize,
+}
+
+impl<'a, 'de> SeqAccess<'de> for Elements<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.walker).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left)
+    }
+}
+
+/// Walks the element type of a sequence, then ends it empty so its length stays 0
+struct OneElement<'a> {
+    walker: &'a mut Walker,
+    taken: bool,
+}
+
+impl<'a, 'de> SeqAccess<'de> for OneElement<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if !self.taken {
+            self.taken = true;
+            seed.deserialize(&mut *self.walker)?;
+        }
+        Ok(None)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
+struct Chosen<'a> {
+    walker: &'a mut Walker,
+    index: usize,
+    variant: usize,
+}
+
+impl<'a> Chosen<'a> {
+    /// Keep the format of the variant, which is read into `out` by `read`
+    fn walk<T, F>(self, read: F) -> Result<T>
+    where
+        F: FnOnce(&mut Walker) -> Result<T>,
+    {
+        let start = self.walker.out.len();
+        let value = read(&mut *self.walker)?;
+        let mut parts = self.walker.out.split_off(start);
+        let format = match parts.len() {
+            1 => parts.remove(0),
+            _ => Format::Tuple(parts),
+        };
+        self.walker.enums[self.index].formats[self.variant] = Some(format);
+        Ok(value)
+    }
+}
+
+impl<'a, 'de> EnumAccess<'de> for Chosen<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let id: serde::de::value::U32Deserializer<Error> =
+            (self.variant as u32).into_deserializer();
+        let value = seed.deserialize(id)?;
+        Ok((value, self))
+    }
+}
+
+impl<'a, 'de> VariantAccess<'de> for Chosen<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        self.walk(|_| Ok(()))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.walk(|walker| seed.deserialize(walker))
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.walk(|walker| walker.deserialize_tuple(len, visitor))
+    }
+
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let name = self.walker.enums[self.index].variants[self.variant];
+        self.walk(|walker| walker.deserialize_struct(name, fields, visitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Deserialize;
+
+    use crate::types::array::ShortPrefix;
+    use crate::types::uuid::Uuid;
+    use crate::types::var::VarInt;
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    enum Action {
+        Add { names: ShortPrefix<String> },
+        Remove,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    enum Packet {
+        KeepAlive {
+            keep_alive_id: i32,
+        },
+        Team {
+            name: String,
+            action: Action,
+        },
+        Spawn {
+            id: Uuid,
+            position: [i32; 3],
+            item: Option<i16>,
+        },
+        Chunk(VarInt),
+    }
+
+    #[test]
+    fn walks_every_variant() {
+        let mut schema = Schema::new();
+        assert_eq!(schema.walk::<Packet>().unwrap(), Format::Enum(0));
+        let names: Vec<_> = schema.enums.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Packet", "Action"]);
+
+        let packet = &schema.enums[0].variants;
+        assert_eq!(
+            packet[1].format,
+            Format::Struct {
+                name: "Team",
+                fields: vec![("name", Format::String), ("action", Format::Enum(1))],
+            }
+        );
+        assert_eq!(
+            packet[2].format,
+            Format::Struct {
+                name: "Spawn",
+                fields: vec![
+                    ("id", Format::String),
+                    ("position", Format::Tuple(vec![Format::I32; 3])),
+                    ("item", Format::Option(Box::new(Format::I16))),
+                ],
+            }
+        );
+        assert_eq!(packet[3].format, Format::VarInt);
+
+        // only reached through the second variant of `Packet`, once that one was walked
+        let action = &schema.enums[1].variants;
+        assert_eq!(
+            action[0].format,
+            Format::Struct {
+                name: "Add",
+                fields: vec![(
+                    "names",
+                    Format::Array {
+                        prefix: Box::new(Format::I16),
+                        element: Box::new(Format::String),
+                    }
+                )],
+            }
+        );
+        assert_eq!(action[1].format, Format::Tuple(Vec::new()));
+
+        // later walks share the enums
+        assert_eq!(schema.walk::<Action>().unwrap(), Format::Enum(1));
+        assert_eq!(schema.enums.len(), 2);
+    }
+}
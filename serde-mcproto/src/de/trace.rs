@@ -0,0 +1,791 @@
+//! Decoding which records every value it reads
+//!
+//! [`trace`] decodes like [`MCProtoDeserializer`] and also lists the values it read, with
+//! their field names, types and the bytes they were read from. When decoding fails, the
+//! values which were being read when the error happened are the ones left without an end.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+
+use super::MCProtoDeserializer;
+use crate::error::{Error, Result};
+
+/// Value read while decoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Nesting level, 0 for the decoded value itself
+    pub depth: usize,
+    /// Struct field, enum variant or element index
+    pub name: Option<String>,
+    /// Type as the decoder sees it, e.g. `i32`, `VarInt` or the name of a struct
+    pub kind: String,
+    /// Scalars only
+    pub value: Option<String>,
+    /// Offset of the first byte
+    pub start: usize,
+    /// Offset after the last byte, `None` if decoding failed while reading it
+    pub end: Option<usize>,
+}
+
+/// What [`trace`] read
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    /// In the order they were started, children after their parent
+    pub fields: Vec<Field>,
+    /// Bytes consumed, where the error happened if decoding failed
+    pub position: usize,
+}
+
+/// Decode `bytes` recording every value read
+pub fn trace<T: DeserializeOwned>(bytes: &[u8]) -> (Result<T>, Trace) {
+    let position = Rc::new(Cell::new(0));
+    let mut de = MCProtoDeserializer::new(CountingReader {
+        inner: bytes,
+        position: position.clone(),
+    });
+    let ctx = Context {
+        position,
+        fields: RefCell::new(Vec::new()),
+        open: RefCell::new(Vec::new()),
+        name: RefCell::new(None),
+        start: Cell::new(None),
+    };
+    let result = T::deserialize(Tracer {
+        de: &mut de,
+        ctx: &ctx,
+    });
+    let trace = Trace {
+        fields: ctx.fields.into_inner(),
+        position: ctx.position.get(),
+    };
+    (result, trace)
+}
+
+struct CountingReader<'a> {
+    inner: &'a [u8],
+    position: Rc<Cell<usize>>,
+}
+
+impl<'a> Read for CountingReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position.set(self.position.get() + read);
+        Ok(read)
+    }
+}
+
+struct Context {
+    position: Rc<Cell<usize>>,
+    fields: RefCell<Vec<Field>>,
+    /// Indexes of the fields being read
+    open: RefCell<Vec<usize>>,
+    /// Name of the next field
+    name: RefCell<Option<String>>,
+    /// Start of the next field, if it was read before it is known
+    start: Cell<Option<usize>>,
+}
+
+impl Context {
+    fn open(&self, kind: &str) -> usize {
+        let mut fields = self.fields.borrow_mut();
+        let mut open = self.open.borrow_mut();
+        fields.push(Field {
+            depth: open.len(),
+            name: self.name.borrow_mut().take(),
+            kind: kind.to_owned(),
+            value: None,
+            start: self.start.take().unwrap_or_else(|| self.position.get()),
+            end: None,
+        });
+        open.push(fields.len() - 1);
+        fields.len() - 1
+    }
+
+    fn close<T>(&self, index: usize, result: &Result<T>) {
+        self.open.borrow_mut().pop();
+        if result.is_ok() {
+            self.fields.borrow_mut()[index].end = Some(self.position.get());
+        }
+    }
+
+    fn value<T: fmt::Display>(&self, value: T) {
+        if let Some(&index) = self.open.borrow().last() {
+            self.fields.borrow_mut()[index].value = Some(value.to_string());
+        }
+    }
+
+    fn name(&self, name: String) {
+        *self.name.borrow_mut() = Some(name);
+    }
+}
+
+/// Readable name of the types the deserializer handles by name
+fn newtype_kind(name: &'static str) -> &'static str {
+    match name {
+        "MCVARINT" => "VarInt",
+        "MCVARLONG" => "VarLong",
+        "MCUBYTEPREFIXEDARRAY" => "UBytePrefix",
+        "MCBYTEPREFIXEDARRAY" => "BytePrefix",
+        "MCSHORTPREFIXEDARRAY" => "ShortPrefix",
+        "MCINTPREFIXEDARRAY" => "IntPrefix",
+        "MCVARINTPREFIXEDARRAY" => "VarIntPrefix",
+        "MCMETADATAENTRY" => "EntityMetadata",
+        "NBT" => "NBT",
+        "GZIPNBT" => "GZIPNBT",
+        name => name,
+    }
+}
+
+/// Names the elements of a sequence or the variants of an identifier
+#[derive(Clone, Copy)]
+enum Names {
+    None,
+    Fields(&'static [&'static str]),
+    Variants(&'static [&'static str]),
+}
+
+struct Tracer<'c, D> {
+    de: D,
+    ctx: &'c Context,
+}
+
+impl<'c, D> Tracer<'c, D> {
+    fn visitor<V>(&self, visitor: V, names: Names) -> TraceVisitor<'c, V> {
+        TraceVisitor {
+            visitor,
+            ctx: self.ctx,
+            names,
+        }
+    }
+}
+
+macro_rules! trace_method {
+    ($method:ident, $kind:expr) => {
+        fn $method<V>(self, visitor: V) -> std::result::Result<V::Value, D::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let index = self.ctx.open($kind);
+            let visitor = self.visitor(visitor, Names::None);
+            let result = self.de.$method(visitor);
+            self.ctx.close(index, &ok_or_unit(&result));
+            result
+        }
+    };
+}
+
+impl<'de, 'c, D> Deserializer<'de> for Tracer<'c, D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    trace_method!(deserialize_any, "any");
+    trace_method!(deserialize_bool, "bool");
+    trace_method!(deserialize_i8, "i8");
+    trace_method!(deserialize_i16, "i16");
+    trace_method!(deserialize_i32, "i32");
+    trace_method!(deserialize_i64, "i64");
+    trace_method!(deserialize_i128, "i128");
+    trace_method!(deserialize_u8, "u8");
+    trace_method!(deserialize_u16, "u16");
+    trace_method!(deserialize_u32, "u32");
+    trace_method!(deserialize_u64, "u64");
+    trace_method!(deserialize_u128, "u128");
+    trace_method!(deserialize_f32, "f32");
+    trace_method!(deserialize_f64, "f64");
+    trace_method!(deserialize_char, "char");
+    trace_method!(deserialize_str, "string");
+    trace_method!(deserialize_string, "string");
+    trace_method!(deserialize_bytes, "bytes");
+    trace_method!(deserialize_byte_buf, "bytes");
+    trace_method!(deserialize_option, "option");
+    trace_method!(deserialize_unit, "unit");
+    trace_method!(deserialize_seq, "seq");
+    trace_method!(deserialize_map, "map");
+    trace_method!(deserialize_identifier, "identifier");
+    trace_method!(deserialize_ignored_any, "ignored");
+
+    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.ctx.open(name);
+        let visitor = self.visitor(visitor, Names::None);
+        let result = self.de.deserialize_unit_struct(name, visitor);
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.ctx.open(newtype_kind(name));
+        let visitor = self.visitor(visitor, Names::None);
+        let result = self.de.deserialize_newtype_struct(name, visitor);
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.ctx.open("tuple");
+        let visitor = self.visitor(visitor, Names::None);
+        let result = self.de.deserialize_tuple(len, visitor);
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.ctx.open(name);
+        let visitor = self.visitor(visitor, Names::None);
+        let result = self.de.deserialize_tuple_struct(name, len, visitor);
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.ctx.open(name);
+        let visitor = self.visitor(visitor, Names::Fields(fields));
+        let result = self.de.deserialize_struct(name, fields, visitor);
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let index = self.ctx.open(name);
+        let visitor = self.visitor(visitor, Names::Variants(variants));
+        let result = self.de.deserialize_enum(name, variants, visitor);
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.de.is_human_readable()
+    }
+}
+
+/// Only success matters when closing a field
+fn ok_or_unit<T, E>(result: &std::result::Result<T, E>) -> Result<()> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::Serde(String::new())),
+    }
+}
+
+struct TraceVisitor<'c, V> {
+    visitor: V,
+    ctx: &'c Context,
+    names: Names,
+}
+
+macro_rules! visit_scalar {
+    ($method:ident, $ty:ty) => {
+        fn $method<E>(self, v: $ty) -> std::result::Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            self.ctx.value(v);
+            self.visitor.$method(v)
+        }
+    };
+}
+
+impl<'de, 'c, V> Visitor<'de> for TraceVisitor<'c, V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    visit_scalar!(visit_bool, bool);
+    visit_scalar!(visit_i8, i8);
+    visit_scalar!(visit_i16, i16);
+    visit_scalar!(visit_i32, i32);
+    visit_scalar!(visit_i64, i64);
+    visit_scalar!(visit_i128, i128);
+    visit_scalar!(visit_u8, u8);
+    visit_scalar!(visit_u16, u16);
+    visit_scalar!(visit_u128, u128);
+    visit_scalar!(visit_f32, f32);
+    visit_scalar!(visit_f64, f64);
+    visit_scalar!(visit_char, char);
+
+    fn visit_u32<E>(self, v: u32) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match self.names {
+            Names::Variants(variants) => match variants.get(v as usize) {
+                Some(variant) => self.ctx.value(format_args!("{} ({})", v, variant)),
+                None => self.ctx.value(v),
+            },
+            _ => self.ctx.value(v),
+        }
+        self.visitor.visit_u32(v)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match self.names {
+            Names::Variants(variants) => match variants.get(v as usize) {
+                Some(variant) => self.ctx.value(format_args!("{} ({})", v, variant)),
+                None => self.ctx.value(v),
+            },
+            _ => self.ctx.value(v),
+        }
+        self.visitor.visit_u64(v)
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value(format_args!("{:?}", v));
+        self.visitor.visit_str(v)
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value(format_args!("{:?}", v));
+        self.visitor.visit_borrowed_str(v)
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value(format_args!("{:?}", v));
+        self.visitor.visit_string(v)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value(format_args!("{} bytes", v.len()));
+        self.visitor.visit_bytes(v)
+    }
+
+    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value(format_args!("{} bytes", v.len()));
+        self.visitor.visit_borrowed_bytes(v)
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value(format_args!("{} bytes", v.len()));
+        self.visitor.visit_byte_buf(v)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.ctx.value("None");
+        self.visitor.visit_none()
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.visitor.visit_some(Tracer {
+            de: deserializer,
+            ctx: self.ctx,
+        })
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.visitor.visit_newtype_struct(Tracer {
+            de: deserializer,
+            ctx: self.ctx,
+        })
+    }
+
+    fn visit_seq<A>(self, seq: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let fields = match self.names {
+            Names::Fields(fields) => fields,
+            _ => &[],
+        };
+        self.visitor.visit_seq(TraceSeq {
+            seq,
+            ctx: self.ctx,
+            fields,
+            index: 0,
+        })
+    }
+
+    fn visit_map<A>(self, map: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        self.visitor.visit_map(TraceMap { map, ctx: self.ctx })
+    }
+
+    fn visit_enum<A>(self, data: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let variants = match self.names {
+            Names::Variants(variants) => variants,
+            _ => &[],
+        };
+        self.visitor.visit_enum(TraceEnum {
+            data,
+            ctx: self.ctx,
+            variants,
+        })
+    }
+}
+
+struct TraceSeed<'c, S> {
+    seed: S,
+    ctx: &'c Context,
+}
+
+impl<'de, 'c, S> DeserializeSeed<'de> for TraceSeed<'c, S>
+where
+    S: DeserializeSeed<'de>,
+{
+    type Value = S::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<S::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.seed.deserialize(Tracer {
+            de: deserializer,
+            ctx: self.ctx,
+        })
+    }
+}
+
+struct TraceSeq<'c, A> {
+    seq: A,
+    ctx: &'c Context,
+    fields: &'static [&'static str],
+    index: usize,
+}
+
+impl<'de, 'c, A> SeqAccess<'de> for TraceSeq<'c, A>
+where
+    A: SeqAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> std::result::Result<Option<T::Value>, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let name = match self.fields.get(self.index) {
+            Some(field) => (*field).to_owned(),
+            None => format!("[{}]", self.index),
+        };
+        self.ctx.name(name);
+        self.index += 1;
+        let element = self.seq.next_element_seed(TraceSeed { seed, ctx: self.ctx });
+        // nothing read the name at the end of the sequence
+        self.ctx.name.borrow_mut().take();
+        element
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.seq.size_hint()
+    }
+}
+
+struct TraceMap<'c, A> {
+    map: A,
+    ctx: &'c Context,
+}
+
+impl<'de, 'c, A> MapAccess<'de> for TraceMap<'c, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> std::result::Result<Option<K::Value>, A::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.ctx.name("key".to_owned());
+        let key = self.map.next_key_seed(TraceSeed { seed, ctx: self.ctx });
+        self.ctx.name.borrow_mut().take();
+        key
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> std::result::Result<V::Value, A::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.ctx.name("value".to_owned());
+        self.map.next_value_seed(TraceSeed { seed, ctx: self.ctx })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.map.size_hint()
+    }
+}
+
+struct TraceEnum<'c, A> {
+    data: A,
+    ctx: &'c Context,
+    variants: &'static [&'static str],
+}
+
+impl<'de, 'c, A> EnumAccess<'de> for TraceEnum<'c, A>
+where
+    A: EnumAccess<'de>,
+{
+    type Error = A::Error;
+    type Variant = TraceVariant<'c, A::Variant>;
+
+    fn variant_seed<S>(self, seed: S) -> std::result::Result<(S::Value, Self::Variant), A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        // the id is read before the seed runs, it starts where the enum does
+        let start = self.ctx.open.borrow().last().map(|&index| self.ctx.fields.borrow()[index].start);
+        self.ctx.start.set(start);
+        self.ctx.name("variant".to_owned());
+        let seed = VariantSeed {
+            seed,
+            ctx: self.ctx,
+            variants: self.variants,
+        };
+        let (value, variant) = self.data.variant_seed(seed)?;
+        Ok((
+            value,
+            TraceVariant {
+                variant,
+                ctx: self.ctx,
+            },
+        ))
+    }
+}
+
+/// Seed of the variant id, which names the variant
+struct VariantSeed<'c, S> {
+    seed: S,
+    ctx: &'c Context,
+    variants: &'static [&'static str],
+}
+
+impl<'de, 'c, S> DeserializeSeed<'de> for VariantSeed<'c, S>
+where
+    S: DeserializeSeed<'de>,
+{
+    type Value = S::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<S::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let index = self.ctx.open("VarInt");
+        let result = self.seed.deserialize(VariantId {
+            de: deserializer,
+            ctx: self.ctx,
+            variants: self.variants,
+        });
+        self.ctx.close(index, &ok_or_unit(&result));
+        result
+    }
+}
+
+/// Deserializer of a variant id, every request is answered with the variant names
+struct VariantId<'c, D> {
+    de: D,
+    ctx: &'c Context,
+    variants: &'static [&'static str],
+}
+
+impl<'de, 'c, D> Deserializer<'de> for VariantId<'c, D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> std::result::Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.de.deserialize_any(TraceVisitor {
+            visitor,
+            ctx: self.ctx,
+            names: Names::Variants(self.variants),
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct TraceVariant<'c, A> {
+    variant: A,
+    ctx: &'c Context,
+}
+
+impl<'de, 'c, A> VariantAccess<'de> for TraceVariant<'c, A>
+where
+    A: VariantAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn unit_variant(self) -> std::result::Result<(), A::Error> {
+        self.variant.unit_variant()
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> std::result::Result<T::Value, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.variant.newtype_variant_seed(TraceSeed { seed, ctx: self.ctx })
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> std::result::Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.variant.tuple_variant(
+            len,
+            TraceVisitor {
+                visitor,
+                ctx: self.ctx,
+                names: Names::None,
+            },
+        )
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.variant.struct_variant(
+            fields,
+            TraceVisitor {
+                visitor,
+                ctx: self.ctx,
+                names: Names::Fields(fields),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Deserialize;
+
+    use crate::types::var::VarInt;
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    enum Packet {
+        KeepAlive { keep_alive_id: i32 },
+        Chat { message: String, position: VarInt },
+    }
+
+    type Summary<'a> = (usize, Option<&'a str>, &'a str, Option<&'a str>, usize, Option<usize>);
+
+    fn summary(trace: &Trace) -> Vec<Summary<'_>> {
+        trace
+            .fields
+            .iter()
+            .map(|f| (f.depth, f.name.as_deref(), f.kind.as_str(), f.value.as_deref(), f.start, f.end))
+            .collect()
+    }
+
+    #[test]
+    fn names_types_and_offsets() {
+        let (packet, trace) = trace::<Packet>(&[0x01, 0x02, b'h', b'i', 0x80, 0x01]);
+        assert!(matches!(packet, Ok(Packet::Chat { .. })));
+        assert_eq!(
+            summary(&trace),
+            vec![
+                (0, None, "Packet", None, 0, Some(6)),
+                (1, Some("variant"), "VarInt", Some("1 (Chat)"), 0, Some(1)),
+                (1, Some("message"), "string", Some("\"hi\""), 1, Some(4)),
+                (1, Some("position"), "VarInt", Some("128"), 4, Some(6)),
+            ]
+        );
+        assert_eq!(trace.position, 6);
+    }
+
+    #[test]
+    fn failure_leaves_fields_open() {
+        let (packet, trace) = trace::<Packet>(&[0x00, 0x00, 0x01]);
+        assert!(packet.is_err());
+        let fields = summary(&trace);
+        assert_eq!(fields[1].3, Some("0 (KeepAlive)"));
+        assert_eq!(fields[2], (1, Some("keep_alive_id"), "i32", None, 1, None));
+        assert_eq!(fields[0].5, None);
+    }
+}
@@ -21,7 +21,7 @@ impl<'a, W: Write> serde::Serializer for &'a mut MCProtoSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
     type SerializeSeq = ser::Impossible<(), Self::Error>;
-    type SerializeTuple = ser::Impossible<(), Self::Error>;
+    type SerializeTuple = Compound<'a, W>;
     type SerializeTupleStruct = ser::Impossible<(), Self::Error>;
     type SerializeTupleVariant = ser::Impossible<(), Self::Error>;
     type SerializeMap = ser::Impossible<(), Self::Error>;
@@ -101,8 +101,7 @@ impl<'a, W: Write> serde::Serializer for &'a mut MCProtoSerializer<W> {
     }
 
     fn serialize_bytes(self, value: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.writer.write(value);
-        Ok(())//TODO handle
+        Ok(self.writer.write_all(value)?)
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
@@ -134,15 +133,16 @@ impl<'a, W: Write> serde::Serializer for &'a mut MCProtoSerializer<W> {
 
     fn serialize_newtype_variant<T: ?Sized>(self, name: &'static str, variant_index: u32, variant: &'static str, value: &T) -> Result<Self::Ok, Self::Error> where
         T: Serialize {
-        unimplemented!()
+        write_varint(&(variant_index as i32), &mut self.writer)?;
+        value.serialize(self)
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
         unimplemented!()
     }
 
-    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        unimplemented!()
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(Compound { ser: self })
     }
 
     fn serialize_tuple_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
@@ -197,6 +197,27 @@ impl<'a, W> serde::ser::SerializeStruct for Compound<'a, W>
     }
 }
 
+impl<'a, W> serde::ser::SerializeTuple for Compound<'a, W>
+    where
+        W: Write
+{
+    type Ok = ();
+    type Error = crate::error::Error;
+
+    #[inline]
+    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+        where
+            T: serde::ser::Serialize,
+    {
+        value.serialize(&mut *self.ser)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 impl<'a, W> serde::ser::SerializeStructVariant for Compound<'a, W>
     where
         W: Write
@@ -4,6 +4,7 @@ use std::io::Write;
 use byteorder::{BigEndian, WriteBytesExt};
 
 use crate::error::Error;
+use crate::types::string::MAX_STRING_LEN;
 
 /* While many of the functions here may seem redundant, keeping them is
  * convenient and consistent. */
@@ -122,6 +123,11 @@ pub fn write_String<W: Write>(val: &str, writer: &mut W) -> Result<(), Error> {
     let string = val.as_bytes();
     let length = val.len() as i32;
 
+    // vanilla limits the encoded length, not the character count
+    if val.len() > MAX_STRING_LEN {
+        return Err(Error::Serde(format!("write_String refusing to write a string of {} bytes", length)));
+    }
+
     write_varint(&length, writer)?;
 
     Ok(writer.write_all(string)?)
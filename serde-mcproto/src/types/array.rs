@@ -1,7 +1,10 @@
-use crate::ser::write::{write_i16, write_i32, write_i8, write_u8, write_varint};
-use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use crate::types::var::VarInt;
+use serde::de::{DeserializeOwned, DeserializeSeed, SeqAccess, Visitor};
 use serde::export::PhantomData;
+use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::ops::Deref;
 
 #[derive(Debug, Clone)]
 pub struct UBytePrefix<T>
@@ -18,6 +21,21 @@ where
     pub fn new(elements: Vec<T>) -> Self {
         UBytePrefix { elements }
     }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.elements
+    }
+}
+
+impl<T> Deref for UBytePrefix<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.elements
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +53,21 @@ where
     pub fn new(elements: Vec<T>) -> Self {
         BytePrefix { elements }
     }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.elements
+    }
+}
+
+impl<T> Deref for BytePrefix<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.elements
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -52,6 +85,21 @@ where
     pub fn new(elements: Vec<T>) -> Self {
         ShortPrefix { elements }
     }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.elements
+    }
+}
+
+impl<T> Deref for ShortPrefix<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.elements
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +117,21 @@ where
     pub fn new(elements: Vec<T>) -> Self {
         IntPrefix { elements }
     }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.elements
+    }
+}
+
+impl<T> Deref for IntPrefix<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.elements
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -86,6 +149,21 @@ where
     pub fn new(elements: Vec<T>) -> Self {
         VarIntPrefix { elements }
     }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.elements
+    }
+}
+
+impl<T> Deref for VarIntPrefix<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.elements
+    }
 }
 
 impl<'de, T> Deserialize<'de> for UBytePrefix<T>
@@ -211,6 +289,72 @@ where
     }
 }
 
+/// Reads a known number of elements into a vec, for arrays whose length is sent in an earlier field
+pub struct ExternalLength<'a, T: 'a>(pub usize, pub &'a mut Vec<T>);
+
+impl<'de, 'a, T> DeserializeSeed<'de> for ExternalLength<'a, T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ExtendVecVisitor<'a, T: 'a>(usize, &'a mut Vec<T>);
+
+        impl<'de, 'a, T> Visitor<'de> for ExtendVecVisitor<'a, T>
+        where
+            T: Deserialize<'de>,
+        {
+            type Value = ();
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(formatter, "an array of {} elements", self.0)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                for i in 0..self.0 {
+                    let element = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                    self.1.push(element);
+                }
+                Ok(())
+            }
+        }
+        deserializer.deserialize_tuple(self.0, ExtendVecVisitor(self.0, self.1))
+    }
+}
+
+/// Length prefix of `len` elements, an error if it does not fit the prefix type
+fn length<P, E>(len: usize, prefix: &str) -> Result<P, E>
+where
+    P: TryFrom<usize>,
+    E: serde::ser::Error,
+{
+    P::try_from(len).map_err(|_| E::custom(format!("{} elements do not fit a {} length prefix", len, prefix)))
+}
+
+/// Write the length prefix followed by the elements
+fn serialize_prefixed<P, T, S>(prefix: &P, elements: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    P: Serialize,
+    T: Serialize,
+    S: Serializer,
+{
+    let mut tuple = serializer.serialize_tuple(elements.len() + 1)?;
+    tuple.serialize_element(prefix)?;
+    for element in elements {
+        tuple.serialize_element(element)?;
+    }
+    tuple.end()
+}
+
 impl<T> Serialize for UBytePrefix<T>
 where
     T: DeserializeOwned + Serialize,
@@ -219,9 +363,8 @@ where
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_u8(&(self.elements.len() as u8), &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let len: u8 = length::<_, S::Error>(self.elements.len(), "unsigned byte")?;
+        serialize_prefixed(&len, &self.elements, serializer)
     }
 }
 
@@ -233,9 +376,8 @@ where
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_i8(&(self.elements.len() as i8), &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let len: i8 = length::<_, S::Error>(self.elements.len(), "byte")?;
+        serialize_prefixed(&len, &self.elements, serializer)
     }
 }
 
@@ -247,9 +389,8 @@ where
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_i16(&(self.elements.len() as i16), &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let len: i16 = length::<_, S::Error>(self.elements.len(), "short")?;
+        serialize_prefixed(&len, &self.elements, serializer)
     }
 }
 
@@ -261,13 +402,12 @@ where
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_i32(&(self.elements.len() as i32), &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let len: i32 = length::<_, S::Error>(self.elements.len(), "int")?;
+        serialize_prefixed(&len, &self.elements, serializer)
     }
 }
 
-impl<'de, T> Serialize for VarIntPrefix<T>
+impl<T> Serialize for VarIntPrefix<T>
 where
     T: DeserializeOwned + Serialize,
 {
@@ -275,8 +415,44 @@ where
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_i32(&(self.elements.len() as i32), &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let len: i32 = length::<_, S::Error>(self.elements.len(), "VarInt")?;
+        serialize_prefixed(&VarInt(len), &self.elements, serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::MCProtoDeserializer;
+    use crate::ser::MCProtoSerializer;
+
+    #[test]
+    fn prefixed_round_trip() {
+        let mut bytes = Vec::new();
+        let mut serializer = MCProtoSerializer::new(&mut bytes);
+        ShortPrefix::new(vec![1u8, 2, 3]).serialize(&mut serializer).unwrap();
+        VarIntPrefix::new(vec![-1i32]).serialize(&mut serializer).unwrap();
+        assert_eq!(bytes, vec![0, 3, 1, 2, 3, 1, 0xff, 0xff, 0xff, 0xff]);
+
+        let mut deserializer = MCProtoDeserializer::new(bytes.as_slice());
+        let short: ShortPrefix<u8> = Deserialize::deserialize(&mut deserializer).unwrap();
+        let varint: VarIntPrefix<i32> = Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(short.into_inner(), vec![1, 2, 3]);
+        assert_eq!(varint.into_inner(), vec![-1]);
+    }
+
+    fn encode<T: Serialize>(value: &T) -> crate::error::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        value.serialize(&mut MCProtoSerializer::new(&mut bytes))?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn oversized_prefixed() {
+        assert_eq!(encode(&UBytePrefix::new(vec![0u8; 255])).unwrap().len(), 256);
+        assert!(encode(&UBytePrefix::new(vec![0u8; 256])).is_err());
+        assert!(encode(&BytePrefix::new(vec![0u8; 128])).is_err());
+        assert_eq!(encode(&ShortPrefix::new(vec![0u8; 32767])).unwrap().len(), 32769);
+        assert!(encode(&ShortPrefix::new(vec![0u8; 32768])).is_err());
     }
 }
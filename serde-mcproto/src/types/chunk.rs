@@ -1,97 +1,246 @@
-use serde::{Deserialize, Deserializer, Serialize};
-use serde::de::{SeqAccess, Visitor, DeserializeSeed};
+use std::io::{Read, Write};
 
-use super::array::IntPrefix;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
 
-#[derive(Debug, Clone, Serialize)]
+use crate::error::{Error, Result};
+use crate::types::array::ExternalLength;
+
+/// Blocks, metadata and block light of a 16x16x16 section
+const SECTION_LEN: usize = 4096 + 2048 + 2048;
+const NIBBLE_ARRAY_LEN: usize = 2048;
+const BIOMES_LEN: usize = 256;
+/// Compressed data length above which a bulk cannot fit the largest frame, `2^21 - 1` bytes
+const MAX_DATA_LEN: i32 = 2_097_151;
+
+/// Map Chunk Bulk payload. The column count and data length are derived from the content.
+#[derive(Debug, Clone)]
 pub struct ChunkDataBulk {
-    chunk_column_count: i16,
-    data_length: i32,
     sky_light_sent: bool,
     compressed_chunk_data: Vec<u8>,
     meta: Vec<ChunkMeta>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChunkMeta {
-    x: i32,
-    z: i32,
-    mask: u16,
-    add_mask: u16,
+    pub x: i32,
+    pub z: i32,
+    pub mask: u16,
+    pub add_mask: u16,
 }
 
+impl ChunkMeta {
+    /// Length of the inflated column data, bulk columns are always sent ground up
+    pub fn data_len(&self, sky_light_sent: bool) -> usize {
+        data_len(self.mask, self.add_mask, sky_light_sent, true)
+    }
+}
 
-struct ExternalLength<'a, T: 'a>(usize, &'a mut Vec<T>);
+/// Length of the inflated data of a column with these masks, in `ChunkData` or a bulk
+pub fn data_len(mask: u16, add_mask: u16, sky_light_sent: bool, ground_up: bool) -> usize {
+    let mut len = mask.count_ones() as usize * SECTION_LEN;
+    if sky_light_sent {
+        len += mask.count_ones() as usize * NIBBLE_ARRAY_LEN;
+    }
+    len += (add_mask & mask).count_ones() as usize * NIBBLE_ARRAY_LEN;
+    if ground_up {
+        len += BIOMES_LEN;
+    }
+    len
+}
 
-impl<'de, 'a, T> DeserializeSeed<'de> for ExternalLength<'a, T>
+impl ChunkDataBulk {
+    pub fn new(sky_light_sent: bool, compressed_chunk_data: Vec<u8>, meta: Vec<ChunkMeta>) -> Self {
+        ChunkDataBulk {
+            sky_light_sent,
+            compressed_chunk_data,
+            meta,
+        }
+    }
+
+    /// Build a bulk from the inflated data of each column
+    pub fn from_columns<'a, I>(sky_light_sent: bool, columns: I) -> Result<Self>
     where
-        T: Deserialize<'de>,
-{
-    type Value = ();
-    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
-        where
-            D: Deserializer<'de>,
+        I: IntoIterator<Item = (ChunkMeta, &'a [u8])>,
     {
-
-        struct ExtendVecVisitor<'a, T: 'a>(usize, &'a mut Vec<T>);
-        impl<'de, 'a, T> Visitor<'de> for ExtendVecVisitor<'a, T>
-            where
-                T: Deserialize<'de>,
-        {
-            type Value = ();
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(formatter, "an array of T")
-            }
-            fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
-                where
-                    A: SeqAccess<'de>,
-            {
-                let mut len = self.0;
-                while len > 0 {
-                    len -= 1;
-                    self.1.push(seq.next_element()?.unwrap());
-                }
-                Ok(())
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut meta = Vec::new();
+        for (column, data) in columns {
+            let expected = column.data_len(sky_light_sent);
+            if data.len() != expected {
+                return Err(Error::Serde(format!(
+                    "Chunk column ({}, {}) has {} bytes of data, its masks need {}",
+                    column.x,
+                    column.z,
+                    data.len(),
+                    expected
+                )));
             }
+            encoder.write_all(data)?;
+            meta.push(column);
         }
-        deserializer.deserialize_tuple(self.0,ExtendVecVisitor(self.0, self.1))
+        Ok(ChunkDataBulk::new(sky_light_sent, encoder.finish()?, meta))
+    }
+
+    pub fn sky_light_sent(&self) -> bool {
+        self.sky_light_sent
+    }
+
+    pub fn compressed_chunk_data(&self) -> &[u8] {
+        &self.compressed_chunk_data
+    }
+
+    pub fn meta(&self) -> &[ChunkMeta] {
+        &self.meta
+    }
+
+    /// Inflate the chunk data, the result gives access to each column
+    pub fn inflate(&self) -> Result<InflatedChunkDataBulk<'_>> {
+        let mut data = Vec::new();
+        ZlibDecoder::new(self.compressed_chunk_data.as_slice()).read_to_end(&mut data)?;
+        let expected: usize = self
+            .meta
+            .iter()
+            .map(|meta| meta.data_len(self.sky_light_sent))
+            .sum();
+        if data.len() < expected {
+            return Err(Error::Serde(format!(
+                "Chunk bulk inflated to {} bytes but its columns need {}",
+                data.len(),
+                expected
+            )));
+        }
+        Ok(InflatedChunkDataBulk { bulk: self, data })
+    }
+}
+
+pub struct InflatedChunkDataBulk<'a> {
+    bulk: &'a ChunkDataBulk,
+    data: Vec<u8>,
+}
+
+impl<'a> InflatedChunkDataBulk<'a> {
+    pub fn sky_light_sent(&self) -> bool {
+        self.bulk.sky_light_sent
+    }
+
+    /// Iterate over the columns and their inflated data
+    pub fn columns(&self) -> Columns<'_> {
+        Columns {
+            meta: self.bulk.meta.iter(),
+            data: &self.data,
+            sky_light_sent: self.bulk.sky_light_sent,
+        }
+    }
+}
+
+pub struct Columns<'a> {
+    meta: std::slice::Iter<'a, ChunkMeta>,
+    data: &'a [u8],
+    sky_light_sent: bool,
+}
+
+impl<'a> Iterator for Columns<'a> {
+    type Item = (ChunkMeta, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let meta = *self.meta.next()?;
+        let (column, rest) = self.data.split_at(meta.data_len(self.sky_light_sent));
+        self.data = rest;
+        Some((meta, column))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.meta.size_hint()
+    }
+}
+
+/// Bytes written as they are, without a length
+struct RawBytes<'a>(&'a [u8]);
+
+impl Serialize for RawBytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Serialize for ChunkDataBulk {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(4 + self.meta.len())?;
+        tuple.serialize_element(&(self.meta.len() as i16))?;
+        tuple.serialize_element(&(self.compressed_chunk_data.len() as i32))?;
+        tuple.serialize_element(&self.sky_light_sent)?;
+        tuple.serialize_element(&RawBytes(&self.compressed_chunk_data))?;
+        for meta in &self.meta {
+            tuple.serialize_element(meta)?;
+        }
+        tuple.end()
     }
 }
 
 impl<'de> Deserialize<'de> for ChunkDataBulk {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where
         D: Deserializer<'de> {
         struct BulkVisitor;
         impl<'de> Visitor<'de> for BulkVisitor {
             type Value = ChunkDataBulk;
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
                 formatter.write_str("a chunk bulk")
             }
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error> where
                 A: SeqAccess<'de>, {
 
-                let chunk_column_count: i16 = seq.next_element()?.unwrap();
-                let data_length: i32 = seq.next_element()?.unwrap();
-                let sky_light_sent: bool = seq.next_element()?.unwrap();
+                let chunk_column_count: i16 = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+                if chunk_column_count < 0 {
+                    return Err(serde::de::Error::invalid_value(
+                        Unexpected::Signed(chunk_column_count as i64),
+                        &"a column count of at least 0",
+                    ));
+                }
+                let data_length: i32 = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+                if !(0..=MAX_DATA_LEN).contains(&data_length) {
+                    return Err(serde::de::Error::invalid_value(
+                        Unexpected::Signed(data_length as i64),
+                        &"a data length between 0 and 2097151",
+                    ));
+                }
+                let sky_light_sent: bool = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(2, &self))?;
 
-                let mut compressed_chunk_data: Vec<u8> = Vec::with_capacity(data_length as usize);
-                seq.next_element_seed(ExternalLength(data_length as usize, &mut compressed_chunk_data))?.unwrap();
+                // grows as bytes are read, a truncated bulk fails before allocating its length
+                let mut compressed_chunk_data: Vec<u8> = Vec::new();
+                seq.next_element_seed(ExternalLength(data_length as usize, &mut compressed_chunk_data))?
+                    .ok_or_else(|| serde::de::Error::invalid_length(3, &self))?;
 
-                let mut meta: Vec<ChunkMeta> = Vec::with_capacity(chunk_column_count as usize);
-                seq.next_element_seed(ExternalLength(chunk_column_count as usize, &mut meta))?.unwrap();
+                let mut meta: Vec<ChunkMeta> = Vec::new();
+                seq.next_element_seed(ExternalLength(chunk_column_count as usize, &mut meta))?
+                    .ok_or_else(|| serde::de::Error::invalid_length(4, &self))?;
 
                 Ok(ChunkDataBulk {
-                    chunk_column_count,
-                    data_length,
                     sky_light_sent,
                     compressed_chunk_data,
                     meta,
                 })
             }
         }
-        let FIELDS: &'static [&'static str] = &["chunk_column_count",
+        const FIELDS: &[&str] = &["chunk_column_count",
             "data_length",
             "sky_light_sent",
             "compressed_chunk_data",
@@ -100,3 +249,64 @@ impl<'de> Deserialize<'de> for ChunkDataBulk {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::MCProtoDeserializer;
+    use crate::ser::MCProtoSerializer;
+
+    fn column(meta: ChunkMeta, fill: u8) -> Vec<u8> {
+        vec![fill; meta.data_len(true)]
+    }
+
+    #[test]
+    fn bulk_round_trip() {
+        let first = ChunkMeta { x: -1, z: 2, mask: 0b1, add_mask: 0 };
+        let second = ChunkMeta { x: 0, z: 2, mask: 0b11, add_mask: 0b10 };
+        let first_data = column(first, 1);
+        let second_data = column(second, 2);
+        let bulk = ChunkDataBulk::from_columns(
+            true,
+            vec![(first, first_data.as_slice()), (second, second_data.as_slice())],
+        )
+        .unwrap();
+
+        let mut bytes = Vec::new();
+        bulk.serialize(&mut MCProtoSerializer::new(&mut bytes)).unwrap();
+
+        let compressed_len = bulk.compressed_chunk_data().len();
+        assert_eq!(&bytes[0..2], &[0, 2]);
+        assert_eq!(&bytes[2..6], &(compressed_len as i32).to_be_bytes());
+        assert_eq!(bytes[6], 1);
+        assert_eq!(
+            &bytes[7 + compressed_len..],
+            &[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 3, 0, 2]
+        );
+
+        let decoded = ChunkDataBulk::deserialize(&mut MCProtoDeserializer::new(bytes.as_slice())).unwrap();
+        assert_eq!(decoded.meta(), &[first, second]);
+
+        let inflated = decoded.inflate().unwrap();
+        let columns: Vec<_> = inflated.columns().collect();
+        assert_eq!(columns, vec![(first, first_data.as_slice()), (second, second_data.as_slice())]);
+    }
+
+    #[test]
+    fn bulk_lengths() {
+        let decode = |bytes: &[u8]| ChunkDataBulk::deserialize(&mut MCProtoDeserializer::new(bytes));
+        let empty = decode(&[0, 0, 0, 0, 0, 0, 1]).unwrap();
+        assert!(empty.meta().is_empty() && empty.compressed_chunk_data().is_empty());
+
+        // negative column count and data length
+        let error = decode(&[0xff, 0xff, 0, 0, 0, 0, 1]).unwrap_err().to_string();
+        assert!(error.contains("a column count of at least 0"), "{}", error);
+        assert!(decode(&[0, 0, 0x80, 0, 0, 0, 1]).is_err());
+        // longer than a frame, or than the bytes left
+        assert!(decode(&[0, 0, 0x7f, 0xff, 0xff, 0xff, 1]).is_err());
+        assert!(decode(&[0, 0, 0, 0x20, 0, 0, 1]).is_err());
+        assert!(decode(&[0, 0, 0, 0x1f, 0xff, 0xff, 1, 0, 0]).is_err());
+        assert!(decode(&[0x7f, 0xff, 0, 0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+        // missing fields
+        assert!(decode(&[0, 0, 0, 0]).is_err());
+    }
+}
@@ -9,9 +9,28 @@ use serde::de::{Visitor, SeqAccess, DeserializeOwned, DeserializeSeed};
 use std::convert::TryInto;
 use core::borrow::Borrow;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct EntityMetadata(HashMap<u8, Entry>);
 
+impl EntityMetadata {
+    pub fn get(&self, index: u8) -> Option<&Entry> {
+        self.0.get(&index)
+    }
+
+    pub fn insert(&mut self, index: u8, entry: Entry) -> Option<Entry> {
+        self.0.insert(index, entry)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u8, &Entry)> {
+        self.0.iter()
+    }
+
+    /// Overwrite entries with the ones of an update
+    pub fn update(&mut self, other: &EntityMetadata) {
+        self.0.extend(other.0.iter().map(|(k, v)| (*k, v.clone())));
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Entry {
     Byte(i8),
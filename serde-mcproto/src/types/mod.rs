@@ -1,6 +1,7 @@
 pub mod var;
 pub mod array;
 pub mod uuid;
+pub mod string;
 pub mod slot;
 pub mod chunk;
 pub mod entity_metadata;
@@ -3,8 +3,8 @@ use super::nbt::GZIPNBT;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Slot {
-    id: i16,
-    count: u8,
-    damage: i16,
-    tag: Option<GZIPNBT>,
+    pub id: i16,
+    pub count: u8,
+    pub damage: i16,
+    pub tag: Option<GZIPNBT>,
 }
\ No newline at end of file
@@ -0,0 +1,318 @@
+//! Length rules of vanilla strings and Java's modified UTF-8
+//!
+//! Vanilla limits strings by UTF-16 code units, as `String.length()` counts them, so a
+//! supplementary character such as an emoji counts twice.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{SeqAccess, Visitor};
+
+use crate::error::{Error, Result};
+
+/// Limit of a string without a more specific one
+pub const MAX_STRING_LEN: usize = 32767;
+
+/// Length of a string in UTF-16 code units
+pub fn utf16_len(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+/// Fail if the string is longer than `max` UTF-16 code units
+pub fn check_len(s: &str, max: usize) -> Result<()> {
+    let len = utf16_len(s);
+    if len > max {
+        return Err(Error::Serde(format!(
+            "String is {} UTF-16 code units long, the limit is {}",
+            len, max
+        )));
+    }
+    Ok(())
+}
+
+/// String of at most `N` UTF-16 code units, checked when built, written and read
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BoundedString<const N: usize>(String);
+
+/// Player name of `LoginStart`
+pub type Username = BoundedString<16>;
+
+impl<const N: usize> BoundedString<N> {
+    pub fn new<S: Into<String>>(s: S) -> Result<Self> {
+        let s = s.into();
+        check_len(&s, N)?;
+        Ok(BoundedString(s))
+    }
+
+    /// Shorten the string to fit, never splitting a supplementary character
+    pub fn truncate<S: Into<String>>(s: S) -> Self {
+        let mut s = s.into();
+        let mut len = 0;
+        let end = s
+            .char_indices()
+            .find(|(_, c)| {
+                len += c.len_utf16();
+                len > N
+            })
+            .map(|(i, _)| i);
+        if let Some(end) = end {
+            s.truncate(end);
+        }
+        BoundedString(s)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl<const N: usize> Deref for BoundedString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<const N: usize> fmt::Display for BoundedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<const N: usize> TryFrom<String> for BoundedString<N> {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        BoundedString::new(s)
+    }
+}
+
+impl<const N: usize> TryFrom<&str> for BoundedString<N> {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        BoundedString::new(s)
+    }
+}
+
+impl<const N: usize> From<BoundedString<N>> for String {
+    fn from(s: BoundedString<N>) -> Self {
+        s.0
+    }
+}
+
+impl<const N: usize> Serialize for BoundedString<N> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        check_len(&self.0, N).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for BoundedString<N> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        BoundedString::new(s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Encode like Java's `DataOutput.writeUTF`: NUL as two bytes and supplementary
+/// characters as a surrogate pair of three bytes each
+pub fn to_modified_utf8(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut units = [0u16; 2];
+    for c in s.chars() {
+        for unit in c.encode_utf16(&mut units).iter() {
+            let unit = *unit;
+            match unit {
+                0x0001..=0x007F => bytes.push(unit as u8),
+                0x0000 | 0x0080..=0x07FF => {
+                    bytes.push(0xC0 | (unit >> 6) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+                _ => {
+                    bytes.push(0xE0 | (unit >> 12) as u8);
+                    bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+            }
+        }
+    }
+    bytes
+}
+
+/// Decode like Java's `DataInput.readUTF`
+pub fn from_modified_utf8(bytes: &[u8]) -> Result<String> {
+    let invalid = |at: usize| Error::Serde(format!("Invalid modified UTF-8 at byte {}", at));
+    let continuation = |at: usize| -> Result<u16> {
+        match bytes.get(at) {
+            Some(b) if b & 0xC0 == 0x80 => Ok((b & 0x3F) as u16),
+            _ => Err(invalid(at)),
+        }
+    };
+
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b & 0x80 == 0 {
+            units.push(b as u16);
+            i += 1;
+        } else if b & 0xE0 == 0xC0 {
+            units.push(((b & 0x1F) as u16) << 6 | continuation(i + 1)?);
+            i += 2;
+        } else if b & 0xF0 == 0xE0 {
+            units.push(((b & 0x0F) as u16) << 12 | continuation(i + 1)? << 6 | continuation(i + 2)?);
+            i += 3;
+        } else {
+            return Err(invalid(i));
+        }
+    }
+    String::from_utf16(&units).map_err(|e| Error::Serde(format!("Invalid modified UTF-8: {}", e)))
+}
+
+/// String written like Java's `DataOutput.writeUTF`, a u16 byte length followed by
+/// modified UTF-8
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ModifiedUtf8String(pub String);
+
+impl Deref for ModifiedUtf8String {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for ModifiedUtf8String {
+    fn from(s: String) -> Self {
+        ModifiedUtf8String(s)
+    }
+}
+
+impl Serialize for ModifiedUtf8String {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let encoded = to_modified_utf8(&self.0);
+        if encoded.len() > u16::MAX as usize {
+            return Err(serde::ser::Error::custom(format!(
+                "Modified UTF-8 string is {} bytes long, the limit is {}",
+                encoded.len(),
+                u16::MAX
+            )));
+        }
+        let mut bytes = Vec::with_capacity(encoded.len() + 2);
+        bytes.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&encoded);
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for ModifiedUtf8String {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ModifiedUtf8Visitor;
+
+        impl<'de> Visitor<'de> for ModifiedUtf8Visitor {
+            type Value = ModifiedUtf8String;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a modified UTF-8 string")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let len: u16 = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+                let mut bytes = Vec::with_capacity(len as usize);
+                for i in 0..len as usize {
+                    bytes.push(
+                        seq.next_element()?
+                            .ok_or_else(|| serde::de::Error::invalid_length(i + 1, &self))?,
+                    );
+                }
+                from_modified_utf8(&bytes)
+                    .map(ModifiedUtf8String)
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+        deserializer.deserialize_tuple(u16::MAX as usize + 1, ModifiedUtf8Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::MCProtoDeserializer;
+    use crate::ser::MCProtoSerializer;
+
+    #[test]
+    fn utf16_length() {
+        assert_eq!(utf16_len("Notch"), 5);
+        assert_eq!(utf16_len("\u{e9}"), 1);
+        assert_eq!(utf16_len("\u{1F600}"), 2);
+
+        assert!(Username::new("0123456789abcdef").is_ok());
+        assert!(Username::new("0123456789abcdefg").is_err());
+        // 8 emoji are 32 bytes but only 16 code units
+        assert!(Username::new("\u{1F600}".repeat(8)).is_ok());
+        assert!(Username::new(format!("a{}", "\u{1F600}".repeat(8))).is_err());
+        assert_eq!(
+            BoundedString::<3>::truncate("a\u{1F600}\u{1F600}").as_str(),
+            "a\u{1F600}"
+        );
+    }
+
+    #[test]
+    fn bounded_on_read() {
+        let mut bytes = Vec::new();
+        "0123456789abcdefg"
+            .serialize(&mut MCProtoSerializer::new(&mut bytes))
+            .unwrap();
+        assert!(Username::deserialize(&mut MCProtoDeserializer::new(bytes.as_slice())).is_err());
+        assert!(
+            BoundedString::<17>::deserialize(&mut MCProtoDeserializer::new(bytes.as_slice())).is_ok()
+        );
+    }
+
+    #[test]
+    fn modified_utf8() {
+        let s = "a\u{0}\u{e9}\u{1F600}";
+        let encoded = to_modified_utf8(s);
+        assert_eq!(
+            encoded,
+            vec![0x61, 0xC0, 0x80, 0xC3, 0xA9, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80]
+        );
+        assert_eq!(from_modified_utf8(&encoded).unwrap(), s);
+        assert!(from_modified_utf8(&[0xC3]).is_err());
+        // lone surrogate
+        assert!(from_modified_utf8(&[0xED, 0xA0, 0xBD]).is_err());
+
+        let mut bytes = Vec::new();
+        ModifiedUtf8String(s.to_owned())
+            .serialize(&mut MCProtoSerializer::new(&mut bytes))
+            .unwrap();
+        assert_eq!(&bytes[..2], &[0, 11]);
+        let decoded = ModifiedUtf8String::deserialize(&mut MCProtoDeserializer::new(bytes.as_slice()));
+        assert_eq!(decoded.unwrap().0, s);
+    }
+}
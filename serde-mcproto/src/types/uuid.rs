@@ -1,101 +1,274 @@
+//! UUIDs and their wire encodings
+//!
+//! Every type wraps the same `uuid::Uuid` and differs only in how it is written:
+//!
+//! * [`Uuid`] - hyphenated string, e.g. `LoginSuccess` and `SpawnPlayer`
+//! * [`UuidSimple`] - dashless string, as returned by the session server
+//! * [`UuidBinary`] - most and least significant bits as two big-endian longs
+//!
+//! The bytes of a `uuid::Uuid` are in the same order as Java's most/least significant
+//! longs, so the encodings round-trip identically on every platform.
+
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
-use serde::de::Visitor;
+use serde::de::{SeqAccess, Visitor};
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// Java's `UUID.nameUUIDFromBytes`: the MD5 of the name made a version 3 UUID
+pub fn name_uuid_from_bytes(name: &[u8]) -> uuid::Uuid {
+    let mut bytes: uuid::Bytes = md5::compute(name).into();
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    uuid::Uuid::from_bytes(bytes)
+}
+
+/// UUID vanilla assigns to a player of an offline mode server
+pub fn offline_uuid(username: &str) -> uuid::Uuid {
+    name_uuid_from_bytes(format!("OfflinePlayer:{}", username).as_bytes())
+}
+
+/// Parse a UUID with or without dashes, session server responses use the dashless form
+pub fn parse_uuid(s: &str) -> crate::error::Result<uuid::Uuid> {
+    uuid::Uuid::parse_str(s).map_err(|e| Error::Serde(format!("Invalid UUID {}: {}", s, e)))
+}
+
+/// Build a UUID from Java's `getMostSignificantBits` and `getLeastSignificantBits`
+pub fn from_bits(most_significant: i64, least_significant: i64) -> uuid::Uuid {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&most_significant.to_be_bytes());
+    bytes[8..].copy_from_slice(&least_significant.to_be_bytes());
+    uuid::Uuid::from_bytes(bytes)
+}
+
+/// Java's most and least significant bits of a UUID
+pub fn to_bits(uuid: &uuid::Uuid) -> (i64, i64) {
+    let bytes = uuid.as_bytes();
+    let mut most_significant = [0u8; 8];
+    let mut least_significant = [0u8; 8];
+    most_significant.copy_from_slice(&bytes[..8]);
+    least_significant.copy_from_slice(&bytes[8..]);
+    (i64::from_be_bytes(most_significant), i64::from_be_bytes(least_significant))
+}
+
+/// Constructors and conversions shared by every encoding
+macro_rules! uuid_encoding {
+    ($name:ident) => {
+        impl $name {
+            pub fn offline(username: &str) -> Self {
+                $name(offline_uuid(username))
+            }
+
+            pub fn from_bits(most_significant: i64, least_significant: i64) -> Self {
+                $name(from_bits(most_significant, least_significant))
+            }
+
+            pub fn to_bits(&self) -> (i64, i64) {
+                to_bits(&self.0)
+            }
+
+            /// Hyphenated form
+            pub fn to_hyphenated_string(&self) -> String {
+                self.0.to_hyphenated().to_string()
+            }
+
+            /// Dashless hexadecimal form
+            pub fn to_simple_string(&self) -> String {
+                self.0.to_simple().to_string()
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> crate::error::Result<Self> {
+                parse_uuid(s).map($name)
+            }
+        }
+
+        impl From<uuid::Uuid> for $name {
+            #[inline]
+            fn from(u: uuid::Uuid) -> Self {
+                $name(u)
+            }
+        }
+
+        impl From<$name> for uuid::Uuid {
+            #[inline]
+            fn from(u: $name) -> Self {
+                u.0
+            }
+        }
+    };
+}
+
+/// Converts between two encodings of the same UUID
+macro_rules! uuid_conversion {
+    ($from:ident => $to:ident) => {
+        impl From<$from> for $to {
+            #[inline]
+            fn from(u: $from) -> Self {
+                $to(u.0)
+            }
+        }
+    };
+}
 
-#[derive(Debug, Clone)]
+/// UUID sent as a hyphenated string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Uuid(pub uuid::Uuid);
 
-impl Into<uuid::Uuid> for Uuid {
-    #[inline]
-    fn into(self) -> uuid::Uuid {
-        self.0
+/// UUID sent as a dashless string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UuidSimple(pub uuid::Uuid);
+
+/// UUID sent as its most and least significant bits, two big-endian longs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UuidBinary(pub uuid::Uuid);
+
+uuid_encoding!(Uuid);
+uuid_encoding!(UuidSimple);
+uuid_encoding!(UuidBinary);
+
+uuid_conversion!(Uuid => UuidSimple);
+uuid_conversion!(Uuid => UuidBinary);
+uuid_conversion!(UuidSimple => Uuid);
+uuid_conversion!(UuidSimple => UuidBinary);
+uuid_conversion!(UuidBinary => Uuid);
+uuid_conversion!(UuidBinary => UuidSimple);
+
+struct StringVisitor;
+
+impl<'a> Visitor<'a> for StringVisitor {
+    type Value = uuid::Uuid;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        formatter.write_str("a UUID string")
     }
-}
 
-impl From<uuid::Uuid> for Uuid {
-    #[inline]
-    fn from(u: uuid::Uuid) -> Self {
-        Uuid(u)
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where
+        E: serde::de::Error, {
+        uuid::Uuid::parse_str(v).map_err(|e| serde::de::Error::custom(e.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E> where
+        E: serde::de::Error, {
+        self.visit_str(&v)
     }
 }
 
 impl Serialize for Uuid {
     fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error> where
         S: Serializer {
-        serializer.serialize_str(&*self.0.to_hyphenated().to_string())
+        serializer.serialize_str(&self.to_hyphenated_string())
     }
 }
 
 impl<'de> Deserialize<'de> for Uuid {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
         D: Deserializer<'de> {
-        struct UUIDVisitor;
-
-        impl<'a> Visitor<'a> for UUIDVisitor {
-            type Value = Uuid;
-
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-                Ok(())
-            }
-
-            fn visit_string<E>(self, v: String) -> Result<Self::Value, E> where
-                E: serde::de::Error, {
-                use std::str::FromStr;
-                uuid::Uuid::from_str(&*v)
-                    .map(|v| Uuid(v))
-                    .map_err(|e| serde::de::Error::custom(e.to_string()))
-            }
-        }
-        deserializer.deserialize_string(UUIDVisitor)
+        deserializer.deserialize_string(StringVisitor).map(Uuid)
     }
 }
 
-
-#[derive(Debug, Clone)]
-pub struct Uuidi128(pub uuid::Uuid);
-
-impl Into<uuid::Uuid> for Uuidi128 {
-    #[inline]
-    fn into(self) -> uuid::Uuid {
-        self.0
+impl Serialize for UuidSimple {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error> where
+        S: Serializer {
+        serializer.serialize_str(&self.to_simple_string())
     }
 }
 
-impl From<uuid::Uuid> for Uuidi128 {
-    #[inline]
-    fn from(u: uuid::Uuid) -> Self {
-        Uuidi128(u)
+impl<'de> Deserialize<'de> for UuidSimple {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
+        D: Deserializer<'de> {
+        deserializer.deserialize_string(StringVisitor).map(UuidSimple)
     }
 }
 
-impl Serialize for Uuidi128 {
+impl Serialize for UuidBinary {
     fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error> where
         S: Serializer {
-        let mut bytes = self.0.as_bytes().as_ref();
-        let uuid = byteorder::ReadBytesExt::read_u128::<byteorder::NativeEndian>(&mut bytes).unwrap();
-        serializer.serialize_u128(uuid)
+        serializer.serialize_bytes(self.0.as_bytes())
     }
 }
 
-impl<'de> Deserialize<'de> for Uuidi128 {
+impl<'de> Deserialize<'de> for UuidBinary {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
         D: Deserializer<'de> {
-        struct UUIDVisitor;
+        struct BinaryVisitor;
 
-        impl<'a> Visitor<'a> for UUIDVisitor {
-            type Value = Uuidi128;
+        impl<'a> Visitor<'a> for BinaryVisitor {
+            type Value = UuidBinary;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-                Ok(())
+                formatter.write_str("two longs")
             }
-            serde::serde_if_integer128! {
-                fn visit_u128<E>(self, v: u128) -> Result<Self::Value, E> where
-                    E: serde::de::Error, {
-                        Ok(Uuidi128(uuid::Uuid::from(v)))
-                }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where
+                A: SeqAccess<'a>, {
+                let most_significant: i64 = seq.next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+                let least_significant: i64 = seq.next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+                Ok(UuidBinary::from_bits(most_significant, least_significant))
             }
         }
-        deserializer.deserialize_u128(UUIDVisitor)
+        deserializer.deserialize_tuple(2, BinaryVisitor)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::MCProtoDeserializer;
+    use crate::ser::MCProtoSerializer;
+
+    /// Notch, as sent by a vanilla 1.7.10 server
+    const HYPHENATED: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+    const SIMPLE: &str = "069a79f444e94726a5befca90e38aaf5";
+    const BINARY: [u8; 16] = [
+        0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x47, 0x26,
+        0xa5, 0xbe, 0xfc, 0xa9, 0x0e, 0x38, 0xaa, 0xf5,
+    ];
+
+    fn to_bytes<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.serialize(&mut MCProtoSerializer::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> T {
+        T::deserialize(&mut MCProtoDeserializer::new(bytes)).unwrap()
+    }
+
+    fn string_dump(s: &str) -> Vec<u8> {
+        let mut bytes = vec![s.len() as u8];
+        bytes.extend_from_slice(s.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn binary_matches_vanilla() {
+        let uuid: UuidBinary = HYPHENATED.parse().unwrap();
+        assert_eq!(uuid.to_bits(), (0x069a79f444e94726, 0xa5befca90e38aaf5u64 as i64));
+        assert_eq!(to_bytes(&uuid), BINARY.to_vec());
+        assert_eq!(from_bytes::<UuidBinary>(&BINARY), uuid);
+    }
+
+    #[test]
+    fn strings_match_vanilla() {
+        let uuid: Uuid = SIMPLE.parse().unwrap();
+        let hyphenated = string_dump(HYPHENATED);
+        assert_eq!(to_bytes(&uuid), hyphenated);
+        assert_eq!(from_bytes::<Uuid>(&hyphenated), uuid);
 
+        let simple = string_dump(SIMPLE);
+        assert_eq!(to_bytes(&UuidSimple::from(uuid)), simple);
+        assert_eq!(from_bytes::<UuidSimple>(&simple), UuidSimple::from(uuid));
+    }
+
+    #[test]
+    fn offline() {
+        assert_eq!(Uuid::offline("Notch").to_hyphenated_string(), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+    }
+}
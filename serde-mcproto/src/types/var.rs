@@ -3,6 +3,11 @@ use serde::de::{DeserializeSeed, MapAccess, Visitor};
 use serde::export::Formatter;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// Longest encoding of a VarInt
+pub const MAX_VARINT_LEN: usize = 5;
+/// Longest encoding of a VarLong
+pub const MAX_VARLONG_LEN: usize = 10;
+
 #[derive(Debug, Clone)]
 pub struct VarInt(pub i32);
 
@@ -14,9 +19,11 @@ impl Serialize for VarInt {
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_varint(&self.0, &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let mut bytes = [0u8; MAX_VARINT_LEN];
+        let mut writer = &mut bytes[..];
+        write_varint(&self.0, &mut writer).map_err(serde::ser::Error::custom)?;
+        let len = MAX_VARINT_LEN - writer.len();
+        serializer.serialize_bytes(&bytes[..len])
     }
 }
 
@@ -25,9 +32,11 @@ impl Serialize for VarLong {
     where
         S: Serializer,
     {
-        let mut bytes: Vec<u8> = Vec::new();
-        write_varlong(&self.0, &mut bytes);
-        serializer.serialize_bytes(bytes.as_slice())
+        let mut bytes = [0u8; MAX_VARLONG_LEN];
+        let mut writer = &mut bytes[..];
+        write_varlong(&self.0, &mut writer).map_err(serde::ser::Error::custom)?;
+        let len = MAX_VARLONG_LEN - writer.len();
+        serializer.serialize_bytes(&bytes[..len])
     }
 }
 
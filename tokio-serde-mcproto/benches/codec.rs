@@ -0,0 +1,207 @@
+//! Throughput of the codecs on play traffic, and of relaying it as frames
+//!
+//! `cargo bench -p tokio-serde-mcproto`, or `cargo bench -p tokio-serde-mcproto -- relay`.
+
+use bytes::BytesMut;
+use criterion::{black_box, BatchSize, criterion_group, criterion_main, Criterion, Throughput};
+use tokio_util::codec::{Decoder, Encoder};
+
+use mcproto::v1_7_10::PlayToClient;
+use serde_mcproto::types::array::IntPrefix;
+use serde_mcproto::types::chunk::{ChunkDataBulk, ChunkMeta};
+use serde_mcproto::types::var::VarInt;
+use tokio_serde_mcproto::codec::MCProtoCodec;
+use tokio_serde_mcproto::frame::FrameCodec;
+
+/// Movement heavy traffic of a busy server with a chunk now and then
+fn traffic() -> Vec<PlayToClient> {
+    let meta = ChunkMeta {
+        x: 0,
+        z: 0,
+        mask: 0x000f,
+        add_mask: 0,
+    };
+    let column = vec![1u8; meta.data_len(true)];
+    let chunk = ChunkDataBulk::from_columns(true, vec![(meta, column.as_slice())]).unwrap();
+    let mut packets = Vec::new();
+    for i in 0..100 {
+        packets.push(PlayToClient::EntityRelativeMove {
+            entity_id: i,
+            delta: [1, 0, -1],
+        });
+        packets.push(PlayToClient::EntityHeadLook {
+            entity_id: i,
+            head_yaw: 64,
+        });
+        packets.push(PlayToClient::EntityVelocity {
+            entity_id: i,
+            velocity: [100, -200, 0],
+        });
+        if i % 10 == 0 {
+            packets.push(PlayToClient::EntityTeleport {
+                entity_id: i,
+                position: [3200, 2048, -3200],
+                yaw: 0,
+                pitch: 0,
+            });
+            packets.push(PlayToClient::BlockChange {
+                x: i,
+                y: 64,
+                z: -i,
+                block_type: VarInt(1),
+                metadata: 0,
+            });
+        }
+    }
+    packets.push(PlayToClient::TimeUpdate {
+        world_age: 1000,
+        time_of_day: 6000,
+    });
+    packets.push(PlayToClient::KeepAlive { keep_alive_id: 7 });
+    packets.push(PlayToClient::ChunkDataBulk(chunk));
+    packets
+}
+
+/// Full columns sent one by one as `ChunkData`, their compressed data as large as it gets
+fn chunks() -> Vec<PlayToClient> {
+    (0..8)
+        .map(|i| PlayToClient::ChunkData {
+            x: i,
+            z: 0,
+            ground_up: true,
+            bit_map: 0xffff,
+            add_bit_map: 0,
+            chunk_data: IntPrefix::new((0..100_000u32).map(|b| (b.wrapping_mul(2_654_435_761) >> 24) as u8).collect()),
+        })
+        .collect()
+}
+
+fn wire(packets: &[PlayToClient]) -> BytesMut {
+    let mut codec = MCProtoCodec::<PlayToClient, ()>::new();
+    let mut wire = BytesMut::new();
+    for packet in packets {
+        codec.encode(packet.clone(), &mut wire).unwrap();
+    }
+    wire
+}
+
+/// `WindowItems` of a full player inventory, which the encoder cannot write yet
+fn window_items() -> BytesMut {
+    let mut body = vec![0x30, 0];
+    body.extend_from_slice(&45i16.to_be_bytes());
+    for i in 0..45i16 {
+        body.extend_from_slice(&(i + 1).to_be_bytes());
+        body.extend_from_slice(&[64, 0, 0, 0xff, 0xff]);
+    }
+    let mut wire = Vec::new();
+    serde_mcproto::write_varint(&(body.len() as i32), &mut wire).unwrap();
+    wire.extend_from_slice(&body);
+    BytesMut::from(&wire[..])
+}
+
+fn codec(c: &mut Criterion) {
+    let packets = traffic();
+    let wire = wire(&packets);
+    let mut group = c.benchmark_group("codec");
+    group.throughput(Throughput::Bytes(wire.len() as u64));
+    group.bench_function("encode", |b| {
+        let mut codec = MCProtoCodec::<PlayToClient, ()>::new();
+        let mut dst = BytesMut::with_capacity(wire.len());
+        b.iter_batched(
+            || packets.clone(),
+            |packets| {
+                dst.clear();
+                for packet in packets {
+                    codec.encode(packet, &mut dst).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("decode", |b| {
+        let mut codec = MCProtoCodec::<(), PlayToClient>::new();
+        b.iter(|| {
+            let mut src = wire.clone();
+            while let Some(packet) = codec.decode(&mut src).unwrap() {
+                black_box(packet);
+            }
+        })
+    });
+    group.finish();
+
+    let chunks = chunks();
+    let mut group = c.benchmark_group("chunks");
+    group.throughput(Throughput::Bytes(self::wire(&chunks).len() as u64));
+    group.bench_function("encode", |b| {
+        let mut codec = MCProtoCodec::<PlayToClient, ()>::new();
+        let mut dst = BytesMut::new();
+        b.iter_batched(
+            || chunks.clone(),
+            |packets| {
+                dst.clear();
+                for packet in packets {
+                    codec.encode(packet, &mut dst).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+
+    let window_items = window_items();
+    let mut group = c.benchmark_group("window_items");
+    group.throughput(Throughput::Bytes(window_items.len() as u64));
+    group.bench_function("decode", |b| {
+        let mut codec = MCProtoCodec::<(), PlayToClient>::new();
+        b.iter(|| codec.decode(&mut window_items.clone()).unwrap().unwrap())
+    });
+    group.finish();
+}
+
+/// Reading and writing back every packet, as the proxy does
+fn relay(c: &mut Criterion) {
+    let wire = wire(&traffic());
+    let mut group = c.benchmark_group("relay");
+    group.throughput(Throughput::Bytes(wire.len() as u64));
+    group.bench_function("packets", |b| {
+        let mut codec = MCProtoCodec::<PlayToClient, PlayToClient>::new();
+        let mut dst = BytesMut::with_capacity(wire.len());
+        b.iter(|| {
+            let mut src = wire.clone();
+            dst.clear();
+            while let Some(packet) = codec.decode(&mut src).unwrap() {
+                codec.encode(packet, &mut dst).unwrap();
+            }
+        })
+    });
+    group.bench_function("frames", |b| {
+        let mut codec = FrameCodec::<PlayToClient, PlayToClient>::new();
+        let mut dst = BytesMut::with_capacity(wire.len());
+        b.iter(|| {
+            let mut src = wire.clone();
+            dst.clear();
+            while let Some(frame) = codec.decode(&mut src).unwrap() {
+                codec.encode(frame, &mut dst).unwrap();
+            }
+        })
+    });
+    group.bench_function("frames_by_name", |b| {
+        let mut codec = FrameCodec::<PlayToClient, PlayToClient>::new();
+        let mut dst = BytesMut::with_capacity(wire.len());
+        b.iter(|| {
+            let mut src = wire.clone();
+            dst.clear();
+            while let Some(mut frame) = codec.decode(&mut src).unwrap() {
+                // a hook looking at the keep alives only
+                if frame.name() == Some("KeepAlive") {
+                    black_box(frame.packet().unwrap());
+                }
+                codec.encode(frame, &mut dst).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, codec, relay);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Blocking calls kept off the executor threads
+//!
+//! Session server requests block for a network round trip. They run on the blocking pool of
+//! tokio and are awaited, so other connections go on and timeouts still apply.
+
+use std::panic;
+
+use tokio::task;
+
+/// Run `f` on the blocking pool and wait for its result
+pub async fn run<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(_) => panic!("runtime shut down during a blocking call"),
+    }
+}
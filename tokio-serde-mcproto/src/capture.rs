@@ -0,0 +1,439 @@
+//! Recording of connections and their replay
+//!
+//! A capture starts with a header followed by frames, every number big-endian:
+//!
+//! ```text
+//! header: magic "MCPC", format version u8, protocol version i32, start in unix millis u64
+//! frame:  micros since the start u64, direction u8, state u8, length u32, packet
+//! ```
+//!
+//! A packet is its id and fields, as framed by `MCProtoCodec` but without the length prefix
+//! and before encryption. Directions and states are the ids of [`Direction`] and [`State`].
+//!
+//! A [`Recorder`] given to `MCProtoCodec::enable_recording` writes every packet the codec
+//! sends and receives. It follows the state of the connection by watching the handshake
+//! and `LoginSuccess`, so it keeps working when the codec is switched to other packets.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "tokio")]
+use futures::{Sink, SinkExt};
+use mcproto::packet::{Direction, State};
+#[cfg(feature = "tokio")]
+use mcproto::v1_7_10::PlayToClient;
+use mcproto::v1_7_10::{Handshake, NextState, PROTOCOL_VERSION};
+use serde::de::DeserializeOwned;
+use serde_mcproto::de::MCProtoDeserializer;
+use serde_mcproto::error::Error;
+#[cfg(feature = "tokio")]
+use tokio::time;
+
+pub const MAGIC: [u8; 4] = *b"MCPC";
+pub const FORMAT_VERSION: u8 = 1;
+/// Bytes of the header, the first frame starts after it
+pub const HEADER_LEN: usize = 17;
+/// Bytes of a frame before its packet
+pub const FRAME_PREFIX_LEN: usize = 14;
+
+/// Id of `LoginSuccess`, after which the connection is in the play state
+const LOGIN_SUCCESS_ID: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub protocol_version: i32,
+    /// Unix time in milliseconds
+    pub started_at: u64,
+}
+
+/// Packet of a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Time since the start of the capture
+    pub time: Duration,
+    pub direction: Direction,
+    pub state: State,
+    /// Packet id and fields
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Id of the packet, the VarInt at the start of the data
+    pub fn packet_id(&self) -> Result<i32, Error> {
+        serde_mcproto::read_varint(&mut self.data.as_slice())
+    }
+
+    /// Decode into packets of the frame's state and direction, e.g. `PlayToClient`
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        serde_mcproto::de::deserialize(&mut MCProtoDeserializer::new(self.data.as_slice()))
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, header: Header) -> io::Result<CaptureWriter<W>> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&header.protocol_version.to_be_bytes())?;
+        writer.write_all(&header.started_at.to_be_bytes())?;
+        Ok(CaptureWriter { writer })
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let micros = frame.time.as_micros().min(u64::MAX as u128) as u64;
+        if frame.data.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Frame is too long"));
+        }
+        self.writer.write_all(&micros.to_be_bytes())?;
+        self.writer.write_all(&[frame.direction.id(), frame.state.id()])?;
+        self.writer.write_all(&(frame.data.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&frame.data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Frames of a capture, in the order they were recorded
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header: Header,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CaptureReader<BufReader<File>>> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> io::Result<CaptureReader<R>> {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid("Not a capture"));
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(invalid(format!("Unsupported capture format version {}", header[4])));
+        }
+        let mut protocol_version = [0u8; 4];
+        protocol_version.copy_from_slice(&header[5..9]);
+        let mut started_at = [0u8; 8];
+        started_at.copy_from_slice(&header[9..]);
+        Ok(CaptureReader {
+            reader,
+            header: Header {
+                protocol_version: i32::from_be_bytes(protocol_version),
+                started_at: u64::from_be_bytes(started_at),
+            },
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Next frame, `None` at the end of the capture
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        let mut prefix = [0u8; FRAME_PREFIX_LEN];
+        // a capture may end between frames but not inside one
+        let read = read_full(&mut self.reader, &mut prefix)?;
+        if read == 0 {
+            return Ok(None);
+        } else if read < prefix.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let mut micros = [0u8; 8];
+        micros.copy_from_slice(&prefix[..8]);
+        let direction = Direction::from_id(prefix[8])
+            .ok_or_else(|| invalid(format!("Invalid direction {}", prefix[8])))?;
+        let state =
+            State::from_id(prefix[9]).ok_or_else(|| invalid(format!("Invalid state {}", prefix[9])))?;
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&prefix[10..]);
+
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(u32::from_be_bytes(len) as u64)
+            .read_to_end(&mut data)?;
+        if data.len() != u32::from_be_bytes(len) as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(Frame {
+            time: Duration::from_micros(u64::from_be_bytes(micros)),
+            direction,
+            state,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<io::Result<Frame>> {
+        self.read_frame().transpose()
+    }
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Like `read_exact`, but tells how much was read when the reader ends early
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+struct Recording {
+    writer: CaptureWriter<Box<dyn Write + Send>>,
+    start: Instant,
+    state: State,
+}
+
+/// Capture shared by the codecs of a connection, cloning gives another handle to it
+#[derive(Clone)]
+pub struct Recorder {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Recorder {
+    /// Start a capture of a connection which has not sent its handshake yet
+    pub fn new<W: Write + Send + 'static>(writer: W) -> io::Result<Recorder> {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or(0);
+        let header = Header {
+            protocol_version: PROTOCOL_VERSION.0,
+            started_at,
+        };
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Ok(Recorder {
+            recording: Arc::new(Mutex::new(Recording {
+                writer: CaptureWriter::new(writer, header)?,
+                start: Instant::now(),
+                state: State::Handshake,
+            })),
+        })
+    }
+
+    /// Capture into a new file, buffered until [`Recorder::flush`] or the last handle drops
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        Recorder::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Write a packet, `data` is its id and fields
+    pub fn record(&self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let mut recording = self.recording.lock().unwrap_or_else(|e| e.into_inner());
+        let frame = Frame {
+            time: recording.start.elapsed(),
+            direction,
+            state: recording.state,
+            data: data.to_vec(),
+        };
+        recording.writer.write_frame(&frame)?;
+        recording.state = next_state(&frame);
+        Ok(())
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.recording
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .writer
+            .flush()
+    }
+}
+
+/// State of the connection after `frame`
+fn next_state(frame: &Frame) -> State {
+    match (frame.state, frame.direction) {
+        (State::Handshake, Direction::ToServer) => match frame.decode() {
+            Ok(Handshake::Handshake {
+                next_state: NextState::Status,
+                ..
+            }) => State::Status,
+            Ok(Handshake::Handshake {
+                next_state: NextState::Login,
+                ..
+            }) => State::Login,
+            _ => State::Handshake,
+        },
+        (State::Login, Direction::ToClient) if frame.data.first() == Some(&LOGIN_SUCCESS_ID) => {
+            State::Play
+        }
+        (state, _) => state,
+    }
+}
+
+/// Packets sent by [`replay`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Replayed {
+    pub sent: usize,
+    /// Frames which did not decode as `PlayToClient`, e.g. of another protocol version
+    pub skipped: usize,
+}
+
+/// Send the `PlayToClient` packets of a capture to a client in the play state.
+///
+/// `speed` scales the recorded timing from the first of these packets, which is sent right
+/// away: 1.0 waits as long as the original connection, 2.0 half as long and `f64::INFINITY`
+/// does not wait at all. Frames which do not decode are skipped and counted.
+#[cfg(feature = "tokio")]
+pub async fn replay<I, K, E>(frames: I, sink: &mut K, speed: f64) -> Result<Replayed, E>
+where
+    I: IntoIterator<Item = io::Result<Frame>>,
+    K: Sink<PlayToClient, Error = E> + Unpin,
+    E: From<io::Error>,
+{
+    let start = time::Instant::now();
+    let mut first = None;
+    let mut replayed = Replayed::default();
+    for frame in frames {
+        let frame = frame?;
+        if frame.state != State::Play || frame.direction != Direction::ToClient {
+            continue;
+        }
+        let packet: PlayToClient = match frame.decode() {
+            Ok(packet) => packet,
+            Err(_) => {
+                replayed.skipped += 1;
+                continue;
+            }
+        };
+        let first = *first.get_or_insert(frame.time);
+        let wait = frame.time.saturating_sub(first).as_secs_f64() / speed;
+        if wait > 0.0 && wait.is_finite() {
+            let at = start + Duration::from_secs_f64(wait);
+            time::sleep_until(at).await;
+        }
+        sink.send(packet).await?;
+        replayed.sent += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcproto::v1_7_10::PlayToClient;
+
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn recorder_follows_state() {
+        let bytes = Shared(Arc::new(Mutex::new(Vec::new())));
+        let recorder = Recorder::new(bytes.clone()).unwrap();
+        // Handshake to login: id, protocol 5, "a", port 25565, next state login
+        recorder
+            .record(Direction::ToServer, &[0x00, 0x05, 0x01, b'a', 0x63, 0xdd, 0x02])
+            .unwrap();
+        // LoginStart, LoginSuccess, then a KeepAlive of the play state
+        recorder.record(Direction::ToServer, &[0x00, 0x01, b'a']).unwrap();
+        recorder.record(Direction::ToClient, &[0x02, 0x00, 0x00]).unwrap();
+        recorder.record(Direction::ToClient, &[0x00, 0, 0, 0, 7]).unwrap();
+
+        let bytes = bytes.0.lock().unwrap().clone();
+        let mut reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.header().protocol_version, 5);
+        let frames = reader.by_ref().collect::<io::Result<Vec<_>>>().unwrap();
+        let states: Vec<_> = frames.iter().map(|frame| frame.state).collect();
+        assert_eq!(states, vec![State::Handshake, State::Login, State::Login, State::Play]);
+        match frames[3].decode::<PlayToClient>().unwrap() {
+            PlayToClient::KeepAlive { keep_alive_id } => assert_eq!(keep_alive_id, 7),
+            packet => panic!("Unexpected {:?}", packet),
+        }
+
+        // cut inside the last frame
+        let mut reader = CaptureReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(reader.by_ref().take(3).count(), 3);
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    fn frame(secs: u64, direction: Direction, data: &[u8]) -> io::Result<Frame> {
+        Ok(Frame {
+            time: Duration::from_secs(secs),
+            direction,
+            state: State::Play,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Sink keeping the packets sent to it
+    #[cfg(feature = "tokio")]
+    fn sent() -> futures::sink::SinkMapErr<Vec<PlayToClient>, fn(std::convert::Infallible) -> io::Error> {
+        Vec::new().sink_map_err(|never| match never {})
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn replay_skips_what_does_not_decode() {
+        let frames = vec![
+            frame(1, Direction::ToClient, &[0x00, 0, 0, 0, 1]),
+            // a KeepAlive cut short
+            frame(2, Direction::ToClient, &[0x00, 0]),
+            frame(3, Direction::ToServer, &[0x00, 0, 0, 0, 2]),
+            frame(3600, Direction::ToClient, &[0x00, 0, 0, 0, 3]),
+        ];
+        let mut sink = sent();
+        let replayed = replay(frames, &mut sink, f64::INFINITY).await.unwrap();
+        assert_eq!(replayed, Replayed { sent: 2, skipped: 1 });
+        let ids: Vec<_> = sink
+            .get_ref()
+            .iter()
+            .map(|packet| match packet {
+                PlayToClient::KeepAlive { keep_alive_id } => *keep_alive_id,
+                packet => panic!("Unexpected {:?}", packet),
+            })
+            .collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn replay_waits_from_the_first_packet() {
+        // an hour of login before the play state starts
+        let frames = vec![
+            frame(3600, Direction::ToClient, &[0x00, 0, 0, 0, 1]),
+            frame(3610, Direction::ToClient, &[0x00, 0, 0, 0, 2]),
+        ];
+        let start = time::Instant::now();
+        let replayed = replay(frames, &mut sent(), 2.0).await.unwrap();
+        assert_eq!(replayed.sent, 2);
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,514 @@
+//! Client connection: handshake, login and the play state
+//!
+//! Encryption is enabled when the server asks for it. 1.7.10 has no compression, so there
+//! is nothing to negotiate for it.
+//!
+//! ```ignore
+//! let mut client = Client::connect("localhost", 25565, "Steve").await?;
+//! while let Some(packet) = client.next().await {
+//!     match packet {
+//!         Ok(packet) => println!("{:?}", packet),
+//!         Err(ClientError::Disconnected(reason)) => println!("Kicked: {}", reason),
+//!         Err(e) => return Err(e),
+//!     }
+//! }
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::{ready, Sink, SinkExt, Stream, StreamExt};
+use mcproto::auth::{self, EncryptionReply, HttpClient, SessionServer};
+use mcproto::types::array::ShortPrefix;
+use mcproto::types::string::{BoundedString, Username};
+use mcproto::types::uuid::{Uuid, UuidSimple};
+use mcproto::v1_7_10::{
+    Handshake, LoginToClient, LoginToServer, NextState, PlayToClient, PlayToServer,
+    PROTOCOL_VERSION,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use crate::blocking;
+use crate::codec::{switch_codec, MCProtoCodec};
+
+pub type ClientPlayFramed<S> = Framed<S, MCProtoCodec<PlayToServer, PlayToClient>>;
+type ClientLoginFramed<S> = Framed<S, MCProtoCodec<LoginToServer, LoginToClient>>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Codec(serde_mcproto::error::Error),
+    Auth(auth::Error),
+    /// Server sent `Disconnect`, the reason is a JSON chat message
+    Disconnected(String),
+    /// Connection closed without a `Disconnect`
+    Closed,
+    /// Server is in online mode but no credentials were given
+    AuthRequired,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => e.fmt(f),
+            ClientError::Codec(e) => e.fmt(f),
+            ClientError::Auth(e) => e.fmt(f),
+            ClientError::Disconnected(reason) => write!(f, "Disconnected: {}", reason),
+            ClientError::Closed => write!(f, "Connection closed"),
+            ClientError::AuthRequired => write!(f, "Server is in online mode"),
+        }
+    }
+}
+
+impl StdError for ClientError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::Codec(e) => Some(e),
+            ClientError::Auth(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+impl From<serde_mcproto::error::Error> for ClientError {
+    fn from(e: serde_mcproto::error::Error) -> ClientError {
+        match e {
+            serde_mcproto::error::Error::IoError(e) => ClientError::Io(e),
+            e => ClientError::Codec(e),
+        }
+    }
+}
+
+impl From<auth::Error> for ClientError {
+    fn from(e: auth::Error) -> ClientError {
+        ClientError::Auth(e)
+    }
+}
+
+/// Credentials used to join online mode servers
+pub struct Credentials {
+    pub access_token: String,
+    pub profile: UuidSimple,
+    pub session: Arc<SessionServer<Box<dyn HttpClient + Send + Sync>>>,
+}
+
+impl Credentials {
+    pub fn new<H>(access_token: String, profile: UuidSimple, http: H) -> Credentials
+    where
+        H: HttpClient + Send + Sync + 'static,
+    {
+        Credentials {
+            access_token,
+            profile,
+            session: Arc::new(SessionServer::new(http).boxed()),
+        }
+    }
+}
+
+/// Options of a connection
+pub struct ClientBuilder {
+    username: String,
+    credentials: Option<Credentials>,
+}
+
+impl ClientBuilder {
+    pub fn new<S: Into<String>>(username: S) -> ClientBuilder {
+        ClientBuilder {
+            username: username.into(),
+            credentials: None,
+        }
+    }
+
+    /// Join online mode servers with these credentials
+    pub fn credentials(mut self, credentials: Credentials) -> ClientBuilder {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Resolve `host` and connect
+    pub async fn connect(self, host: &str, port: u16) -> Result<Client<TcpStream>, ClientError> {
+        let stream = TcpStream::connect((host, port)).await?;
+        self.connect_stream(stream, host, port).await
+    }
+
+    /// Log in over an established connection, `host` and `port` are sent in the handshake
+    pub async fn connect_stream<S>(self, stream: S, host: &str, port: u16) -> Result<Client<S>, ClientError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let username = Username::new(self.username.as_str())?;
+        let server_address = BoundedString::new(host)?;
+
+        let mut framed = Framed::new(stream, MCProtoCodec::<Handshake, ()>::new());
+        framed
+            .send(Handshake::Handshake {
+                proto_version: PROTOCOL_VERSION,
+                server_address,
+                server_port: port,
+                next_state: NextState::Login,
+            })
+            .await?;
+
+        let mut framed: ClientLoginFramed<S> = switch_codec(framed);
+        framed
+            .send(LoginToServer::LoginStart { name: username })
+            .await?;
+
+        loop {
+            let packet = match framed.next().await {
+                Some(packet) => packet?,
+                None => return Err(ClientError::Closed),
+            };
+            match packet {
+                LoginToClient::EncryptionRequest {
+                    server_id,
+                    pubkey,
+                    verify_token,
+                } => {
+                    let credentials = self.credentials.as_ref().ok_or(ClientError::AuthRequired)?;
+                    let reply = EncryptionReply::new(&server_id, &pubkey, &verify_token)?;
+                    let session = Arc::clone(&credentials.session);
+                    let (access_token, profile) = (credentials.access_token.clone(), credentials.profile);
+                    let server_hash = reply.server_hash.clone();
+                    blocking::run(move || session.join(&access_token, profile, &server_hash)).await?;
+                    framed
+                        .send(LoginToServer::EncryptionResponse {
+                            shared_secret: ShortPrefix::new(reply.encrypted_shared_secret),
+                            verify_token: ShortPrefix::new(reply.encrypted_verify_token),
+                        })
+                        .await?;
+                    framed.codec_mut().enable_encryption(&reply.shared_secret)?;
+                }
+                LoginToClient::LoginSuccess { uuid, username } => {
+                    return Ok(Client {
+                        framed: switch_codec(framed),
+                        uuid,
+                        username,
+                        keep_alive: None,
+                        disconnected: false,
+                    });
+                }
+                LoginToClient::Disconnect { reason } => return Err(ClientError::Disconnected(reason)),
+            }
+        }
+    }
+}
+
+/// Logged in connection.
+///
+/// As a `Stream` it yields every `PlayToClient` packet but `KeepAlive`, which is answered
+/// automatically. A `Disconnect` is yielded as [`ClientError::Disconnected`] and ends the
+/// stream. As a `Sink` it sends `PlayToServer` packets.
+pub struct Client<S> {
+    framed: ClientPlayFramed<S>,
+    uuid: Uuid,
+    username: String,
+    /// Reply waiting for room in the write buffer
+    keep_alive: Option<PlayToServer>,
+    disconnected: bool,
+}
+
+impl Client<TcpStream> {
+    /// Connect to an offline mode server
+    pub async fn connect(host: &str, port: u16, username: &str) -> Result<Client<TcpStream>, ClientError> {
+        ClientBuilder::new(username).connect(host, port).await
+    }
+
+    pub fn builder<S: Into<String>>(username: S) -> ClientBuilder {
+        ClientBuilder::new(username)
+    }
+}
+
+impl<S> Client<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// UUID the server assigned to us
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn get_ref(&self) -> &ClientPlayFramed<S> {
+        &self.framed
+    }
+
+    pub fn into_inner(self) -> ClientPlayFramed<S> {
+        self.framed
+    }
+
+    fn poll_keep_alive(&mut self, cx: &mut Context) -> Poll<Result<(), ClientError>> {
+        if self.keep_alive.is_some() {
+            ready!(Pin::new(&mut self.framed).poll_ready(cx))?;
+            if let Some(reply) = self.keep_alive.take() {
+                Pin::new(&mut self.framed).start_send(reply)?;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> Stream for Client<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<PlayToClient, ClientError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.disconnected {
+                return Poll::Ready(None);
+            }
+            if let Err(e) = ready!(this.poll_keep_alive(cx)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+            // the reply goes out even if the user never flushes
+            if let Poll::Ready(Err(e)) = Pin::new(&mut this.framed).poll_flush(cx) {
+                return Poll::Ready(Some(Err(e.into())));
+            }
+
+            let packet = match ready!(Pin::new(&mut this.framed).poll_next(cx)) {
+                Some(Ok(packet)) => packet,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => return Poll::Ready(None),
+            };
+            match packet {
+                PlayToClient::KeepAlive { keep_alive_id } => {
+                    this.keep_alive = Some(PlayToServer::KeepAlive { keep_alive_id });
+                }
+                PlayToClient::Disconnect { reason } => {
+                    this.disconnected = true;
+                    return Poll::Ready(Some(Err(ClientError::Disconnected(reason))));
+                }
+                packet => return Poll::Ready(Some(Ok(packet))),
+            }
+        }
+    }
+}
+
+impl<S> Sink<PlayToServer> for Client<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = ClientError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_keep_alive(cx))?;
+        Pin::new(&mut this.framed).poll_ready(cx).map_err(Into::into)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: PlayToServer) -> Result<(), Self::Error> {
+        Pin::new(&mut self.get_mut().framed)
+            .start_send(item)
+            .map_err(Into::into)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_keep_alive(cx))?;
+        Pin::new(&mut this.framed).poll_flush(cx).map_err(Into::into)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().framed)
+            .poll_close(cx)
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use futures::future;
+    use mcproto::auth::{HttpResponse, ServerKey};
+    use tokio::io::{duplex, DuplexStream};
+
+    use crate::login::{LoginError, ServerLogin, ServerLoginFramed};
+    use crate::server::ServerPlayFramed;
+
+    /// Session server remembering the joins, `hasJoined` finds the player once it joined
+    /// with the same server hash
+    #[derive(Clone, Default)]
+    struct MockSession {
+        joins: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl HttpClient for MockSession {
+        fn get(&self, url: &str) -> auth::Result<HttpResponse> {
+            let hash = url.split("serverId=").nth(1).unwrap().split('&').next().unwrap();
+            let server_id = format!(r#""serverId":"{}""#, hash);
+            let joined = self.joins.lock().unwrap().iter().any(|join| join.contains(&server_id));
+            Ok(HttpResponse {
+                status: if joined { 200 } else { 204 },
+                body: if joined {
+                    r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch"}"#.to_owned()
+                } else {
+                    String::new()
+                },
+            })
+        }
+
+        fn post_json(&self, url: &str, body: &str) -> auth::Result<HttpResponse> {
+            assert_eq!(url, "http://session/session/minecraft/join");
+            self.joins.lock().unwrap().push(body.to_owned());
+            Ok(HttpResponse {
+                status: 204,
+                body: String::new(),
+            })
+        }
+    }
+
+    fn online_login(session: &MockSession) -> ServerLogin {
+        let key = ServerKey::generate_with_bits(512).unwrap();
+        ServerLogin::with_session(key, SessionServer::with_base_url(session.clone(), "http://session"))
+    }
+
+    fn credentials(session: &MockSession) -> Credentials {
+        Credentials {
+            access_token: "token".to_owned(),
+            profile: UuidSimple::offline("Notch"),
+            session: Arc::new(SessionServer::with_base_url(session.clone(), "http://session").boxed()),
+        }
+    }
+
+    /// Server side of a connection: check the handshake, then log the player in
+    async fn serve(stream: DuplexStream, login: &ServerLogin) -> Result<ServerPlayFramed<DuplexStream>, LoginError> {
+        let mut framed = Framed::new(stream, MCProtoCodec::<(), Handshake>::new());
+        match framed.next().await.unwrap().unwrap() {
+            Handshake::Handshake {
+                proto_version,
+                server_address,
+                server_port,
+                next_state,
+            } => {
+                assert_eq!(proto_version.0, PROTOCOL_VERSION.0);
+                assert_eq!(&*server_address, "localhost");
+                assert_eq!(server_port, 25565);
+                assert!(matches!(next_state, NextState::Login));
+            }
+        }
+        let mut framed: ServerLoginFramed<DuplexStream> = switch_codec(framed);
+        login.run(&mut framed).await?;
+        Ok(switch_codec(framed))
+    }
+
+    async fn logged_in(login: &ServerLogin) -> (Client<DuplexStream>, ServerPlayFramed<DuplexStream>) {
+        let (client, server) = duplex(64 * 1024);
+        let connect = ClientBuilder::new("Steve").connect_stream(client, "localhost", 25565);
+        let (client, server) = future::join(connect, serve(server, login)).await;
+        (client.unwrap(), server.unwrap())
+    }
+
+    #[tokio::test]
+    async fn offline() {
+        let (client, server) = logged_in(&ServerLogin::offline()).await;
+        assert_eq!(client.username(), "Steve");
+        assert_eq!(*client.uuid(), Uuid::from(UuidSimple::offline("Steve")));
+        assert!(!client.get_ref().codec().is_encrypted());
+        assert!(!server.codec().is_encrypted());
+    }
+
+    #[tokio::test]
+    async fn online() {
+        let session = MockSession::default();
+        let (client, server) = duplex(64 * 1024);
+        let connect = ClientBuilder::new("Notch")
+            .credentials(credentials(&session))
+            .connect_stream(client, "localhost", 25565);
+        let login = online_login(&session);
+        let (client, server) = future::join(connect, serve(server, &login)).await;
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        assert_eq!(client.username(), "Notch");
+        assert!(client.get_ref().codec().is_encrypted());
+
+        let joins = session.joins.lock().unwrap().clone();
+        assert_eq!(joins.len(), 1);
+        assert!(joins[0].starts_with(r#"{"accessToken":"token","selectedProfile":""#));
+
+        // both ends encrypt with the same secret
+        server
+            .send(PlayToClient::TimeUpdate {
+                world_age: 1,
+                time_of_day: 2,
+            })
+            .await
+            .unwrap();
+        match client.next().await.unwrap().unwrap() {
+            PlayToClient::TimeUpdate { world_age, time_of_day } => assert_eq!((world_age, time_of_day), (1, 2)),
+            other => panic!("expected a time update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn online_mode_needs_credentials() {
+        let session = MockSession::default();
+        let (client, server) = duplex(64 * 1024);
+        let connect = ClientBuilder::new("Notch").connect_stream(client, "localhost", 25565);
+        let login = online_login(&session);
+        let (client, server) = future::join(connect, serve(server, &login)).await;
+        match client {
+            Err(ClientError::AuthRequired) => {}
+            Err(e) => panic!("expected credentials to be required, got {}", e),
+            Ok(_) => panic!("expected credentials to be required"),
+        }
+        // the client hung up instead of answering the encryption request
+        assert!(matches!(server, Err(LoginError::Closed)));
+        assert!(session.joins.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keep_alives_are_answered() {
+        let (mut client, mut server) = logged_in(&ServerLogin::offline()).await;
+        server.send(PlayToClient::KeepAlive { keep_alive_id: 42 }).await.unwrap();
+        server
+            .send(PlayToClient::TimeUpdate {
+                world_age: 1,
+                time_of_day: 2,
+            })
+            .await
+            .unwrap();
+        // the keep alive is not yielded, the answer goes out without a flush by the user
+        match client.next().await.unwrap().unwrap() {
+            PlayToClient::TimeUpdate { .. } => {}
+            other => panic!("expected a time update, got {:?}", other),
+        }
+        match server.next().await.unwrap().unwrap() {
+            PlayToServer::KeepAlive { keep_alive_id } => assert_eq!(keep_alive_id, 42),
+            other => panic!("expected a keep alive, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnect_ends_the_stream() {
+        let (mut client, mut server) = logged_in(&ServerLogin::offline()).await;
+        let reason = r#"{"text":"Bye"}"#.to_owned();
+        server.send(PlayToClient::Disconnect { reason: reason.clone() }).await.unwrap();
+        // more packets after it are not read
+        server.send(PlayToClient::KeepAlive { keep_alive_id: 1 }).await.unwrap();
+        match client.next().await {
+            Some(Err(ClientError::Disconnected(sent))) => assert_eq!(sent, reason),
+            other => panic!("expected a disconnect, got {:?}", other),
+        }
+        assert!(client.next().await.is_none());
+    }
+}
@@ -1,21 +1,91 @@
-use bytes::IntoBuf;
-use bytes::{Buf, BufMut, BytesMut};
+//! Framing of packets: length prefixes, encryption and recording
+//!
+//! [`MCProtoCodec`] does not depend on a runtime. It is a tokio-util codec with the
+//! `tokio-util` feature, and [`crate::io::Framed`] drives it on the futures `AsyncRead` and
+//! `AsyncWrite` with `futures-io`.
+
+use aes::Aes128;
+use bytes::{BufMut, BytesMut};
+use cfb8::cipher::generic_array::GenericArray;
+use cfb8::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use mcproto::packet::Direction;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_mcproto::de::MCProtoDeserializer;
 use serde_mcproto::error::Error;
 use serde_mcproto::ser::MCProtoSerializer;
+use serde_mcproto::types::var::MAX_VARINT_LEN;
 use serde_mcproto::write_varint;
+use std::fmt;
+use std::io::{self, Write};
 use std::marker::PhantomData;
-use tokio::codec::{Decoder, Encoder};
+#[cfg(feature = "tokio-util")]
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts};
+
+use crate::capture::Recorder;
+
+/// Longest frame vanilla accepts, its length fits in a three byte VarInt
+pub const MAX_FRAME_LEN: usize = 2_097_151;
+
+/// Part of a frame the decoder is waiting for
+#[derive(Debug, Clone, Copy)]
+enum ReadState {
+    /// Length VarInt, read again from its first byte until it is complete
+    Length,
+    /// Packet of this many bytes, its length is already consumed
+    Packet(usize),
+}
+
+/// AES/CFB8, the shared secret is both key and IV
+struct Encryption {
+    encryptor: cfb8::Encryptor<Aes128>,
+    decryptor: cfb8::Decryptor<Aes128>,
+    /// Bytes at the start of the read buffer which are already decrypted
+    decrypted: usize,
+}
+
+impl Encryption {
+    fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.encryptor
+                .encrypt_block_mut(GenericArray::from_mut_slice(byte));
+        }
+    }
+
+    fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.decryptor
+                .decrypt_block_mut(GenericArray::from_mut_slice(byte));
+        }
+    }
+}
 
-#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct MCProtoCodec<T, R>
 where
     T: Serialize,
     R: DeserializeOwned,
 {
-    _ghost_t: PhantomData<T>,
-    _ghost_r: PhantomData<R>,
+    encryption: Option<Encryption>,
+    /// Capture of the connection and the direction of the packets this codec sends
+    recording: Option<(Recorder, Direction)>,
+    read_state: ReadState,
+    max_frame_len: usize,
+    /// The codec holds no packets, it is `Send` and `Unpin` whatever they are
+    _ghost_t: PhantomData<fn(T)>,
+    _ghost_r: PhantomData<fn() -> R>,
+}
+
+impl<T, R> fmt::Debug for MCProtoCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MCProtoCodec")
+            .field("encrypted", &self.is_encrypted())
+            .field("recording", &self.is_recording())
+            .field("max_frame_len", &self.max_frame_len)
+            .finish()
+    }
 }
 
 impl<T, R> MCProtoCodec<T, R>
@@ -25,87 +95,431 @@ where
 {
     pub fn new() -> Self {
         MCProtoCodec {
+            encryption: None,
+            recording: None,
+            read_state: ReadState::Length,
+            max_frame_len: MAX_FRAME_LEN,
+            _ghost_t: PhantomData,
+            _ghost_r: PhantomData,
+        }
+    }
+
+    /// Encrypt everything written and read from now on
+    pub fn enable_encryption(&mut self, shared_secret: &[u8]) -> Result<(), Error> {
+        let invalid = |_| {
+            Error::Serde(format!(
+                "Shared secret must be 16 bytes, got {}",
+                shared_secret.len()
+            ))
+        };
+        self.encryption = Some(Encryption {
+            encryptor: cfb8::Encryptor::new_from_slices(shared_secret, shared_secret)
+                .map_err(invalid)?,
+            decryptor: cfb8::Decryptor::new_from_slices(shared_secret, shared_secret)
+                .map_err(invalid)?,
+            decrypted: 0,
+        });
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Write every packet to `recorder`, `sends` is the direction of the packets this codec
+    /// encodes: `ToServer` for clients, `ToClient` for servers
+    pub fn enable_recording(&mut self, recorder: Recorder, sends: Direction) {
+        self.recording = Some((recorder, sends));
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Fail to decode frames longer than `max`, [`MAX_FRAME_LEN`] by default
+    pub fn set_max_frame_len(&mut self, max: usize) {
+        self.max_frame_len = max;
+    }
+
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+
+    /// Same connection state with other packet types, used when the protocol state changes
+    pub fn cast<T2, R2>(self) -> MCProtoCodec<T2, R2>
+    where
+        T2: Serialize,
+        R2: DeserializeOwned,
+    {
+        MCProtoCodec {
+            encryption: self.encryption,
+            recording: self.recording,
+            read_state: self.read_state,
+            max_frame_len: self.max_frame_len,
             _ghost_t: PhantomData,
             _ghost_r: PhantomData,
         }
     }
 }
 
-impl<T, R> Decoder for MCProtoCodec<T, R>
+/// Switch the packet types of a connection, keeping buffered bytes and encryption
+#[cfg(feature = "tokio-util")]
+pub fn switch_codec<S, T, R, T2, R2>(
+    framed: Framed<S, MCProtoCodec<T, R>>,
+) -> Framed<S, MCProtoCodec<T2, R2>>
 where
     T: Serialize,
     R: DeserializeOwned,
+    T2: Serialize,
+    R2: DeserializeOwned,
 {
-    type Item = R;
-    type Error = Error;
+    let parts = framed.into_parts();
+    let mut switched = FramedParts::new::<T2>(parts.io, parts.codec.cast());
+    switched.read_buf = parts.read_buf;
+    switched.write_buf = parts.write_buf;
+    Framed::from_parts(switched)
+}
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() > 0 {
-            let (len, consumed_bytes) = read_varint(src).unwrap();
-            if src.len() - consumed_bytes < len as usize {
-                Ok(None)
-            } else {
-                src.advance(consumed_bytes);
-                let result = src.split_to(len as usize);
-                serde_mcproto::de::deserialize(&mut MCProtoDeserializer {
-                    reader: result.into_buf().reader(),
-                })
-                .map_err(Into::into)
-                .map(|e| Some(e))
+impl<T, R> MCProtoCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    /// Take the next packet of `src` without its length, decrypting and recording it.
+    /// `None` until the whole packet is buffered, a length that is read is not read again.
+    pub(crate) fn read_frame(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, Error> {
+        if let Some(encryption) = &mut self.encryption {
+            let decrypted = encryption.decrypted;
+            encryption.decrypt(&mut src[decrypted..]);
+            encryption.decrypted = src.len();
+        }
+        loop {
+            match self.read_state {
+                ReadState::Length => match read_frame_len(src, self.max_frame_len)? {
+                    Some((len, prefix_len)) => {
+                        self.take(src, prefix_len);
+                        self.read_state = ReadState::Packet(len);
+                    }
+                    None => return Ok(None),
+                },
+                ReadState::Packet(len) => {
+                    if src.len() < len {
+                        src.reserve(len - src.len());
+                        return Ok(None);
+                    }
+                    let frame = self.take(src, len);
+                    self.read_state = ReadState::Length;
+                    if let Some((recorder, sends)) = &self.recording {
+                        recorder.record(sends.opposite(), &frame)?;
+                    }
+                    return Ok(Some(frame));
+                }
+            }
+        }
+    }
+
+    /// Split off the first `len` decrypted bytes of `src`
+    fn take(&mut self, src: &mut BytesMut, len: usize) -> BytesMut {
+        if let Some(encryption) = &mut self.encryption {
+            encryption.decrypted -= len;
+        }
+        src.split_to(len)
+    }
+
+    /// Write a packet with its length, recording and encrypting it
+    #[cfg(feature = "tokio-util")]
+    pub(crate) fn write_frame(&mut self, frame: &[u8], dst: &mut BytesMut) -> Result<(), Error> {
+        if let Some((recorder, sends)) = &self.recording {
+            recorder.record(*sends, frame)?;
+        }
+        let (prefix, prefix_len) = varint_bytes(frame.len());
+        dst.reserve(prefix_len + frame.len());
+        let start = dst.len();
+        dst.extend_from_slice(&prefix[..prefix_len]);
+        dst.extend_from_slice(frame);
+        if let Some(encryption) = &mut self.encryption {
+            encryption.encrypt(&mut dst[start..]);
+        }
+        Ok(())
+    }
+
+    /// Serialize a packet straight into `dst` after room for the longest length, then write
+    /// the length in front of it and move the packet back against it
+    pub(crate) fn write_packet<P: Serialize>(&mut self, packet: &P, dst: &mut BytesMut) -> Result<(), Error> {
+        let start = dst.len();
+        dst.put_bytes(0, MAX_VARINT_LEN);
+        let serialized = serde_mcproto::ser::serialize(
+            packet,
+            &mut MCProtoSerializer {
+                writer: Append(&mut *dst),
+            },
+        );
+        if let Err(e) = serialized {
+            dst.truncate(start);
+            return Err(e);
+        }
+
+        let len = dst.len() - start - MAX_VARINT_LEN;
+        let (prefix, prefix_len) = varint_bytes(len);
+        dst.copy_within(start + MAX_VARINT_LEN.., start + prefix_len);
+        dst.truncate(start + prefix_len + len);
+        dst[start..start + prefix_len].copy_from_slice(&prefix[..prefix_len]);
+        if let Some((recorder, sends)) = &self.recording {
+            if let Err(e) = recorder.record(*sends, &dst[start + prefix_len..]) {
+                dst.truncate(start);
+                return Err(e.into());
             }
-        } else {
-            Ok(None)
         }
+        if let Some(encryption) = &mut self.encryption {
+            encryption.encrypt(&mut dst[start..]);
+        }
+        Ok(())
     }
 }
 
-impl<T, R> Encoder for MCProtoCodec<T, R>
+/// Writer appending to a `BytesMut`. Serializers write a few bytes at a time, which the
+/// `Writer` of `BufMut::writer` makes about five times slower.
+struct Append<'a>(&'a mut BytesMut);
+
+impl Write for Append<'_> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// VarInt of a frame length, and how many of its bytes are used
+fn varint_bytes(len: usize) -> ([u8; MAX_VARINT_LEN], usize) {
+    let mut bytes = [0; MAX_VARINT_LEN];
+    let mut writer = &mut bytes[..];
+    write_varint(&(len as i32), &mut writer).expect("a varint fits in five bytes");
+    let used = MAX_VARINT_LEN - writer.len();
+    (bytes, used)
+}
+
+impl<T, R> MCProtoCodec<T, R>
 where
     T: Serialize,
     R: DeserializeOwned,
 {
-    type Item = T;
+    /// Take and deserialize the next packet of `src`
+    pub(crate) fn read_packet(&mut self, src: &mut BytesMut) -> Result<Option<R>, Error> {
+        match self.read_frame(src)? {
+            Some(frame) => serde_mcproto::de::deserialize(&mut MCProtoDeserializer {
+                reader: &frame[..],
+            })
+            .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl<T, R> Decoder for MCProtoCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    type Item = R;
     type Error = Error;
 
-    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let mut buffer = Vec::new();
-        serde_mcproto::ser::serialize(
-            &item,
-            &mut MCProtoSerializer {
-                writer: &mut buffer,
-            },
-        )?;
-        let len = buffer.len() as i32;
-        let mut varint_length = Vec::new();
-        write_varint(&len, &mut varint_length).unwrap();
-        dst.reserve(varint_length.len() + buffer.len());
-        dst.put(varint_length);
-        dst.put(buffer);
-        Ok(())
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.read_packet(src)
     }
 }
 
-pub fn read_varint(bytes: &mut BytesMut) -> Result<(i32, usize), Error> {
-    let mut result = 0;
-    let msb: u8 = 0b10000000;
-    let mask: u8 = !msb;
+#[cfg(feature = "tokio-util")]
+impl<T, R> Encoder<T> for MCProtoCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    type Error = Error;
 
-    for i in 0..5 {
-        let read: &u8 = bytes.get(i).unwrap();
-        result |= ((read & mask) as i32) << (7 * i as i32);
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.write_packet(&item, dst)
+    }
+}
 
-        /* The last (5th) byte is only allowed to have the 4 LSB set */
-        if i == 4 && (read & 0xf0 != 0) {
+/// Length of the frame at the start of `src` and the length of its VarInt, `None` until the
+/// VarInt is complete. Fails as soon as the length is known to be over `max`.
+fn read_frame_len(src: &[u8], max: usize) -> Result<Option<(usize, usize)>, Error> {
+    let mut len = 0u64;
+    for (i, byte) in src.iter().take(MAX_VARINT_LEN).enumerate() {
+        len |= u64::from(byte & 0x7f) << (7 * i);
+        if len > i32::MAX as u64 {
+            return Err(Error::Serde(format!("Frame length {} does not fit in a VarInt", len)));
+        }
+        if len > max as u64 {
             return Err(Error::Serde(format!(
-                "VarInt is too long, last byte: {}",
-                read
+                "Frame is longer than the limit of {} bytes",
+                max
             )));
         }
+        if byte & 0x80 == 0 {
+            if len == 0 {
+                return Err(Error::Serde("Frame is empty, without a packet id".to_owned()));
+            }
+            return Ok(Some((len as usize, i + 1)));
+        }
+    }
+    if src.len() >= MAX_VARINT_LEN {
+        return Err(Error::Serde(format!(
+            "Frame length is not a VarInt, it goes on after {} bytes",
+            MAX_VARINT_LEN
+        )));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_round_trip() {
+        let secret = [7u8; 16];
+        let mut writer = MCProtoCodec::<String, String>::new();
+        let mut reader = MCProtoCodec::<String, String>::new();
 
-        if (read & msb) == 0 {
-            return Ok((result, i + 1));
+        let mut wire = BytesMut::new();
+        writer.write_packet(&"before", &mut wire).unwrap();
+        writer.enable_encryption(&secret).unwrap();
+        writer.write_packet(&"first", &mut wire).unwrap();
+        writer.write_packet(&"second", &mut wire).unwrap();
+        assert!(!wire[8..].windows(5).any(|w| w == b"first"));
+
+        // the plain packet arrives together with encrypted bytes
+        let mut src = wire.split_to(10);
+        assert_eq!(reader.read_packet(&mut src).unwrap(), Some("before".to_owned()));
+        reader.enable_encryption(&secret).unwrap();
+        assert_eq!(reader.read_packet(&mut src).unwrap(), None);
+
+        let mut decoded = Vec::new();
+        while !wire.is_empty() {
+            src.extend_from_slice(&wire.split_to(1));
+            if let Some(packet) = reader.read_packet(&mut src).unwrap() {
+                decoded.push(packet);
+            }
         }
+        assert_eq!(decoded, vec!["first".to_owned(), "second".to_owned()]);
     }
 
-    panic!("read_varint reached end of loop, which should not be possible");
+    #[test]
+    fn lengths_around_varint_boundaries() {
+        let mut codec = MCProtoCodec::<String, String>::new();
+        let mut wire = BytesMut::new();
+        let mut expected = Vec::new();
+        // packets whose length takes one, two and three bytes, around each boundary
+        for &len in [0, 126, 127, 128, 16381, 16382, 16383, 32767].iter() {
+            let packet = "x".repeat(len);
+            codec.write_packet(&packet, &mut wire).unwrap();
+
+            let mut body = Vec::new();
+            serde_mcproto::ser::serialize(&packet, &mut MCProtoSerializer { writer: &mut body }).unwrap();
+            write_varint(&(body.len() as i32), &mut expected).unwrap();
+            expected.extend_from_slice(&body);
+        }
+        assert_eq!(&wire[..], &expected[..]);
+
+        let mut decoded = Vec::new();
+        while let Some(packet) = codec.read_packet(&mut wire).unwrap() {
+            decoded.push(packet.len());
+        }
+        assert_eq!(decoded, vec![0, 126, 127, 128, 16381, 16382, 16383, 32767]);
+    }
+
+    /// Packets as sent by a fresh codec
+    fn stream(packets: Vec<String>, secret: Option<&[u8]>) -> (BytesMut, Vec<String>) {
+        let mut writer = MCProtoCodec::<String, String>::new();
+        if let Some(secret) = secret {
+            writer.enable_encryption(secret).unwrap();
+        }
+        let mut wire = BytesMut::new();
+        for packet in &packets {
+            writer.write_packet(&packet, &mut wire).unwrap();
+        }
+        (wire, packets)
+    }
+
+    #[test]
+    fn split_at_every_byte() {
+        let secret = [3u8; 16];
+        for &secret in [None, Some(&secret[..])].iter() {
+            // lengths of one and two bytes
+            let (wire, packets) = stream(vec!["a".repeat(3), "b".repeat(200), String::new()], secret);
+            for split in 0..=wire.len() {
+                let mut reader = MCProtoCodec::<String, String>::new();
+                if let Some(secret) = secret {
+                    reader.enable_encryption(secret).unwrap();
+                }
+                let mut src = BytesMut::from(&wire[..split]);
+                let mut decoded = Vec::new();
+                while let Some(packet) = reader.read_packet(&mut src).unwrap() {
+                    decoded.push(packet);
+                }
+                src.extend_from_slice(&wire[split..]);
+                while let Some(packet) = reader.read_packet(&mut src).unwrap() {
+                    decoded.push(packet);
+                }
+                assert_eq!(decoded, packets, "split at {}", split);
+                assert!(src.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let secret = [5u8; 16];
+        // the second length takes three bytes
+        let (wire, packets) = stream(vec!["a".repeat(3), "c".repeat(20000)], Some(&secret));
+        let mut reader = MCProtoCodec::<String, String>::new();
+        reader.enable_encryption(&secret).unwrap();
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for &byte in wire.iter() {
+            src.extend_from_slice(&[byte]);
+            if let Some(packet) = reader.read_packet(&mut src).unwrap() {
+                decoded.push(packet);
+            }
+        }
+        assert_eq!(decoded, packets);
+    }
+
+    #[test]
+    fn malformed_lengths() {
+        let decode = |bytes: &[u8], max: usize| {
+            let mut reader = MCProtoCodec::<(), String>::new();
+            reader.set_max_frame_len(max);
+            reader.read_packet(&mut BytesMut::from(bytes)).map(|packet| packet.is_some())
+        };
+        // incomplete, not an error yet
+        assert!(!decode(&[0x80], MAX_FRAME_LEN).unwrap());
+        assert!(!decode(&[0x80, 0x80, 0x80, 0x80], 1 << 30).unwrap());
+        // a VarInt that never ends
+        assert!(decode(&[0x80; 5], 1 << 30).is_err());
+        assert!(decode(&[0xff, 0xff, 0xff, 0xff, 0x7f], usize::MAX).is_err());
+        // over the limit, known before the VarInt or the frame is complete
+        assert!(decode(&[0x80, 0x80, 0x80, 0x01], MAX_FRAME_LEN).is_err());
+        assert!(decode(&[0x80, 0x80, 0x80, 0x80], MAX_FRAME_LEN).is_ok());
+        assert!(decode(&[0x80, 0x80, 0x81], MAX_FRAME_LEN).is_ok());
+        assert!(decode(&[11, 0], 10).is_err());
+        assert!(!decode(&[10, 0], 10).unwrap());
+        // no packet id
+        assert!(decode(&[0], MAX_FRAME_LEN).is_err());
+    }
+
+    #[test]
+    fn failed_encode_writes_nothing() {
+        let mut codec = MCProtoCodec::<String, String>::new();
+        let mut wire = BytesMut::new();
+        codec.write_packet(&"kept", &mut wire).unwrap();
+        assert!(codec.write_packet(&"x".repeat(40000), &mut wire).is_err());
+        assert_eq!(&wire[..], b"\x05\x04kept");
+    }
 }
@@ -0,0 +1,305 @@
+//! Packets read without decoding their fields
+//!
+//! [`FrameCodec`] reads the length and packet id of every packet and keeps the rest as bytes.
+//! A [`Frame`] is decoded into its packet enum when asked to, and is written back as the bytes
+//! it was read from unless its packet was changed, so relaying packets costs no decoding.
+
+use std::fmt;
+
+use bytes::Bytes;
+#[cfg(feature = "tokio-util")]
+use bytes::BytesMut;
+use mcproto::packet::{Direction, Packet, State};
+use serde::de::DeserializeOwned;
+#[cfg(feature = "tokio-util")]
+use serde::Serialize;
+use serde_mcproto::de::MCProtoDeserializer;
+use serde_mcproto::error::Error;
+use serde_mcproto::ser::MCProtoSerializer;
+#[cfg(feature = "tokio-util")]
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts};
+
+#[cfg(feature = "tokio-util")]
+use crate::codec::MCProtoCodec;
+
+/// Packet id followed by the undecoded fields, decoded on demand
+pub struct Frame<P> {
+    /// Id in `bytes`
+    id: i32,
+    /// Packet id and fields as read, empty for a frame made from a packet
+    bytes: Bytes,
+    packet: Option<P>,
+    /// Whether `packet` may differ from `bytes`, so it has to be encoded again
+    modified: bool,
+}
+
+impl<P> Frame<P> {
+    /// Frame of the packet id and fields of a packet, without its length
+    pub fn from_bytes(bytes: Bytes) -> Result<Self, Error> {
+        let id = serde_mcproto::read_varint(&mut bytes.as_ref())?;
+        Ok(Frame {
+            id,
+            bytes,
+            packet: None,
+            modified: false,
+        })
+    }
+
+    /// Bytes the frame was read from, `None` if its packet was changed or made by the caller
+    pub fn bytes(&self) -> Option<&[u8]> {
+        if self.modified {
+            None
+        } else {
+            Some(&self.bytes)
+        }
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        self.packet.is_some()
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+}
+
+impl<P: Packet> Frame<P> {
+    /// Id of the packet, read from the bytes unless the packet was changed
+    pub fn id(&self) -> i32 {
+        match &self.packet {
+            Some(packet) if self.modified => packet.id(),
+            _ => self.id,
+        }
+    }
+
+    /// Name of the packet variant, `None` for an unknown id
+    pub fn name(&self) -> Option<&'static str> {
+        P::name_of(self.id())
+    }
+
+    pub fn state(&self) -> State {
+        P::STATE
+    }
+
+    pub fn direction(&self) -> Direction {
+        P::DIRECTION
+    }
+
+    /// Decode the packet the first time, later calls return the same packet
+    pub fn packet(&mut self) -> Result<&P, Error> {
+        if self.packet.is_none() {
+            self.packet = Some(decode(&self.bytes)?);
+        }
+        Ok(self.packet.as_ref().unwrap())
+    }
+
+    /// Decode the packet to change it, the frame is then written from the packet
+    pub fn packet_mut(&mut self) -> Result<&mut P, Error> {
+        if self.packet.is_none() {
+            self.packet = Some(decode(&self.bytes)?);
+        }
+        self.modified = true;
+        Ok(self.packet.as_mut().unwrap())
+    }
+
+    pub fn into_packet(self) -> Result<P, Error> {
+        match self.packet {
+            Some(packet) => Ok(packet),
+            None => decode(&self.bytes),
+        }
+    }
+
+    /// Bytes to send: the ones read, or the packet encoded again if it was changed
+    pub fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        match &self.packet {
+            Some(packet) if self.modified => {
+                serde_mcproto::ser::serialize(packet, &mut MCProtoSerializer { writer: buffer })
+            }
+            _ => {
+                buffer.extend_from_slice(&self.bytes);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<P: Packet> From<P> for Frame<P> {
+    fn from(packet: P) -> Self {
+        Frame {
+            id: packet.id(),
+            bytes: Bytes::new(),
+            packet: Some(packet),
+            modified: true,
+        }
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for Frame<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("id", &self.id)
+            .field("len", &self.bytes.len())
+            .field("packet", &self.packet)
+            .field("modified", &self.modified)
+            .finish()
+    }
+}
+
+fn decode<P: DeserializeOwned>(bytes: &[u8]) -> Result<P, Error> {
+    serde_mcproto::de::deserialize(&mut MCProtoDeserializer { reader: bytes })
+}
+
+#[cfg(feature = "tokio-util")]
+/// Codec of frames, it shares encryption and recording with [`MCProtoCodec`]
+#[derive(Debug)]
+pub struct FrameCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    codec: MCProtoCodec<T, R>,
+}
+
+#[cfg(feature = "tokio-util")]
+impl<T, R> FrameCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    pub fn new() -> Self {
+        FrameCodec {
+            codec: MCProtoCodec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &MCProtoCodec<T, R> {
+        &self.codec
+    }
+
+    pub fn get_mut(&mut self) -> &mut MCProtoCodec<T, R> {
+        &mut self.codec
+    }
+
+    pub fn into_inner(self) -> MCProtoCodec<T, R> {
+        self.codec
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl<T, R> Default for FrameCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    fn default() -> Self {
+        FrameCodec::new()
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl<T, R> From<MCProtoCodec<T, R>> for FrameCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    fn from(codec: MCProtoCodec<T, R>) -> Self {
+        FrameCodec { codec }
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+/// Read and write frames on a connection, keeping buffered bytes and encryption
+pub fn into_frames<S, T, R>(framed: Framed<S, MCProtoCodec<T, R>>) -> Framed<S, FrameCodec<T, R>>
+where
+    T: Packet,
+    R: DeserializeOwned,
+{
+    let parts = framed.into_parts();
+    let mut switched = FramedParts::new::<Frame<T>>(parts.io, FrameCodec::from(parts.codec));
+    switched.read_buf = parts.read_buf;
+    switched.write_buf = parts.write_buf;
+    Framed::from_parts(switched)
+}
+
+#[cfg(feature = "tokio-util")]
+impl<T, R> Decoder for FrameCodec<T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    type Item = Frame<R>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.codec.read_frame(src)? {
+            Some(frame) => Frame::from_bytes(frame.freeze()).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl<T, R> Encoder<Frame<T>> for FrameCodec<T, R>
+where
+    T: Packet,
+    R: DeserializeOwned,
+{
+    type Error = Error;
+
+    fn encode(&mut self, item: Frame<T>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match &item.packet {
+            Some(packet) if item.modified => self.codec.write_packet(packet, dst),
+            _ => self.codec.write_frame(&item.bytes, dst),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tokio-util"))]
+mod tests {
+    use super::*;
+    use mcproto::v1_7_10::PlayToClient;
+
+    #[test]
+    fn unmodified_frames_keep_their_bytes() {
+        let mut writer = MCProtoCodec::<PlayToClient, ()>::new();
+        let mut wire = BytesMut::new();
+        writer
+            .encode(PlayToClient::KeepAlive { keep_alive_id: 7 }, &mut wire)
+            .unwrap();
+        writer
+            .encode(PlayToClient::TimeUpdate { world_age: 1, time_of_day: 2 }, &mut wire)
+            .unwrap();
+        // not a valid keep alive, relayed as it is as long as nobody decodes it
+        wire.extend_from_slice(&[2, 0x00, 0xff]);
+        let sent = wire.clone();
+
+        let mut reader = FrameCodec::<PlayToClient, PlayToClient>::new();
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.decode(&mut wire).unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(frames.iter().map(Frame::id).collect::<Vec<_>>(), vec![0x00, 0x03, 0x00]);
+        assert_eq!(frames[1].name(), Some("TimeUpdate"));
+        assert!(!frames[0].is_decoded());
+        assert!(frames[2].packet().is_err());
+
+        match frames[0].packet_mut().unwrap() {
+            PlayToClient::KeepAlive { keep_alive_id } => *keep_alive_id = 8,
+            other => panic!("expected a keep alive, got {:?}", other),
+        }
+        frames[1].packet().unwrap();
+
+        let mut relayed = BytesMut::new();
+        for frame in frames {
+            reader.encode(frame, &mut relayed).unwrap();
+        }
+        assert_eq!(&relayed[..6], &[5, 0x00, 0, 0, 0, 8]);
+        assert_eq!(&relayed[6..], &sent[6..]);
+
+        let mut injected = BytesMut::new();
+        reader
+            .encode(Frame::from(PlayToClient::KeepAlive { keep_alive_id: 8 }), &mut injected)
+            .unwrap();
+        assert_eq!(injected, relayed[..6]);
+    }
+}
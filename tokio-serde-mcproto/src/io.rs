@@ -0,0 +1,243 @@
+//! Packets on any `futures::io` connection, for async-std, smol or any other executor
+//!
+//! [`Framed`] reads and writes packets with an [`MCProtoCodec`], so encryption, recording
+//! and frame limits work as with tokio.
+//!
+//! ```ignore
+//! let stream = async_std::net::TcpStream::connect("localhost:25565").await?;
+//! let mut framed = Framed::new(stream, MCProtoCodec::<Handshake, ()>::new());
+//! framed.send(handshake).await?;
+//! let mut framed = framed.switch::<LoginToServer, LoginToClient>();
+//! ```
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BytesMut};
+use futures_core::{ready, Stream};
+use futures_io::{AsyncRead, AsyncWrite};
+use futures_sink::Sink;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_mcproto::error::Error;
+
+use crate::codec::MCProtoCodec;
+
+/// Bytes read from the connection at once
+const READ_CHUNK: usize = 8 * 1024;
+/// Buffered bytes above which sending waits for them to be written
+const BACKPRESSURE: usize = 64 * 1024;
+
+/// Stream of the packets read from `io` and sink of the packets written to it
+pub struct Framed<S, T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    io: S,
+    codec: MCProtoCodec<T, R>,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    eof: bool,
+}
+
+impl<S, T, R> Framed<S, T, R>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    pub fn new(io: S, codec: MCProtoCodec<T, R>) -> Self {
+        Framed {
+            io,
+            codec,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.io
+    }
+
+    pub fn codec(&self) -> &MCProtoCodec<T, R> {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut MCProtoCodec<T, R> {
+        &mut self.codec
+    }
+
+    /// The connection, bytes read but not decoded and written but not flushed are lost
+    pub fn into_inner(self) -> S {
+        self.io
+    }
+
+    /// Switch the packet types when the protocol state changes, keeping buffered bytes
+    /// and encryption
+    pub fn switch<T2, R2>(self) -> Framed<S, T2, R2>
+    where
+        T2: Serialize,
+        R2: DeserializeOwned,
+    {
+        Framed {
+            io: self.io,
+            codec: self.codec.cast(),
+            read_buf: self.read_buf,
+            write_buf: self.write_buf,
+            eof: self.eof,
+        }
+    }
+}
+
+impl<S, T, R> Stream for Framed<S, T, R>
+where
+    S: AsyncRead + Unpin,
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    type Item = Result<R, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut chunk = [0; READ_CHUNK];
+        loop {
+            match this.codec.read_packet(&mut this.read_buf) {
+                Ok(Some(packet)) => return Poll::Ready(Some(Ok(packet))),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+            if this.eof {
+                if this.read_buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                let len = this.read_buf.len();
+                this.read_buf.clear();
+                return Poll::Ready(Some(Err(Error::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Connection closed inside a frame, {} bytes left", len),
+                )))));
+            }
+            match ready!(Pin::new(&mut this.io).poll_read(cx, &mut chunk)) {
+                Ok(0) => this.eof = true,
+                Ok(n) => this.read_buf.extend_from_slice(&chunk[..n]),
+                Err(e) => return Poll::Ready(Some(Err(e.into()))),
+            }
+        }
+    }
+}
+
+impl<S, T, R> Framed<S, T, R>
+where
+    S: AsyncWrite + Unpin,
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    fn poll_write_buf(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "Connection closed while writing a frame",
+                )
+                .into()));
+            }
+            self.write_buf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S, T, R> Sink<T> for Framed<S, T, R>
+where
+    S: AsyncWrite + Unpin,
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.write_buf.len() >= BACKPRESSURE {
+            this.poll_write_buf(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.codec.write_packet(&item, &mut this.write_buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Poll::Ready(ready!(Pin::new(&mut this.io).poll_flush(cx)).map_err(Error::from))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Poll::Ready(ready!(Pin::new(&mut this.io).poll_close(cx)).map_err(Error::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use futures::{SinkExt, StreamExt};
+
+    /// Reads at most one byte at a time, as a slow connection
+    struct Trickle<R>(R);
+
+    impl<R: AsyncRead + Unpin> AsyncRead for Trickle<R> {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            let len = buf.len().min(1);
+            Pin::new(&mut self.0).poll_read(cx, &mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let secret = [9u8; 16];
+        let packets = vec!["a".to_owned(), "b".repeat(300), "c".repeat(20000)];
+        block_on(async {
+            let mut writer = Framed::new(Cursor::new(Vec::new()), MCProtoCodec::<String, ()>::new());
+            writer.send("plain".to_owned()).await.unwrap();
+            writer.codec_mut().enable_encryption(&secret).unwrap();
+            for packet in &packets {
+                writer.feed(packet.clone()).await.unwrap();
+            }
+            writer.close().await.unwrap();
+            let wire = writer.into_inner().into_inner();
+
+            let mut reader = Framed::new(Trickle(Cursor::new(wire)), MCProtoCodec::<(), String>::new());
+            assert_eq!(reader.next().await.unwrap().unwrap(), "plain");
+            reader.codec_mut().enable_encryption(&secret).unwrap();
+            let mut reader = reader.switch::<(), String>();
+            let mut decoded = Vec::new();
+            while let Some(packet) = reader.next().await {
+                decoded.push(packet.unwrap());
+            }
+            assert_eq!(decoded, packets);
+        });
+    }
+
+    #[test]
+    fn closed_inside_a_frame() {
+        block_on(async {
+            let mut reader = Framed::new(Cursor::new(vec![5, 4, b'a']), MCProtoCodec::<(), String>::new());
+            assert!(reader.next().await.unwrap().is_err());
+            assert!(reader.next().await.is_none());
+        });
+    }
+}
@@ -1,7 +1,20 @@
 extern crate bytes;
+#[cfg(feature = "tokio")]
 extern crate futures;
 //TODO remove
 pub extern crate serde;
 extern crate serde_mcproto;
 
-pub mod codec;
\ No newline at end of file
+#[cfg(feature = "tokio")]
+pub mod blocking;
+pub mod capture;
+#[cfg(feature = "tokio")]
+pub mod client;
+pub mod codec;
+pub mod frame;
+#[cfg(feature = "futures-io")]
+pub mod io;
+#[cfg(feature = "tokio")]
+pub mod login;
+#[cfg(feature = "tokio")]
+pub mod server;
@@ -0,0 +1,359 @@
+//! Server side of the login sequence
+//!
+//! Offline mode answers `LoginStart` with `LoginSuccess` right away. Online mode sends an
+//! `EncryptionRequest`, checks the verify token of the `EncryptionResponse`, enables
+//! encryption and asks the session server whether the player has joined.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use mcproto::auth::{
+    self, generate_verify_token, server_hash, GameProfile, HttpClient, ServerKey, SessionServer,
+};
+use mcproto::types::array::ShortPrefix;
+use mcproto::types::uuid::{Uuid, UuidSimple};
+use mcproto::v1_7_10::{LoginToClient, LoginToServer};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+use crate::blocking;
+use crate::codec::MCProtoCodec;
+
+/// Connection in the login state, as seen by a server
+pub type ServerLoginFramed<S> = Framed<S, MCProtoCodec<LoginToClient, LoginToServer>>;
+
+#[derive(Debug)]
+pub enum LoginError {
+    Codec(serde_mcproto::error::Error),
+    Auth(auth::Error),
+    /// Connection closed before the login finished
+    Closed,
+    UnexpectedPacket(LoginToServer),
+    VerifyTokenMismatch,
+    /// Session server does not know about the player joining
+    NotJoined(String),
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::Codec(e) => e.fmt(f),
+            LoginError::Auth(e) => e.fmt(f),
+            LoginError::Closed => write!(f, "Connection closed during login"),
+            LoginError::UnexpectedPacket(packet) => write!(f, "Unexpected packet {:?}", packet),
+            LoginError::VerifyTokenMismatch => write!(f, "Verify token does not match"),
+            LoginError::NotJoined(username) => write!(f, "{} did not join through the session server", username),
+        }
+    }
+}
+
+impl StdError for LoginError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            LoginError::Codec(e) => Some(e),
+            LoginError::Auth(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_mcproto::error::Error> for LoginError {
+    fn from(e: serde_mcproto::error::Error) -> LoginError {
+        LoginError::Codec(e)
+    }
+}
+
+impl From<auth::Error> for LoginError {
+    fn from(e: auth::Error) -> LoginError {
+        LoginError::Auth(e)
+    }
+}
+
+struct OnlineMode {
+    key: ServerKey,
+    session: Arc<SessionServer<Box<dyn HttpClient + Send + Sync>>>,
+}
+
+/// Login driver shared by every connection of a server
+pub struct ServerLogin {
+    online: Option<OnlineMode>,
+}
+
+impl ServerLogin {
+    /// Trust the name of `LoginStart`, players get their offline UUID
+    pub fn offline() -> ServerLogin {
+        ServerLogin { online: None }
+    }
+
+    /// Authenticate against the Mojang session server with a fresh 1024 bit key
+    pub fn online<H>(http: H) -> auth::Result<ServerLogin>
+    where
+        H: HttpClient + Send + Sync + 'static,
+    {
+        Ok(ServerLogin::with_session(ServerKey::generate()?, SessionServer::new(http)))
+    }
+
+    pub fn with_session<H>(key: ServerKey, session: SessionServer<H>) -> ServerLogin
+    where
+        H: HttpClient + Send + Sync + 'static,
+    {
+        ServerLogin {
+            online: Some(OnlineMode {
+                key,
+                session: Arc::new(session.boxed()),
+            }),
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.is_some()
+    }
+
+    /// Drive the login of a connection which sent a handshake with the login state.
+    ///
+    /// In online mode the connection is encrypted afterwards. The session server is
+    /// queried on the blocking pool.
+    pub async fn run<S>(&self, framed: &mut ServerLoginFramed<S>) -> Result<GameProfile, LoginError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let username = match receive(framed).await? {
+            LoginToServer::LoginStart { name } => name.into_inner(),
+            other => return Err(LoginError::UnexpectedPacket(other)),
+        };
+
+        let profile = match &self.online {
+            None => GameProfile {
+                id: UuidSimple::offline(&username),
+                name: username,
+                properties: Vec::new(),
+            },
+            Some(online) => match online.authenticate(framed, &username).await {
+                Ok(profile) => profile,
+                Err(e) => {
+                    let reason = format!(r#"{{"text":"{}"}}"#, disconnect_message(&e));
+                    // the player is gone anyway if this fails
+                    let _ = framed.send(LoginToClient::Disconnect { reason }).await;
+                    return Err(e);
+                }
+            },
+        };
+
+        framed
+            .send(LoginToClient::LoginSuccess {
+                uuid: Uuid::from(profile.id),
+                username: profile.name.clone(),
+            })
+            .await?;
+        Ok(profile)
+    }
+}
+
+impl OnlineMode {
+    async fn authenticate<S>(
+        &self,
+        framed: &mut ServerLoginFramed<S>,
+        username: &str,
+    ) -> Result<GameProfile, LoginError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let verify_token = generate_verify_token();
+        framed
+            .send(LoginToClient::EncryptionRequest {
+                // empty since 1.7
+                server_id: String::new(),
+                pubkey: ShortPrefix::new(self.key.public_key_der().to_vec()),
+                verify_token: ShortPrefix::new(verify_token.to_vec()),
+            })
+            .await?;
+
+        let (shared_secret, token) = match receive(framed).await? {
+            LoginToServer::EncryptionResponse {
+                shared_secret,
+                verify_token,
+            } => (
+                self.key.decrypt(&shared_secret)?,
+                self.key.decrypt(&verify_token)?,
+            ),
+            other => return Err(LoginError::UnexpectedPacket(other)),
+        };
+        if token != verify_token {
+            return Err(LoginError::VerifyTokenMismatch);
+        }
+        framed.codec_mut().enable_encryption(&shared_secret)?;
+
+        let hash = server_hash("", &shared_secret, self.key.public_key_der());
+        let session = Arc::clone(&self.session);
+        let name = username.to_owned();
+        match blocking::run(move || session.has_joined(&name, &hash, None)).await? {
+            Some(profile) => Ok(profile),
+            None => Err(LoginError::NotJoined(username.to_owned())),
+        }
+    }
+}
+
+async fn receive<S>(framed: &mut ServerLoginFramed<S>) -> Result<LoginToServer, LoginError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match framed.next().await {
+        Some(packet) => Ok(packet?),
+        None => Err(LoginError::Closed),
+    }
+}
+
+/// Disconnect reason shown to the player, as worded by vanilla
+fn disconnect_message(e: &LoginError) -> &'static str {
+    match e {
+        LoginError::NotJoined(_) => "Failed to verify username!",
+        LoginError::Auth(auth::Error::Http(_)) | LoginError::Auth(auth::Error::Status { .. }) => {
+            "Authentication servers are down. Please try again later, sorry!"
+        }
+        _ => "Internal server error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use futures::future;
+    use mcproto::auth::{EncryptionReply, HttpResponse};
+    use mcproto::types::string::Username;
+
+    use tokio::io::{duplex, DuplexStream};
+
+    type ClientLoginFramed = Framed<DuplexStream, MCProtoCodec<LoginToServer, LoginToClient>>;
+
+    /// Answers `hasJoined` with `status` and records the requested URLs
+    #[derive(Clone)]
+    struct MockHttp {
+        status: u16,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl HttpClient for MockHttp {
+        fn get(&self, url: &str) -> auth::Result<HttpResponse> {
+            self.requests.lock().unwrap().push(url.to_owned());
+            let body = r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch"}"#;
+            Ok(HttpResponse {
+                status: self.status,
+                body: if self.status == 200 { body.to_owned() } else { String::new() },
+            })
+        }
+
+        fn post_json(&self, _url: &str, _body: &str) -> auth::Result<HttpResponse> {
+            unreachable!("the server never joins")
+        }
+    }
+
+    fn connection() -> (ClientLoginFramed, ServerLoginFramed<DuplexStream>) {
+        let (client, server) = duplex(64 * 1024);
+        (
+            Framed::new(client, MCProtoCodec::new()),
+            Framed::new(server, MCProtoCodec::new()),
+        )
+    }
+
+    async fn login_start(client: &mut ClientLoginFramed, name: &str) {
+        let name = Username::new(name).unwrap();
+        client.send(LoginToServer::LoginStart { name }).await.unwrap();
+    }
+
+    /// Client side of online mode, returns what the server answered after encryption
+    async fn online_client(mut client: ClientLoginFramed) -> (EncryptionReply, LoginToClient) {
+        login_start(&mut client, "Notch").await;
+        let reply = match client.next().await.unwrap().unwrap() {
+            LoginToClient::EncryptionRequest {
+                server_id,
+                pubkey,
+                verify_token,
+            } => EncryptionReply::new(&server_id, &pubkey, &verify_token).unwrap(),
+            other => panic!("expected an encryption request, got {:?}", other),
+        };
+        client
+            .send(LoginToServer::EncryptionResponse {
+                shared_secret: ShortPrefix::new(reply.encrypted_shared_secret.clone()),
+                verify_token: ShortPrefix::new(reply.encrypted_verify_token.clone()),
+            })
+            .await
+            .unwrap();
+        client.codec_mut().enable_encryption(&reply.shared_secret).unwrap();
+        let answer = client.next().await.unwrap().unwrap();
+        (reply, answer)
+    }
+
+    fn online(status: u16) -> (ServerLogin, Arc<Mutex<Vec<String>>>) {
+        let http = MockHttp {
+            status,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        };
+        let requests = http.requests.clone();
+        let key = ServerKey::generate_with_bits(512).unwrap();
+        let login = ServerLogin::with_session(key, SessionServer::with_base_url(http, "http://session"));
+        (login, requests)
+    }
+
+    #[tokio::test]
+    async fn offline() {
+        let (mut client, mut server) = connection();
+        let login = ServerLogin::offline();
+        let (profile, ()) = future::join(login.run(&mut server), login_start(&mut client, "Steve")).await;
+        let profile = profile.unwrap();
+        assert_eq!(profile.name, "Steve");
+        assert_eq!(profile.id, UuidSimple::offline("Steve"));
+        match client.next().await.unwrap().unwrap() {
+            LoginToClient::LoginSuccess { uuid, username } => {
+                assert_eq!(uuid, Uuid::from(profile.id));
+                assert_eq!(username, "Steve");
+            }
+            other => panic!("expected a login success, got {:?}", other),
+        }
+        assert!(!server.codec().is_encrypted());
+    }
+
+    #[tokio::test]
+    async fn online_joined() {
+        let (client, mut server) = connection();
+        let (login, requests) = online(200);
+        let (profile, (reply, answer)) = future::join(login.run(&mut server), online_client(client)).await;
+        let profile = profile.unwrap();
+        assert_eq!(profile.name, "Notch");
+        match answer {
+            LoginToClient::LoginSuccess { uuid, username } => {
+                assert_eq!(uuid, Uuid::from(profile.id));
+                assert_eq!(username, "Notch");
+            }
+            other => panic!("expected a login success, got {:?}", other),
+        }
+        assert!(server.codec().is_encrypted());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![format!(
+                "http://session/session/minecraft/hasJoined?username=Notch&serverId={}",
+                reply.server_hash
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn online_not_joined() {
+        let (client, mut server) = connection();
+        let (login, _) = online(204);
+        let (result, (_, answer)) = future::join(login.run(&mut server), online_client(client)).await;
+        match result {
+            Err(LoginError::NotJoined(name)) => assert_eq!(name, "Notch"),
+            other => panic!("expected not joined, got {:?}", other),
+        }
+        match answer {
+            LoginToClient::Disconnect { reason } => {
+                assert_eq!(reason, r#"{"text":"Failed to verify username!"}"#)
+            }
+            other => panic!("expected a disconnect, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,632 @@
+//! Server side of a connection: handshake, status, login and the play state
+//!
+//! [`Server::serve`] accepts connections and spawns a task for each of them. Status pings
+//! are answered from the status callback, players who log in are handed to the handler
+//! as a [`Player`], which sends keep alives and disconnects players who stop answering.
+//!
+//! ```ignore
+//! let server = Server::new(ServerLogin::offline())
+//!     .status(|_| ServerStatus::new("Hello", 0, 20));
+//! let listener = TcpListener::bind("0.0.0.0:25565").await?;
+//! server.serve(listener, |mut player| async move {
+//!     while let Some(Ok(packet)) = player.next().await {
+//!         println!("{}: {:?}", player.username(), packet);
+//!     }
+//! }).await?;
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{ready, Sink, SinkExt, Stream, StreamExt};
+use mcproto::auth::GameProfile;
+use mcproto::status::ServerStatus;
+use mcproto::types::uuid::Uuid;
+use mcproto::v1_7_10::{
+    Handshake, LoginToClient, NextState, PlayToClient, PlayToServer, StatusToClient,
+    StatusToServer, PROTOCOL_VERSION,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Instant, Interval};
+use tokio_util::codec::Framed;
+
+use crate::codec::{switch_codec, MCProtoCodec};
+use crate::login::{LoginError, ServerLogin, ServerLoginFramed};
+
+/// Connection in the play state, as seen by a server
+pub type ServerPlayFramed<S> = Framed<S, MCProtoCodec<PlayToClient, PlayToServer>>;
+type ServerStatusFramed<S> = Framed<S, MCProtoCodec<StatusToClient, StatusToServer>>;
+
+type StatusCallback = Box<dyn Fn(&ConnectionInfo) -> ServerStatus + Send + Sync>;
+
+#[derive(Debug)]
+pub enum ServerError {
+    Io(io::Error),
+    Codec(serde_mcproto::error::Error),
+    Login(LoginError),
+    /// Client speaks another protocol version, it was told so
+    UnsupportedVersion(i32),
+    /// Handshake asked for a state other than status or login
+    InvalidNextState,
+    /// Handshake and login took longer than the login timeout
+    LoginTimedOut,
+    /// Player did not answer a keep alive in time, it was disconnected
+    TimedOut,
+    /// Connection closed before the login finished
+    Closed,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::Io(e) => e.fmt(f),
+            ServerError::Codec(e) => e.fmt(f),
+            ServerError::Login(e) => e.fmt(f),
+            ServerError::UnsupportedVersion(version) => write!(f, "Unsupported protocol version {}", version),
+            ServerError::InvalidNextState => write!(f, "Handshake asked for an invalid state"),
+            ServerError::LoginTimedOut => write!(f, "Took too long to log in"),
+            ServerError::TimedOut => write!(f, "Timed out"),
+            ServerError::Closed => write!(f, "Connection closed"),
+        }
+    }
+}
+
+impl StdError for ServerError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ServerError::Io(e) => Some(e),
+            ServerError::Codec(e) => Some(e),
+            ServerError::Login(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Io(e)
+    }
+}
+
+impl From<serde_mcproto::error::Error> for ServerError {
+    fn from(e: serde_mcproto::error::Error) -> ServerError {
+        match e {
+            serde_mcproto::error::Error::IoError(e) => ServerError::Io(e),
+            e => ServerError::Codec(e),
+        }
+    }
+}
+
+impl From<LoginError> for ServerError {
+    fn from(e: LoginError) -> ServerError {
+        ServerError::Login(e)
+    }
+}
+
+/// What the client told in its handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub addr: SocketAddr,
+    pub protocol_version: i32,
+    /// Host name the client connected to, as typed by the player
+    pub server_address: String,
+    pub server_port: u16,
+}
+
+/// Listener options and the callbacks shared by every connection
+pub struct Server {
+    login: ServerLogin,
+    status: StatusCallback,
+    login_timeout: Duration,
+    keep_alive_interval: Duration,
+    keep_alive_timeout: Duration,
+}
+
+impl Server {
+    /// Vanilla timings: 30 seconds to log in, a keep alive every 15 seconds which must be
+    /// answered within 30 seconds
+    pub fn new(login: ServerLogin) -> Server {
+        Server {
+            login,
+            status: Box::new(|_| ServerStatus::new("A Minecraft Server", 0, 20)),
+            login_timeout: Duration::from_secs(30),
+            keep_alive_interval: Duration::from_secs(15),
+            keep_alive_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Answer status requests with what `status` returns
+    pub fn status<F>(mut self, status: F) -> Server
+    where
+        F: Fn(&ConnectionInfo) -> ServerStatus + Send + Sync + 'static,
+    {
+        self.status = Box::new(status);
+        self
+    }
+
+    /// Time a connection has from connecting to `LoginSuccess`, status pings included
+    pub fn login_timeout(mut self, timeout: Duration) -> Server {
+        self.login_timeout = timeout;
+        self
+    }
+
+    /// Send a keep alive every `interval`, disconnect players who did not answer one after
+    /// `timeout`
+    pub fn keep_alive(mut self, interval: Duration, timeout: Duration) -> Server {
+        self.keep_alive_interval = interval;
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Accept connections of `listener` forever, running `handler` for every player in its
+    /// own task. Connections which fail before logging in are dropped.
+    pub async fn serve<F, Fut>(self, listener: TcpListener, handler: F) -> io::Result<()>
+    where
+        F: Fn(Player<TcpStream>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let server = Arc::new(self);
+        let handler = Arc::new(handler);
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let server = server.clone();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                if let Ok(Some(player)) = server.accept(stream, addr).await {
+                    handler(player).await;
+                }
+            });
+        }
+    }
+
+    /// Drive a new connection through its handshake.
+    ///
+    /// Status pings are answered and give `None`, logins give the player.
+    pub async fn accept<S>(&self, stream: S, addr: SocketAddr) -> Result<Option<Player<S>>, ServerError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match time::timeout(self.login_timeout, self.handshake(stream, addr)).await {
+            Ok(result) => result,
+            Err(_) => Err(ServerError::LoginTimedOut),
+        }
+    }
+
+    async fn handshake<S>(&self, stream: S, addr: SocketAddr) -> Result<Option<Player<S>>, ServerError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut framed = Framed::new(stream, MCProtoCodec::<(), Handshake>::new());
+        let (info, next_state) = match framed.next().await {
+            Some(packet) => match packet? {
+                Handshake::Handshake {
+                    proto_version,
+                    server_address,
+                    server_port,
+                    next_state,
+                } => (
+                    ConnectionInfo {
+                        addr,
+                        protocol_version: proto_version.0,
+                        server_address: server_address.into_inner(),
+                        server_port,
+                    },
+                    next_state,
+                ),
+            },
+            None => return Err(ServerError::Closed),
+        };
+
+        match next_state {
+            NextState::Status => {
+                self.status_ping(switch_codec(framed), &info).await?;
+                Ok(None)
+            }
+            NextState::Login => self.login(switch_codec(framed), info).await.map(Some),
+            NextState::None => Err(ServerError::InvalidNextState),
+        }
+    }
+
+    /// Answer the status request and the ping, then close
+    async fn status_ping<S>(&self, mut framed: ServerStatusFramed<S>, info: &ConnectionInfo) -> Result<(), ServerError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        while let Some(packet) = framed.next().await {
+            match packet? {
+                StatusToServer::StatusRequest {} => {
+                    let response = (self.status)(info).to_json();
+                    framed.send(StatusToClient::StatusResponse { response }).await?;
+                }
+                StatusToServer::Ping { time } => {
+                    framed.send(StatusToClient::Pong { time }).await?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn login<S>(&self, mut framed: ServerLoginFramed<S>, info: ConnectionInfo) -> Result<Player<S>, ServerError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if info.protocol_version != PROTOCOL_VERSION.0 {
+            let reason = if info.protocol_version < PROTOCOL_VERSION.0 {
+                "Outdated client! Please use 1.7.10"
+            } else {
+                "Outdated server! I'm still on 1.7.10"
+            };
+            framed
+                .send(LoginToClient::Disconnect {
+                    reason: format!(r#"{{"text":"{}"}}"#, reason),
+                })
+                .await?;
+            return Err(ServerError::UnsupportedVersion(info.protocol_version));
+        }
+
+        let profile = match self.login.run(&mut framed).await {
+            Ok(profile) => profile,
+            Err(LoginError::Closed) => return Err(ServerError::Closed),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Player {
+            framed: switch_codec(framed),
+            profile,
+            info,
+            keep_alive: time::interval_at(
+                Instant::now() + self.keep_alive_interval,
+                self.keep_alive_interval,
+            ),
+            keep_alive_timeout: self.keep_alive_timeout,
+            pending: None,
+            next_id: 0,
+            queued: None,
+            closed: false,
+        })
+    }
+}
+
+/// Logged in player.
+///
+/// As a `Stream` it yields every `PlayToServer` packet but the answers to keep alives,
+/// which are sent while it is polled. A player who does not answer in time is disconnected
+/// and the stream yields [`ServerError::TimedOut`] and ends. As a `Sink` it sends
+/// `PlayToClient` packets.
+///
+/// Keep alives are only sent and their answers only read while the player is polled as a
+/// `Stream`. A handler which mostly sends has to keep reading it too, e.g. after `split`,
+/// or the client gets no keep alives and times out on its side.
+pub struct Player<S> {
+    framed: ServerPlayFramed<S>,
+    profile: GameProfile,
+    info: ConnectionInfo,
+    keep_alive: Interval,
+    keep_alive_timeout: Duration,
+    /// Id and send time of the keep alive waiting for an answer
+    pending: Option<(i32, Instant)>,
+    next_id: i32,
+    /// Packet waiting for room in the write buffer
+    queued: Option<PlayToClient>,
+    closed: bool,
+}
+
+impl<S> Player<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn profile(&self) -> &GameProfile {
+        &self.profile
+    }
+
+    pub fn username(&self) -> &str {
+        &self.profile.name
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        Uuid::from(self.profile.id)
+    }
+
+    pub fn info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    pub fn get_ref(&self) -> &ServerPlayFramed<S> {
+        &self.framed
+    }
+
+    pub fn into_inner(self) -> ServerPlayFramed<S> {
+        self.framed
+    }
+
+    /// Kick the player, `reason` is a JSON chat message
+    pub async fn disconnect(mut self, reason: String) -> Result<(), ServerError> {
+        self.send(PlayToClient::Disconnect { reason }).await?;
+        self.close().await
+    }
+
+    fn poll_queued(&mut self, cx: &mut Context) -> Poll<Result<(), ServerError>> {
+        if self.queued.is_some() {
+            ready!(Pin::new(&mut self.framed).poll_ready(cx))?;
+            if let Some(packet) = self.queued.take() {
+                Pin::new(&mut self.framed).start_send(packet)?;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Queue a keep alive when the interval ticks, `Err` once the last one is overdue
+    fn poll_keep_alive(&mut self, cx: &mut Context) -> Result<(), ServerError> {
+        while let Poll::Ready(now) = self.keep_alive.poll_tick(cx) {
+            match self.pending {
+                Some((_, sent)) if now.duration_since(sent) >= self.keep_alive_timeout => {
+                    return Err(ServerError::TimedOut);
+                }
+                Some(_) => {}
+                None if self.queued.is_none() => {
+                    let keep_alive_id = self.next_id;
+                    self.next_id = self.next_id.wrapping_add(1);
+                    self.pending = Some((keep_alive_id, now));
+                    self.queued = Some(PlayToClient::KeepAlive { keep_alive_id });
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S> Stream for Player<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<PlayToServer, ServerError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.closed {
+                return Poll::Ready(None);
+            }
+            if let Err(e) = ready!(this.poll_queued(cx)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+            if let Err(e) = this.poll_keep_alive(cx) {
+                this.closed = true;
+                // best effort, the player is probably gone
+                this.queued = Some(PlayToClient::Disconnect {
+                    reason: r#"{"text":"Timed out"}"#.to_owned(),
+                });
+                if let Poll::Ready(Ok(())) = this.poll_queued(cx) {
+                    let _ = Pin::new(&mut this.framed).poll_flush(cx);
+                }
+                return Poll::Ready(Some(Err(e)));
+            }
+            if this.queued.is_some() {
+                continue;
+            }
+            // keep alives go out even if the user never flushes
+            if let Poll::Ready(Err(e)) = Pin::new(&mut this.framed).poll_flush(cx) {
+                return Poll::Ready(Some(Err(e.into())));
+            }
+
+            let packet = match ready!(Pin::new(&mut this.framed).poll_next(cx)) {
+                Some(Ok(packet)) => packet,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => {
+                    this.closed = true;
+                    return Poll::Ready(None);
+                }
+            };
+            match packet {
+                PlayToServer::KeepAlive { keep_alive_id } => {
+                    if matches!(this.pending, Some((id, _)) if id == keep_alive_id) {
+                        this.pending = None;
+                    }
+                }
+                packet => return Poll::Ready(Some(Ok(packet))),
+            }
+        }
+    }
+}
+
+impl<S> Sink<PlayToClient> for Player<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = ServerError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_queued(cx))?;
+        Pin::new(&mut this.framed).poll_ready(cx).map_err(Into::into)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: PlayToClient) -> Result<(), Self::Error> {
+        Pin::new(&mut self.get_mut().framed)
+            .start_send(item)
+            .map_err(Into::into)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_queued(cx))?;
+        Pin::new(&mut this.framed).poll_flush(cx).map_err(Into::into)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().framed)
+            .poll_close(cx)
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future;
+    use mcproto::types::string::{BoundedString, Username};
+    use mcproto::types::var::VarInt;
+    use mcproto::v1_7_10::LoginToServer;
+    use tokio::io::{duplex, DuplexStream};
+
+    type ClientFramed<T, R> = Framed<DuplexStream, MCProtoCodec<T, R>>;
+
+    fn addr() -> SocketAddr {
+        "127.0.0.1:50000".parse().unwrap()
+    }
+
+    async fn handshake(stream: DuplexStream, proto_version: i32, next_state: NextState) -> ClientFramed<Handshake, ()> {
+        let mut framed = Framed::new(stream, MCProtoCodec::new());
+        framed
+            .send(Handshake::Handshake {
+                proto_version: VarInt(proto_version),
+                server_address: BoundedString::new("localhost").unwrap(),
+                server_port: 25565,
+                next_state,
+            })
+            .await
+            .unwrap();
+        framed
+    }
+
+    /// Player Steve, logged in offline, and the client side of its connection
+    async fn logged_in(server: &Server) -> (Player<DuplexStream>, ClientFramed<PlayToServer, PlayToClient>) {
+        let (client, server_end) = duplex(64 * 1024);
+        let client = async {
+            let framed = handshake(client, PROTOCOL_VERSION.0, NextState::Login).await;
+            let mut framed: ClientFramed<LoginToServer, LoginToClient> = switch_codec(framed);
+            let name = Username::new("Steve").unwrap();
+            framed.send(LoginToServer::LoginStart { name }).await.unwrap();
+            match framed.next().await.unwrap().unwrap() {
+                LoginToClient::LoginSuccess { username, .. } => assert_eq!(username, "Steve"),
+                other => panic!("expected a login success, got {:?}", other),
+            }
+            switch_codec(framed)
+        };
+        let (player, client) = future::join(server.accept(server_end, addr()), client).await;
+        (player.unwrap().expect("a login gives a player"), client)
+    }
+
+    fn keep_alive_id(packet: Option<Result<PlayToClient, serde_mcproto::error::Error>>) -> i32 {
+        match packet.unwrap().unwrap() {
+            PlayToClient::KeepAlive { keep_alive_id } => keep_alive_id,
+            other => panic!("expected a keep alive, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn status_ping() {
+        let server = Server::new(ServerLogin::offline())
+            .status(|info| ServerStatus::new(format!("Hello {}", info.server_address), 1, 20));
+        let (client, server_end) = duplex(64 * 1024);
+        let client = async {
+            let framed = handshake(client, PROTOCOL_VERSION.0, NextState::Status).await;
+            let mut framed: ClientFramed<StatusToServer, StatusToClient> = switch_codec(framed);
+            framed.send(StatusToServer::StatusRequest {}).await.unwrap();
+            match framed.next().await.unwrap().unwrap() {
+                StatusToClient::StatusResponse { response } => {
+                    let status = ServerStatus::from_json(&response).unwrap();
+                    assert_eq!(status, ServerStatus::new("Hello localhost", 1, 20));
+                }
+                other => panic!("expected a status response, got {:?}", other),
+            }
+            framed.send(StatusToServer::Ping { time: 1234 }).await.unwrap();
+            match framed.next().await.unwrap().unwrap() {
+                StatusToClient::Pong { time } => assert_eq!(time, 1234),
+                other => panic!("expected a pong, got {:?}", other),
+            }
+        };
+        let (accepted, ()) = future::join(server.accept(server_end, addr()), client).await;
+        assert!(accepted.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn other_protocol_versions_are_refused() {
+        let server = Server::new(ServerLogin::offline());
+        for &(version, reason) in [
+            (4, r#"{"text":"Outdated client! Please use 1.7.10"}"#),
+            (47, r#"{"text":"Outdated server! I'm still on 1.7.10"}"#),
+        ]
+        .iter()
+        {
+            let (client, server_end) = duplex(64 * 1024);
+            let client = async {
+                let framed = handshake(client, version, NextState::Login).await;
+                let mut framed: ClientFramed<LoginToServer, LoginToClient> = switch_codec(framed);
+                match framed.next().await.unwrap().unwrap() {
+                    LoginToClient::Disconnect { reason: sent } => assert_eq!(sent, reason),
+                    other => panic!("expected a disconnect, got {:?}", other),
+                }
+            };
+            let (accepted, ()) = future::join(server.accept(server_end, addr()), client).await;
+            let e = accepted.err().expect("the login is refused");
+            assert!(matches!(e, ServerError::UnsupportedVersion(v) if v == version), "{}", e);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn login_timeout() {
+        let server = Server::new(ServerLogin::offline()).login_timeout(Duration::from_secs(5));
+        let (client, server_end) = duplex(64 * 1024);
+        let start = Instant::now();
+        // the login never starts
+        let _client = handshake(client, PROTOCOL_VERSION.0, NextState::Login).await;
+        let e = server.accept(server_end, addr()).await.err().expect("the login times out");
+        assert!(matches!(e, ServerError::LoginTimedOut), "{}", e);
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_alives_are_sent_every_interval() {
+        let server = Server::new(ServerLogin::offline()).keep_alive(Duration::from_secs(10), Duration::from_secs(30));
+        let (mut player, mut client) = logged_in(&server).await;
+        let start = Instant::now();
+        let client = async move {
+            for id in 0..3 {
+                assert_eq!(keep_alive_id(client.next().await), id);
+                assert_eq!(start.elapsed(), Duration::from_secs(10) * (id as u32 + 1));
+                client.send(PlayToServer::KeepAlive { keep_alive_id: id }).await.unwrap();
+            }
+            let message = BoundedString::new("hello").unwrap();
+            client.send(PlayToServer::ChatMessage { message }).await.unwrap();
+            client
+        };
+        // answers are not yielded, the player is still there after three keep alives
+        let (packet, _client) = future::join(player.next(), client).await;
+        match packet.unwrap().unwrap() {
+            PlayToServer::ChatMessage { message } => assert_eq!(&*message, "hello"),
+            other => panic!("expected a chat message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_keep_alive_times_out() {
+        let server = Server::new(ServerLogin::offline()).keep_alive(Duration::from_secs(10), Duration::from_secs(30));
+        let (mut player, mut client) = logged_in(&server).await;
+        let start = Instant::now();
+        match player.next().await {
+            Some(Err(ServerError::TimedOut)) => {}
+            other => panic!("expected a time out, got {:?}", other),
+        }
+        // sent at 10 seconds, overdue at the tick of 40 seconds
+        assert_eq!(start.elapsed(), Duration::from_secs(40));
+        assert!(player.next().await.is_none());
+
+        // no other keep alive while the first one is unanswered
+        assert_eq!(keep_alive_id(client.next().await), 0);
+        match client.next().await.unwrap().unwrap() {
+            PlayToClient::Disconnect { reason } => assert_eq!(reason, r#"{"text":"Timed out"}"#),
+            other => panic!("expected a disconnect, got {:?}", other),
+        }
+    }
+}